@@ -0,0 +1,99 @@
+//! Arbitrary sequences of `SpacetimeDAG::add_event`: known and unknown parents, any coordinates
+//! (NaN and infinities included), error bounds and clock tolerance, nested batches and
+//! compare-and-sets against arbitrary versions.
+//!
+//! Whatever is accepted or rejected, the DAG must stay internally consistent: every head is a
+//! known event with no known children, rejected events leave no trace, re-adding is a no-op, and
+//! the incrementally folded state equals a fold from scratch.
+
+#![no_main]
+
+use std::collections::BTreeSet;
+
+use libfuzzer_sys::arbitrary::{Result, Unstructured};
+use libfuzzer_sys::fuzz_target;
+use lightcone::dag::SpacetimeDAG;
+use lightcone::event::{Event, EventHash, Operation, MAX_NESTING};
+use lightcone::spacetime::{SpacetimeCoord, Uncertainty};
+use lightcone::state::State;
+use uuid::Uuid;
+
+fn key(u: &mut Unstructured) -> Result<String> {
+    Ok(["a", "b", "c"][u.choose_index(3)?].to_string())
+}
+
+fn operation(u: &mut Unstructured, known: &[EventHash], depth: usize) -> Result<Operation> {
+    Ok(match u.int_in_range(0..=7u8)? {
+        0 => Operation::Put(key(u)?, u.arbitrary()?),
+        1 => Operation::Delete(key(u)?),
+        2 => Operation::Append(key(u)?, u.arbitrary()?),
+        3 => Operation::Increment(key(u)?, u.arbitrary()?),
+        4 => Operation::CompareAndSet {
+            key: key(u)?,
+            expected: if u.arbitrary()? { Some(known[u.choose_index(known.len())?]) } else { None },
+            value: u.arbitrary()?,
+        },
+        5 if depth < MAX_NESTING => {
+            let len = u.int_in_range(0..=3)?;
+            Operation::Batch((0..len).map(|_| operation(u, known, depth + 1)).collect::<Result<_>>()?)
+        }
+        // A forged root, which must always be refused.
+        6 => Operation::Genesis,
+        _ => Operation::Merge,
+    })
+}
+
+fn event(u: &mut Unstructured, known: &[EventHash]) -> Result<Event> {
+    let mut parents = BTreeSet::new();
+    for _ in 0..u.int_in_range(0..=3)? {
+        // Mostly real parents, sometimes ones this replica has never seen.
+        parents.insert(if u.ratio(7, 8)? { known[u.choose_index(known.len())?] } else { u.arbitrary()? });
+    }
+    // Full-width times and clock errors, including ones at the very end of the range.
+    let coords = SpacetimeCoord { t: u.arbitrary()?, x: u.arbitrary()?, y: u.arbitrary()?, z: u.arbitrary()? };
+    let time_ns = if u.arbitrary()? { u.arbitrary::<u32>()? as u128 } else { u.arbitrary()? };
+    let uncertainty = Uncertainty { time_ns, radius: u.arbitrary()? };
+    let payload = operation(u, known, 0)?;
+    Ok(Event::with_id(Uuid::from_u128(u.arbitrary()?), parents, coords, uncertainty, payload))
+}
+
+fn check(dag: &SpacetimeDAG) {
+    assert_eq!(dag.graph.node_count(), dag.index_map.len());
+    for head in &dag.heads {
+        assert!(dag.index_map.contains_key(head), "head is a known event");
+        assert!(dag.graph.node_weights().all(|e| !e.parents.contains(head)), "head has no children");
+    }
+    assert_eq!(dag.linearize().len(), dag.graph.node_count());
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let Ok(tolerance) = u.arbitrary::<u32>() else {
+        return;
+    };
+    let mut dag = SpacetimeDAG::with_causal_tolerance(tolerance as u128);
+    let mut known = dag.heads.clone();
+
+    while !u.is_empty() {
+        let Ok(ev) = event(&mut u, &known) else {
+            break;
+        };
+        let before = dag.graph.node_count();
+        match dag.add_event(ev.clone()) {
+            Ok(()) => {
+                assert!(!matches!(ev.payload, Operation::Genesis), "only the root carries a genesis payload");
+                assert!(ev.check_limits().is_ok(), "accepted events are within the decode limits");
+                assert!(dag.index_map.contains_key(&ev.hash));
+                dag.add_event(ev.clone()).expect("re-adding a known event is a no-op");
+                if !known.contains(&ev.hash) {
+                    known.push(ev.hash);
+                }
+            }
+            Err(_) => assert_eq!(dag.graph.node_count(), before, "rejected events are not inserted"),
+        }
+        check(&dag);
+    }
+
+    let folded = State::from_dag(&dag);
+    assert!(dag.state().scan("").eq(folded.scan("")), "incremental state matches a full fold");
+});
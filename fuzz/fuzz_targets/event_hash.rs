@@ -0,0 +1,20 @@
+//! Event hashes are never read off the wire; every decoded event must carry the hash of exactly
+//! the content it decoded to, and keep it through a round trip.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lightcone::event::Event;
+use lightcone::protocol;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(event) = protocol::decode::<Event>(data) else {
+        return;
+    };
+
+    let rebuilt = Event::with_id(event.id, event.parents.clone(), event.coords, event.uncertainty, event.payload.clone());
+    assert_eq!(event.hash, rebuilt.hash);
+
+    let again: Event = protocol::decode(&protocol::encode(&event)).expect("re-encoded event decodes");
+    assert_eq!(again.hash, event.hash);
+});
@@ -0,0 +1,23 @@
+//! Untrusted bytes as a QUIC stream or a BPv7 bundle delivers them.
+//!
+//! Decoding must fail cleanly rather than panic, recurse without bound or allocate past the
+//! decode limits, and anything that does decode must re-encode to a stable form.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lightcone::bundle::Bundle;
+use lightcone::protocol::{self, ProtocolMessage};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = protocol::decode::<ProtocolMessage>(data) {
+        let bytes = protocol::encode(&msg);
+        let again: ProtocolMessage = protocol::decode(&bytes).expect("re-encoded message decodes");
+        assert_eq!(protocol::encode(&again), bytes);
+    }
+
+    if let Ok(bundle) = Bundle::decode(data) {
+        let _ = bundle.message();
+        assert_eq!(Bundle::decode(&bundle.encode()).ok(), Some(bundle));
+    }
+});
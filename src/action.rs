@@ -1,13 +1,14 @@
-use crate::protocol::ProtocolMessage;
-
-#[derive(Debug, Clone)]
-pub enum Action {
-    Tick,
-    Render,
-    Resize(u16, u16),
-    Quit,
-    PanMap { dx: f64, dy: f64 },
-    ZoomMap { factor: f64 },
-    NewEvent(ProtocolMessage),
-    Broadcast(String),
-}
+use crate::custody::{BundleId, DeliveryStatus};
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    Tick,
+    Render,
+    Resize(u16, u16),
+    Quit,
+    PanMap { dx: f64, dy: f64 },
+    ZoomMap { factor: f64 },
+    Broadcast(String),
+    /// Custody status of an outbound bundle changed.
+    Delivery { id: BundleId, peer: String, status: DeliveryStatus },
+}
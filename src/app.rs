@@ -1,42 +1,49 @@
-use crate::action::Action;
-use crate::dag::SpacetimeDAG;
-
-pub struct App {
-    pub should_quit: bool,
-    pub dag: SpacetimeDAG,
-    pub viewport_offset: (f64, f64),
-    pub viewport_scale: f64,
-}
-
-impl App {
-    pub fn new() -> Self {
-        Self {
-            should_quit: false,
-            dag: SpacetimeDAG::new(),
-            viewport_offset: (0.0, 0.0),
-            viewport_scale: 1.0,
-        }
-    }
-
-    pub fn update(&mut self, action: Action) {
-        match action {
-            Action::Quit => self.should_quit = true,
-            Action::PanMap { dx, dy } => {
-                self.viewport_offset.0 += dx;
-                self.viewport_offset.1 += dy;
-                println!("Pan to ({:.2},{:.2})", self.viewport_offset.0, self.viewport_offset.1);
-            }
-            Action::ZoomMap { factor } => {
-                self.viewport_scale *= factor;
-                println!("Zoom to {:.2}", self.viewport_scale);
-            }
-            Action::NewEvent(msg) => {
-                println!("Received network event: {:?}", msg);
-            }
-            Action::Broadcast(text) => {
-                println!("Broadcast requested with message: {text}");
-            }
-            _ => {}
-        }
-    }
-}
+use crate::action::Action;
+
+pub struct App {
+    pub should_quit: bool,
+    /// Size of the local replica's concurrent frontier, refreshed before each frame.
+    pub heads: usize,
+    pub viewport_offset: (f64, f64),
+    pub viewport_scale: f64,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl App {
+    pub fn new() -> Self {
+        Self {
+            should_quit: false,
+            // A fresh replica holds only genesis.
+            heads: 1,
+            viewport_offset: (0.0, 0.0),
+            viewport_scale: 1.0,
+        }
+    }
+
+    pub fn update(&mut self, action: Action) {
+        match action {
+            Action::Quit => self.should_quit = true,
+            Action::PanMap { dx, dy } => {
+                self.viewport_offset.0 += dx;
+                self.viewport_offset.1 += dy;
+                println!("Pan to ({:.2},{:.2})", self.viewport_offset.0, self.viewport_offset.1);
+            }
+            Action::ZoomMap { factor } => {
+                self.viewport_scale *= factor;
+                println!("Zoom to {:.2}", self.viewport_scale);
+            }
+            Action::Broadcast(text) => {
+                println!("Broadcast requested with message: {text}");
+            }
+            Action::Delivery { id, peer, status } => {
+                println!("Bundle {id} to {peer}: {status:?}");
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,500 @@
+//! Bundle Protocol version 7 (RFC 9171) transport.
+//!
+//! Deep-space networks speak BPv7 rather than QUIC. This module wraps each [`ProtocolMessage`] in a
+//! bundle: a CBOR indefinite-length array holding a primary block (version, endpoint IDs, creation
+//! timestamp, lifetime) and a payload block, each protected by a CRC-32C. The message itself is
+//! CBOR-encoded into the payload block.
+//!
+//! Bundles travel over a minimal UDP convergence layer, one bundle per datagram, as used by ION's
+//! UDPCL. A receiver that takes custody answers with a bundle carrying
+//! [`ProtocolMessage::CustodyAck`] to the datagram's source; retransmission is left to the custody
+//! queue. Lifetime runs from each transmission attempt: the custody queue re-wraps the message on
+//! every retry.
+//!
+//! Only what lightcone needs is implemented: no fragmentation, extension blocks are skipped on
+//! receipt, and administrative records are not generated.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use ciborium::value::Value;
+use futures::future::BoxFuture;
+use thiserror::Error;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+use crate::network::CUSTODY_TIMEOUT;
+use crate::protocol::{ProtocolMessage, MAX_MESSAGE_BYTES};
+use crate::spacetime::now_ns;
+use crate::transport::{Inbound, Transport};
+
+/// The BPv7 version number carried in every primary block.
+pub const BP_VERSION: u64 = 7;
+/// Default bundle lifetime: one day.
+pub const DEFAULT_LIFETIME: Duration = Duration::from_secs(86_400);
+/// Unix time (ms) of the DTN epoch, 2000-01-01T00:00:00Z.
+const DTN_EPOCH_UNIX_MS: u128 = 946_684_800_000;
+
+const BLOCK_TYPE_PAYLOAD: u64 = 1;
+const CRC_TYPE_CRC32C: u64 = 2;
+/// Largest datagram the UDP convergence layer accepts.
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum BundleError {
+    #[error("invalid CBOR: {0}")]
+    Cbor(String),
+    #[error("malformed bundle: {0}")]
+    Malformed(&'static str),
+    #[error("unsupported bundle protocol version {0}")]
+    Version(u64),
+    #[error("CRC mismatch in {0} block")]
+    Crc(&'static str),
+}
+
+/// A bundle endpoint ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Eid {
+    /// `dtn:none`, the null endpoint.
+    None,
+    /// `dtn:<ssp>`, e.g. `dtn://earth/lightcone`.
+    Dtn(String),
+    /// `ipn:<node>.<service>`.
+    Ipn { node: u64, service: u64 },
+}
+
+impl Eid {
+    /// The endpoint of the lightcone node on local `port`.
+    pub fn for_port(port: u16) -> Self {
+        Self::Dtn(format!("//node-{port}/lightcone"))
+    }
+
+    /// The local port of a lightcone node endpoint, the inverse of [`Eid::for_port`].
+    pub fn port(&self) -> Option<u16> {
+        let Self::Dtn(ssp) = self else {
+            return None;
+        };
+        ssp.strip_prefix("//node-")?.strip_suffix("/lightcone")?.parse().ok()
+    }
+
+    fn to_cbor(&self) -> Value {
+        let (scheme, ssp) = match self {
+            Self::None => (1u64, Value::from(0u64)),
+            Self::Dtn(ssp) => (1, Value::Text(ssp.clone())),
+            Self::Ipn { node, service } => (2, Value::Array(vec![(*node).into(), (*service).into()])),
+        };
+        Value::Array(vec![scheme.into(), ssp])
+    }
+
+    fn from_cbor(value: &Value) -> Result<Self, BundleError> {
+        let bad = BundleError::Malformed("endpoint ID");
+        let [scheme, ssp] = as_array(value)? else {
+            return Err(bad);
+        };
+        match (as_u64(scheme)?, ssp) {
+            (1, Value::Integer(i)) if u64::try_from(*i).ok() == Some(0) => Ok(Self::None),
+            (1, Value::Text(ssp)) => Ok(Self::Dtn(ssp.clone())),
+            (2, Value::Array(parts)) => match &parts[..] {
+                [node, service] => Ok(Self::Ipn {
+                    node: as_u64(node)?,
+                    service: as_u64(service)?,
+                }),
+                _ => Err(bad),
+            },
+            _ => Err(bad),
+        }
+    }
+}
+
+impl fmt::Display for Eid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "dtn:none"),
+            Self::Dtn(ssp) => write!(f, "dtn:{ssp}"),
+            Self::Ipn { node, service } => write!(f, "ipn:{node}.{service}"),
+        }
+    }
+}
+
+impl FromStr for Eid {
+    type Err = BundleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "dtn:none" {
+            return Ok(Self::None);
+        }
+        if let Some(ssp) = s.strip_prefix("dtn:") {
+            return Ok(Self::Dtn(ssp.to_string()));
+        }
+        let ipn = s.strip_prefix("ipn:").and_then(|rest| {
+            let (node, service) = rest.split_once('.')?;
+            Some(Self::Ipn {
+                node: node.parse().ok()?,
+                service: service.parse().ok()?,
+            })
+        });
+        ipn.ok_or(BundleError::Malformed("endpoint ID"))
+    }
+}
+
+/// A BPv7 bundle with a single payload block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    pub source: Eid,
+    pub destination: Eid,
+    pub report_to: Eid,
+    /// Creation time in ms since the DTN epoch.
+    pub creation_ms: u64,
+    /// Distinguishes bundles created in the same millisecond by the same source.
+    pub sequence: u64,
+    /// Lifetime in ms after creation.
+    pub lifetime_ms: u64,
+    pub payload: Vec<u8>,
+}
+
+impl Bundle {
+    /// Wraps `message` in a bundle created now.
+    pub fn new(source: Eid, destination: Eid, message: &ProtocolMessage, lifetime: Duration, sequence: u64) -> Self {
+        let mut payload = Vec::new();
+        ciborium::into_writer(message, &mut payload).expect("protocol messages encode to CBOR");
+        Self {
+            report_to: source.clone(),
+            source,
+            destination,
+            creation_ms: dtn_time_ms(now_ns()),
+            sequence,
+            lifetime_ms: lifetime.as_millis() as u64,
+            payload,
+        }
+    }
+
+    /// Decodes the payload as a protocol message, within the same limits as
+    /// [`protocol::decode`](crate::protocol::decode).
+    pub fn message(&self) -> Result<ProtocolMessage, BundleError> {
+        if self.payload.len() > MAX_MESSAGE_BYTES {
+            return Err(BundleError::Malformed("payload larger than a protocol message"));
+        }
+        ciborium::from_reader(&self.payload[..]).map_err(|e| BundleError::Cbor(e.to_string()))
+    }
+
+    /// Whether the bundle's lifetime has run out at DTN time `now_ms`.
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        now_ms > self.creation_ms.saturating_add(self.lifetime_ms)
+    }
+
+    /// Encodes the bundle as an indefinite-length CBOR array of blocks.
+    pub fn encode(&self) -> Vec<u8> {
+        let primary = vec![
+            BP_VERSION.into(),
+            0u64.into(), // bundle processing control flags
+            CRC_TYPE_CRC32C.into(),
+            self.destination.to_cbor(),
+            self.source.to_cbor(),
+            self.report_to.to_cbor(),
+            Value::Array(vec![self.creation_ms.into(), self.sequence.into()]),
+            self.lifetime_ms.into(),
+        ];
+        let payload = vec![
+            BLOCK_TYPE_PAYLOAD.into(),
+            1u64.into(), // block number
+            0u64.into(), // block processing control flags
+            CRC_TYPE_CRC32C.into(),
+            Value::Bytes(self.payload.clone()),
+        ];
+
+        let mut out = vec![0x9f];
+        out.extend(encode_with_crc(primary));
+        out.extend(encode_with_crc(payload));
+        out.push(0xff);
+        out
+    }
+
+    /// Decodes a bundle, verifying block CRCs.
+    pub fn decode(bytes: &[u8]) -> Result<Self, BundleError> {
+        let Some((&0x9f, mut rest)) = bytes.split_first() else {
+            return Err(BundleError::Malformed("expected an indefinite-length array"));
+        };
+
+        let primary = read_value(&mut rest)?;
+        let fields = check_crc(&primary, "primary")?;
+        let [version, _flags, _crc_type, destination, source, report_to, creation, lifetime, ..] = fields else {
+            return Err(BundleError::Malformed("primary block too short"));
+        };
+        let version = as_u64(version)?;
+        if version != BP_VERSION {
+            return Err(BundleError::Version(version));
+        }
+        let [creation_ms, sequence] = as_array(creation)? else {
+            return Err(BundleError::Malformed("creation timestamp"));
+        };
+
+        let mut payload = None;
+        loop {
+            match rest.first() {
+                Some(0xff) => break,
+                None => return Err(BundleError::Malformed("missing break")),
+                Some(_) => {}
+            }
+            let block = read_value(&mut rest)?;
+            let fields = check_crc(&block, "canonical")?;
+            let [block_type, _number, _flags, _crc_type, data, ..] = fields else {
+                return Err(BundleError::Malformed("canonical block too short"));
+            };
+            if as_u64(block_type)? == BLOCK_TYPE_PAYLOAD {
+                let Value::Bytes(data) = data else {
+                    return Err(BundleError::Malformed("payload is not a byte string"));
+                };
+                payload = Some(data.clone());
+            }
+        }
+
+        Ok(Self {
+            source: Eid::from_cbor(source)?,
+            destination: Eid::from_cbor(destination)?,
+            report_to: Eid::from_cbor(report_to)?,
+            creation_ms: as_u64(creation_ms)?,
+            sequence: as_u64(sequence)?,
+            lifetime_ms: as_u64(lifetime)?,
+            payload: payload.ok_or(BundleError::Malformed("no payload block"))?,
+        })
+    }
+}
+
+/// Converts coordinate time (ns since the Unix epoch) to DTN time (ms since 2000-01-01).
+pub fn dtn_time_ms(unix_ns: u128) -> u64 {
+    (unix_ns / 1_000_000).saturating_sub(DTN_EPOCH_UNIX_MS) as u64
+}
+
+/// Encodes a block, appending its CRC-32C computed over the block with a zeroed CRC field.
+fn encode_with_crc(mut fields: Vec<Value>) -> Vec<u8> {
+    fields.push(Value::Bytes(vec![0; 4]));
+    let zeroed = encode_value(&Value::Array(fields.clone()));
+    *fields.last_mut().expect("CRC field was just pushed") = Value::Bytes(crc32c(&zeroed).to_be_bytes().to_vec());
+    encode_value(&Value::Array(fields))
+}
+
+/// Returns the block's fields after checking its CRC, if it carries one.
+fn check_crc<'a>(block: &'a Value, name: &'static str) -> Result<&'a [Value], BundleError> {
+    let fields = as_array(block)?;
+    // The CRC type is the third field of the primary block and the fourth of canonical blocks.
+    let crc_type = if name == "primary" { fields.get(2) } else { fields.get(3) };
+    if crc_type.map(as_u64).transpose()? != Some(CRC_TYPE_CRC32C) {
+        return Ok(fields);
+    }
+    let Some((Value::Bytes(crc), rest)) = fields.split_last() else {
+        return Err(BundleError::Malformed("missing CRC"));
+    };
+    let mut zeroed = rest.to_vec();
+    zeroed.push(Value::Bytes(vec![0; 4]));
+    if crc[..] != crc32c(&encode_value(&Value::Array(zeroed))).to_be_bytes() {
+        return Err(BundleError::Crc(name));
+    }
+    Ok(rest)
+}
+
+fn encode_value(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    ciborium::into_writer(value, &mut out).expect("CBOR values encode");
+    out
+}
+
+fn read_value(input: &mut &[u8]) -> Result<Value, BundleError> {
+    ciborium::from_reader(input).map_err(|e| BundleError::Cbor(e.to_string()))
+}
+
+fn as_array(value: &Value) -> Result<&[Value], BundleError> {
+    match value {
+        Value::Array(items) => Ok(items),
+        _ => Err(BundleError::Malformed("expected an array")),
+    }
+}
+
+fn as_u64(value: &Value) -> Result<u64, BundleError> {
+    match value {
+        Value::Integer(i) => u64::try_from(*i).map_err(|_| BundleError::Malformed("expected an unsigned integer")),
+        _ => Err(BundleError::Malformed("expected an unsigned integer")),
+    }
+}
+
+/// CRC-32C (Castagnoli), as required for BPv7 CRC type 2.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// BPv7 over a UDP convergence layer, one bundle per datagram.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    local: Eid,
+    /// Sequence number of the next bundle this endpoint creates.
+    sequence: AtomicU64,
+    pub lifetime: Duration,
+}
+
+impl UdpTransport {
+    /// Binds the node's bundle endpoint on local `port`.
+    pub async fn bind(port: u16) -> Result<Self> {
+        let socket = UdpSocket::bind(("127.0.0.1", port)).await?;
+        let port = socket.local_addr()?.port();
+        Ok(Self {
+            socket,
+            local: Eid::for_port(port),
+            sequence: AtomicU64::new(0),
+            lifetime: DEFAULT_LIFETIME,
+        })
+    }
+
+    /// The port this endpoint listens on.
+    pub fn local_port(&self) -> Result<u16> {
+        Ok(self.socket.local_addr()?.port())
+    }
+
+    async fn send_bundle(&self, peer: u16, msg: ProtocolMessage) -> Result<()> {
+        // A fresh socket per bundle, so the custody acknowledgement comes back to this call.
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let bundle = Bundle::new(self.local.clone(), Eid::for_port(peer), &msg, self.lifetime, sequence);
+        socket.send_to(&bundle.encode(), ("127.0.0.1", peer)).await?;
+
+        let mut buf = vec![0; MAX_DATAGRAM];
+        let len = timeout(CUSTODY_TIMEOUT, socket.recv(&mut buf))
+            .await
+            .context("custody acknowledgement timed out")??;
+        let reply = Bundle::decode(&buf[..len])?;
+        match reply.message()? {
+            ProtocolMessage::CustodyAck if reply.sequence == sequence => Ok(()),
+            ProtocolMessage::CustodyAck => bail!("custody acknowledged for bundle {}, not {sequence}", reply.sequence),
+            other => bail!("unexpected reply: {other:?}"),
+        }
+    }
+
+    async fn accept(&self, data: &[u8], from: SocketAddr, inbound: &mpsc::Sender<Inbound<u16>>) -> Result<()> {
+        let bundle = Bundle::decode(data)?;
+        if bundle.destination != self.local {
+            bail!("bundle for {} delivered to {}", bundle.destination, self.local);
+        }
+        if bundle.is_expired(dtn_time_ms(now_ns())) {
+            bail!("bundle from {} expired", bundle.source);
+        }
+
+        let peer = bundle.source.port().context("source is not a lightcone node")?;
+        let (msg, decision) = Inbound::new(peer, bundle.message()?);
+        inbound.send(msg).await?;
+        if decision.await.unwrap_or(false) {
+            let ack = Bundle::new(self.local.clone(), bundle.source, &ProtocolMessage::CustodyAck, self.lifetime, bundle.sequence);
+            self.socket.send_to(&ack.encode(), from).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Transport for UdpTransport {
+    type Peer = u16;
+
+    fn send(&self, peer: u16, msg: ProtocolMessage) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.send_bundle(peer, msg))
+    }
+
+    fn serve(&self, inbound: mpsc::Sender<Inbound<u16>>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let mut buf = vec![0; MAX_DATAGRAM];
+            loop {
+                let (len, from) = self.socket.recv_from(&mut buf).await?;
+                if let Err(e) = self.accept(&buf[..len], from, &inbound).await {
+                    eprintln!("[bundle] dropped datagram from {from}: {e:#}");
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundles_round_trip_and_detect_corruption() {
+        // RFC 3720 check value.
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+
+        let msg = ProtocolMessage::Hello { coords: (1.0, 2.0, 3.0), heads: vec![[7; 32]] };
+        let bundle = Bundle::new(Eid::for_port(5000), "ipn:2.1".parse().unwrap(), &msg, DEFAULT_LIFETIME, 7);
+        let bytes = bundle.encode();
+        assert_eq!((bytes[0], bytes[bytes.len() - 1]), (0x9f, 0xff));
+
+        let decoded = Bundle::decode(&bytes).unwrap();
+        assert_eq!(decoded, bundle);
+        assert_eq!(decoded.destination.to_string(), "ipn:2.1");
+        assert!(matches!(decoded.message().unwrap(), ProtocolMessage::Hello { coords: (1.0, 2.0, 3.0), heads } if heads == [[7; 32]]));
+        assert!(!decoded.is_expired(decoded.creation_ms + 1_000));
+        assert!(decoded.is_expired(decoded.creation_ms + DEFAULT_LIFETIME.as_millis() as u64 + 1));
+
+        // Events survive the trip with their content hash recomputed.
+        let event = crate::event::Event::genesis();
+        let gossip = Bundle::new(Eid::for_port(5000), Eid::for_port(5001), &ProtocolMessage::Gossip(vec![event.clone()]), DEFAULT_LIFETIME, 8);
+        match Bundle::decode(&gossip.encode()).unwrap().message().unwrap() {
+            ProtocolMessage::Gossip(decoded) => assert_eq!(decoded[0].hash, event.hash),
+            other => panic!("expected gossip, got {other:?}"),
+        }
+
+        let mut corrupt = bytes.clone();
+        let last_payload_byte = corrupt.len() - 7;
+        corrupt[last_payload_byte] ^= 0x01;
+        assert!(matches!(Bundle::decode(&corrupt), Err(BundleError::Crc("canonical"))));
+    }
+
+    #[tokio::test]
+    async fn udp_transport_waits_for_custody() {
+        let receiver = UdpTransport::bind(0).await.unwrap();
+        let sender = UdpTransport::bind(0).await.unwrap();
+        let port = receiver.local_port().unwrap();
+        let sender_port = sender.local_port().unwrap();
+
+        let (tx, mut rx) = mpsc::channel(1);
+        tokio::spawn(async move { receiver.serve(tx).await });
+        tokio::spawn(async move {
+            let Inbound { from, message, custody } = rx.recv().await.unwrap();
+            assert_eq!(from, sender_port);
+            assert!(matches!(message, ProtocolMessage::Digest { .. }));
+            custody.send(true).unwrap();
+        });
+
+        sender.send(port, ProtocolMessage::Digest { heads: vec![] }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn bundles_are_numbered_per_transport() {
+        let sender = UdpTransport::bind(0).await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = peer.local_addr().unwrap().port();
+
+        let acker = tokio::spawn(async move {
+            let mut sequences = Vec::new();
+            let mut buf = vec![0; MAX_DATAGRAM];
+            for _ in 0..3 {
+                let (len, from) = peer.recv_from(&mut buf).await.unwrap();
+                let bundle = Bundle::decode(&buf[..len]).unwrap();
+                let ack = Bundle::new(Eid::for_port(port), bundle.source, &ProtocolMessage::CustodyAck, DEFAULT_LIFETIME, bundle.sequence);
+                peer.send_to(&ack.encode(), from).await.unwrap();
+                sequences.push(bundle.sequence);
+            }
+            sequences
+        });
+
+        for _ in 0..3 {
+            sender.send(port, ProtocolMessage::Digest { heads: vec![] }).await.unwrap();
+        }
+        assert_eq!(acker.await.unwrap(), [0, 1, 2]);
+    }
+}
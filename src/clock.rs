@@ -0,0 +1,124 @@
+//! Sources of coordinate time.
+//!
+//! Anything that gates on time (the [`PhysicsLayer`](crate::physics::PhysicsLayer), the network
+//! tick loop) reads it from an injected [`Clock`] instead of the system clock, so it can run in
+//! real time, be stepped by hand in a test, or follow a [`Simulator`](crate::simulator::Simulator)
+//! during replay.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::sync::watch;
+
+use crate::spacetime::now_ns;
+
+pub trait Clock: Send + Sync {
+    /// Current coordinate time (ns).
+    fn now(&self) -> u128;
+
+    /// Completes once [`Self::now`] has reached `deadline`.
+    fn sleep_until(&self, deadline: u128) -> BoxFuture<'_, ()>;
+}
+
+/// Wall-clock time in the demo's shared frame: nanoseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u128 {
+        now_ns()
+    }
+
+    fn sleep_until(&self, deadline: u128) -> BoxFuture<'_, ()> {
+        let wait = deadline.saturating_sub(self.now()).min(u64::MAX as u128) as u64;
+        Box::pin(tokio::time::sleep(Duration::from_nanos(wait)))
+    }
+}
+
+/// A clock that only moves when told to, for tests.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: watch::Sender<u128>,
+}
+
+impl ManualClock {
+    pub fn new(start: u128) -> Arc<Self> {
+        Arc::new(Self { now: watch::Sender::new(start) })
+    }
+
+    /// Jumps to `t`, waking every sleeper whose deadline has passed. Time may go backwards.
+    pub fn set(&self, t: u128) {
+        self.now.send_replace(t);
+    }
+
+    /// Moves the clock forward by `ns`.
+    pub fn advance(&self, ns: u128) {
+        self.now.send_modify(|t| *t += ns);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u128 {
+        *self.now.borrow()
+    }
+
+    fn sleep_until(&self, deadline: u128) -> BoxFuture<'_, ()> {
+        let mut rx = self.now.subscribe();
+        Box::pin(async move {
+            // The sender lives as long as `self`, so this cannot fail while we are borrowed.
+            let _ = rx.wait_for(|t| *t >= deadline).await;
+        })
+    }
+}
+
+/// The virtual time of a [`Simulator`](crate::simulator::Simulator), which advances it as it
+/// processes scheduled actions. Monotonic; components under simulation only read it.
+#[derive(Debug)]
+pub struct SimulatedClock(ManualClock);
+
+impl SimulatedClock {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self(ManualClock { now: watch::Sender::new(0) }))
+    }
+
+    pub(crate) fn advance_to(&self, t: u128) {
+        self.0.now.send_if_modified(|now| {
+            let later = t > *now;
+            *now = (*now).max(t);
+            later
+        });
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> u128 {
+        self.0.now()
+    }
+
+    fn sleep_until(&self, deadline: u128) -> BoxFuture<'_, ()> {
+        self.0.sleep_until(deadline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn manual_sleepers_wake_only_once_their_deadline_is_reached() {
+        let clock = ManualClock::new(100);
+        let sleeper = {
+            let clock = clock.clone();
+            tokio::spawn(async move { clock.sleep_until(150).await })
+        };
+
+        clock.advance(49);
+        tokio::task::yield_now().await;
+        assert!(!sleeper.is_finished());
+
+        clock.advance(1);
+        sleeper.await.unwrap();
+        assert_eq!(clock.now(), 150);
+    }
+}
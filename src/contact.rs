@@ -0,0 +1,390 @@
+//! Scheduled contact plans and Contact Graph Routing (CGR).
+//!
+//! Deep-space links are not "up" or "down"; they are planned. A ground station sees a spacecraft
+//! for a few hours a day, a relay orbiter passes over a lander twice a sol. Operators publish these
+//! windows ahead of time as a *contact plan*: from node, to node, start, end, data rate and one-way
+//! light time (OWLT). Within a contact the sender may transmit; outside it, nothing leaves.
+//!
+//! Plans are loaded from CSV, one contact per row: `from,to,start,end,rate,owlt`, with times in
+//! seconds from the plan epoch, `rate` in bytes per second and `owlt` in seconds. Lines starting
+//! with `#` and an optional `from,...` header are ignored.
+//!
+//! Routing over a plan uses CGR's earliest-arrival search: contacts are the vertices, and a bundle
+//! can move from one contact to the next if the second starts at the node the first ends at and
+//! is still open once the bundle has arrived and been transmitted. Contact volume is not reserved
+//! between routes; each route is computed against the full plan.
+//!
+//! A [`ScheduledTransport`] enforces a plan on a real transport: nothing goes out towards a peer
+//! except inside a contact with it.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+use crate::clock::Clock;
+use crate::protocol::ProtocolMessage;
+use crate::routing::Route;
+use crate::transport::{Inbound, Transport};
+
+#[derive(Debug, Error)]
+pub enum ContactPlanError {
+    #[error("failed to read contact plan: {0}")]
+    Io(#[from] std::io::Error),
+    /// A row could not be parsed.
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+    /// A contact names a node the caller could not resolve.
+    #[error("unknown node: {0}")]
+    UnknownNode(String),
+    /// Shifting the plan to its epoch would run a contact past the end of the time axis.
+    #[error("contact plan overflows the time axis at epoch {0} ns")]
+    EpochOverflow(u128),
+}
+
+/// A planned transmission window from one node to another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contact<Id> {
+    pub from: Id,
+    pub to: Id,
+    /// Window start, coordinate time in ns.
+    pub start: u128,
+    /// Window end, coordinate time in ns. Transmission must complete by then.
+    pub end: u128,
+    /// Data rate in bytes per second.
+    pub rate: f64,
+    /// One-way light time in ns.
+    pub owlt: u128,
+}
+
+impl<Id> Contact<Id> {
+    /// Time to clock `bytes` onto the link, in ns.
+    fn transmission_ns(&self, bytes: usize) -> u128 {
+        (bytes as f64 / self.rate * 1e9).ceil() as u128
+    }
+
+    /// When a transmission of `bytes` that is ready at `ready` would reach the far end, if it fits
+    /// in this window (and on the time axis).
+    pub fn arrival(&self, ready: u128, bytes: usize) -> Option<u128> {
+        let departure = ready.max(self.start);
+        let done = departure.checked_add(self.transmission_ns(bytes))?;
+        (done <= self.end).then_some(())?;
+        done.checked_add(self.owlt)
+    }
+}
+
+/// A set of planned contacts.
+#[derive(Debug, Clone)]
+pub struct ContactPlan<Id> {
+    pub contacts: Vec<Contact<Id>>,
+}
+
+impl<Id> Default for ContactPlan<Id> {
+    fn default() -> Self {
+        Self { contacts: Vec::new() }
+    }
+}
+
+impl ContactPlan<String> {
+    /// Parses contact rows from `text`.
+    pub fn parse(text: &str) -> Result<Self, ContactPlanError> {
+        let mut contacts = Vec::new();
+
+        for (idx, raw) in text.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("from,") {
+                continue;
+            }
+            let err = |message: &str| ContactPlanError::Parse {
+                line: idx + 1,
+                message: message.to_string(),
+            };
+
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [from, to, start, end, rate, owlt] = fields[..] else {
+                return Err(err("expected 6 columns: from,to,start,end,rate,owlt"));
+            };
+            let num = |field: &str, name: &str| {
+                field
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|v| v.is_finite() && *v >= 0.0)
+                    .ok_or_else(|| err(&format!("invalid {name}: {field:?}")))
+            };
+            let ns = |secs: f64| (secs * 1e9).round() as u128;
+
+            let (start, end) = (ns(num(start, "start")?), ns(num(end, "end")?));
+            if end < start {
+                return Err(err("end must not precede start"));
+            }
+            let rate = num(rate, "rate")?;
+            if rate == 0.0 {
+                return Err(err("rate must be positive"));
+            }
+            contacts.push(Contact {
+                from: from.to_string(),
+                to: to.to_string(),
+                start,
+                end,
+                rate,
+                owlt: ns(num(owlt, "owlt")?),
+            });
+        }
+        Ok(Self { contacts })
+    }
+
+    /// Reads and parses a contact plan file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ContactPlanError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Translates node names into another identifier space (e.g. cluster UUIDs).
+    pub fn resolve<Id>(&self, lookup: impl Fn(&str) -> Option<Id>) -> Result<ContactPlan<Id>, ContactPlanError> {
+        let node = |name: &String| lookup(name).ok_or_else(|| ContactPlanError::UnknownNode(name.clone()));
+        let contacts = self
+            .contacts
+            .iter()
+            .map(|c| {
+                Ok(Contact {
+                    from: node(&c.from)?,
+                    to: node(&c.to)?,
+                    start: c.start,
+                    end: c.end,
+                    rate: c.rate,
+                    owlt: c.owlt,
+                })
+            })
+            .collect::<Result<_, ContactPlanError>>()?;
+        Ok(ContactPlan { contacts })
+    }
+}
+
+impl<Id: Copy + Eq + Hash + Ord> ContactPlan<Id> {
+    /// Shifts every contact so the plan epoch falls at coordinate time `epoch_ns`.
+    pub fn with_epoch(mut self, epoch_ns: u128) -> Result<Self, ContactPlanError> {
+        for contact in &mut self.contacts {
+            let shift = |t: u128| t.checked_add(epoch_ns).ok_or(ContactPlanError::EpochOverflow(epoch_ns));
+            contact.start = shift(contact.start)?;
+            contact.end = shift(contact.end)?;
+        }
+        Ok(self)
+    }
+
+    /// The earliest direct transmission from `from` to `to` of `bytes` ready at `ready`:
+    /// `(departure, arrival)`.
+    pub fn next_transmission(&self, from: Id, to: Id, ready: u128, bytes: usize) -> Option<(u128, u128)> {
+        self.contacts
+            .iter()
+            .filter(|c| c.from == from && c.to == to)
+            .filter_map(|c| Some((ready.max(c.start), c.arrival(ready, bytes)?)))
+            .min_by_key(|(_, arrival)| *arrival)
+    }
+
+    /// Contact Graph Routing: the earliest-arrival route for `bytes` that are ready at `from` at
+    /// `ready`, possibly stored at intermediate nodes until their next contact opens.
+    ///
+    /// A node is zero hops from itself: the route from `from` to `from` is just `from` at `ready`.
+    pub fn route(&self, from: Id, to: Id, ready: u128, bytes: usize) -> Option<Route<Id>> {
+        if from == to {
+            return Some(Route { hops: vec![(from, ready)] });
+        }
+        // Earliest arrival at the far end of each contact, and the contact that fed it.
+        let mut best: HashMap<usize, u128> = HashMap::new();
+        let mut prev: HashMap<usize, usize> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+
+        for (i, contact) in self.contacts.iter().enumerate() {
+            if contact.from != from {
+                continue;
+            }
+            if let Some(arrival) = contact.arrival(ready, bytes) {
+                best.insert(i, arrival);
+                queue.push(Reverse((arrival, i)));
+            }
+        }
+
+        let mut reached = None;
+        while let Some(Reverse((t, i))) = queue.pop() {
+            if best.get(&i).is_some_and(|b| *b < t) {
+                continue;
+            }
+            let node = self.contacts[i].to;
+            if node == to {
+                reached = Some(i);
+                break;
+            }
+            for (j, next) in self.contacts.iter().enumerate() {
+                if next.from != node {
+                    continue;
+                }
+                let Some(arrival) = next.arrival(t, bytes) else {
+                    continue;
+                };
+                if best.get(&j).is_none_or(|b| arrival < *b) {
+                    best.insert(j, arrival);
+                    prev.insert(j, i);
+                    queue.push(Reverse((arrival, j)));
+                }
+            }
+        }
+
+        let mut cursor = reached?;
+        let mut hops = vec![(to, best[&cursor])];
+        while let Some(&p) = prev.get(&cursor) {
+            hops.push((self.contacts[p].to, best[&p]));
+            cursor = p;
+        }
+        hops.push((from, ready));
+        hops.reverse();
+        Some(Route { hops })
+    }
+}
+
+/// Wraps a [`Transport`] so it only transmits inside the contacts of a [`ContactPlan`].
+///
+/// A send waits for the next contact towards the peer that can carry the whole message, and fails
+/// if the plan has none, so the caller's custody queue keeps the message for a later retry.
+pub struct ScheduledTransport<T: Transport> {
+    inner: T,
+    local: T::Peer,
+    plan: ContactPlan<T::Peer>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T: Transport> ScheduledTransport<T> {
+    /// Wraps `inner`, whose own address is `local`, with `plan` timed by `clock`.
+    pub fn new(inner: T, local: T::Peer, plan: ContactPlan<T::Peer>, clock: Arc<dyn Clock>) -> Self {
+        Self { inner, local, plan, clock }
+    }
+}
+
+impl<T: Transport> Transport for ScheduledTransport<T>
+where
+    T::Peer: Ord,
+{
+    type Peer = T::Peer;
+
+    fn send(&self, peer: T::Peer, msg: ProtocolMessage) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let (departure, _) = self
+                .plan
+                .next_transmission(self.local, peer, self.clock.now(), msg.wire_size())
+                .ok_or_else(|| anyhow!("no contact to {peer} in the plan"))?;
+            self.clock.sleep_until(departure).await;
+            self.inner.send(peer, msg).await
+        })
+    }
+
+    fn serve(&self, inbound: mpsc::Sender<Inbound<T::Peer>>) -> BoxFuture<'_, Result<()>> {
+        self.inner.serve(inbound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const S: u128 = 1_000_000_000;
+
+    #[test]
+    fn routes_wait_for_contacts_and_store_at_relays() {
+        let plan = ContactPlan::parse(
+            "from,to,start,end,rate,owlt\n\
+             # Earth uplinks to the relay orbiter, which passes over the lander later.\n\
+             earth,orbiter,100,200,1000,600\n\
+             orbiter,lander,1000,1002,1000,0.01\n\
+             earth,lander,5000,6000,10,1200\n",
+        )
+        .unwrap();
+        let plan = plan.resolve(|name| ["earth", "orbiter", "lander"].into_iter().find(|n| *n == name)).unwrap();
+
+        // Nothing leaves before the first window opens: 100 s + 1 s on the wire + 600 s OWLT.
+        assert_eq!(plan.next_transmission("earth", "orbiter", 0, 1000), Some((100 * S, 701 * S)));
+
+        let route = plan.route("earth", "lander", 0, 1000).expect("route");
+        assert_eq!(route.relays(), ["orbiter"]);
+        assert_eq!(route.hops[1], ("orbiter", 701 * S));
+        assert_eq!(route.arrival(), 1001 * S + 10_000_000);
+
+        // Too large for the relay passes; only the slow direct link has the volume.
+        let big = plan.route("earth", "lander", 0, 200_000);
+        assert!(big.is_none());
+        let medium = plan.route("earth", "lander", 0, 5_000).expect("direct route");
+        assert!(medium.relays().is_empty());
+        assert_eq!(medium.arrival(), (5000 + 500 + 1200) * S);
+
+        let here = plan.route("orbiter", "orbiter", 42, 1_000_000).expect("zero-hop route");
+        assert_eq!(here.hops, [("orbiter", 42)]);
+        assert_eq!(here.arrival(), 42);
+    }
+
+    /// Records what it is asked to send.
+    #[derive(Default)]
+    struct Recorder(std::sync::Mutex<Vec<u16>>);
+
+    impl Transport for Recorder {
+        type Peer = u16;
+
+        fn send(&self, peer: u16, _msg: ProtocolMessage) -> BoxFuture<'_, Result<()>> {
+            self.0.lock().unwrap().push(peer);
+            Box::pin(async { Ok(()) })
+        }
+
+        fn serve(&self, _inbound: mpsc::Sender<Inbound<u16>>) -> BoxFuture<'_, Result<()>> {
+            Box::pin(futures::future::pending())
+        }
+    }
+
+    #[tokio::test]
+    async fn scheduled_transport_sends_only_inside_contacts() {
+        let plan = ContactPlan::parse("1,2,10,20,1000000,0\n").unwrap();
+        let plan = plan.resolve(|name| name.parse::<u16>().ok()).unwrap();
+        let clock = crate::clock::ManualClock::new(0);
+        let transport = Arc::new(ScheduledTransport::new(Recorder::default(), 1, plan, clock.clone()));
+        let digest = || ProtocolMessage::Digest { heads: vec![] };
+
+        assert!(transport.send(3, digest()).await.is_err(), "no contact with 3");
+
+        let sender = transport.clone();
+        let sending = tokio::spawn(async move { sender.send(2, digest()).await });
+        tokio::task::yield_now().await;
+        clock.set(5 * S);
+        tokio::task::yield_now().await;
+        assert!(transport.inner.0.lock().unwrap().is_empty(), "held until the window opens");
+        clock.set(10 * S);
+        sending.await.unwrap().unwrap();
+        assert_eq!(*transport.inner.0.lock().unwrap(), [2]);
+
+        clock.set(21 * S);
+        assert!(transport.send(2, digest()).await.is_err(), "the window has closed");
+    }
+
+    #[test]
+    fn malformed_rows_report_line_numbers() {
+        let err = ContactPlan::parse("a,b,0,10,1,0\na,b,10,5,1,0\n").unwrap_err();
+        assert!(matches!(err, ContactPlanError::Parse { line: 2, .. }));
+        let plan = ContactPlan::parse("a,b,0,10,1,0\n").unwrap();
+        assert!(matches!(plan.resolve(|_| None::<u8>), Err(ContactPlanError::UnknownNode(_))));
+    }
+
+    #[test]
+    fn contacts_at_the_end_of_time_do_not_overflow() {
+        let plan = ContactPlan::parse("a,b,0,1e20,1,600\n").unwrap().resolve(|name| name.chars().next()).unwrap();
+        assert_eq!(plan.route('a', 'b', 0, 1).unwrap().arrival(), 601 * S);
+
+        // A window that closes at the very end of the time axis.
+        let last = u128::MAX - 10;
+        let contact = Contact { start: last, end: u128::MAX, ..plan.contacts[0].clone() };
+        assert_eq!(contact.arrival(last, 1_000), None, "transmission runs past the end of time");
+        assert_eq!(contact.arrival(last, 0), None, "light time runs past the end of time");
+
+        assert!(matches!(plan.clone().with_epoch(u128::MAX), Err(ContactPlanError::EpochOverflow(_))));
+        assert_eq!(plan.with_epoch(S).unwrap().contacts[0].start, S);
+    }
+}
@@ -0,0 +1,432 @@
+//! Built-in CRDT data types addressable by key.
+//!
+//! Registers (`Put`) resolve concurrency by keeping one sibling. Data that several planets edit at
+//! once — mission logs, crew rosters, tallies — needs richer merge semantics, so a key can instead
+//! hold one of the classic CRDTs below. Each is updated by an [`Operation::Crdt`] event and
+//! materialized by the DAG fold in [`crate::state`].
+//!
+//! Every update is tagged by the hash of the event that carries it, which is globally unique, so
+//! the types only need the DAG's causal structure (not replica ids or wall clocks):
+//! - [`PnCounter`]: increments and decrements from all replicas are summed.
+//! - [`OrSet`]: observed-remove set; a remove only cancels the adds in the remover's causal past,
+//!   so a concurrent re-add wins.
+//! - [`LwwMap`]: per-field last-writer-wins, "last" being later in the DAG linearization.
+//! - [`Rga`]: replicated growable array for text; concurrent inserts at the same position are
+//!   ordered by the DAG linearization they are replayed in, and deletes leave tombstones so
+//!   positions stay addressable.
+//!
+//! [`Operation::Crdt`]: crate::event::Operation::Crdt
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::event::EventHash;
+
+/// An update to the CRDT stored under a key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum CrdtOp {
+    Counter(CounterOp),
+    Set(SetOp),
+    Map(MapOp),
+    Sequence(SequenceOp),
+}
+
+/// PN-counter update.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum CounterOp {
+    Increment(u64),
+    Decrement(u64),
+}
+
+/// Observed-remove set update.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SetOp {
+    Add(Vec<u8>),
+    /// Removes every add of the element that is in the remover's causal past.
+    Remove(Vec<u8>),
+}
+
+/// Last-writer-wins map update.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum MapOp {
+    Set(String, Vec<u8>),
+    Remove(String),
+}
+
+/// Identifier of a single character in an [`Rga`]: the inserting event, the insert's index among
+/// that event's operations and the character's offset within the inserted text.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SeqId {
+    pub event: EventHash,
+    /// Position of the insert among the event's operations, counting through nested
+    /// [`Batch`](crate::event::Operation::Batch)es in order; 0 for an event that is not a batch.
+    pub op: u32,
+    pub offset: u32,
+}
+
+/// Replicated growable array (text) update.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SequenceOp {
+    /// Inserts `text` immediately after the character `after` (`None` inserts at the start).
+    Insert { after: Option<SeqId>, text: String },
+    /// Tombstones the given characters.
+    Delete(Vec<SeqId>),
+}
+
+/// Positive-negative counter.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PnCounter {
+    increments: u64,
+    decrements: u64,
+}
+
+impl PnCounter {
+    /// Current counter value (increments minus decrements).
+    pub fn value(&self) -> i128 {
+        self.increments as i128 - self.decrements as i128
+    }
+
+    pub(crate) fn apply(&mut self, op: &CounterOp) {
+        match op {
+            CounterOp::Increment(n) => self.increments = self.increments.saturating_add(*n),
+            CounterOp::Decrement(n) => self.decrements = self.decrements.saturating_add(*n),
+        }
+    }
+}
+
+/// Observed-remove (add-wins) set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrSet {
+    /// Each element maps to the hashes of the events that added it and have not been removed.
+    elements: BTreeMap<Vec<u8>, BTreeSet<EventHash>>,
+}
+
+impl OrSet {
+    /// Whether `element` is currently in the set.
+    pub fn contains(&self, element: &[u8]) -> bool {
+        self.elements.contains_key(element)
+    }
+
+    /// Iterates over the members in byte order.
+    pub fn iter(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.elements.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub(crate) fn add(&mut self, element: &[u8], tag: EventHash) {
+        self.elements.entry(element.to_vec()).or_default().insert(tag);
+    }
+
+    /// Drops the tags of `element` that the remover observed; concurrent adds survive.
+    pub(crate) fn remove(&mut self, element: &[u8], observed: &HashSet<EventHash>) {
+        if let Some(tags) = self.elements.get_mut(element) {
+            tags.retain(|tag| !observed.contains(tag));
+            if tags.is_empty() {
+                self.elements.remove(element);
+            }
+        }
+    }
+}
+
+/// Map whose fields are independent last-writer-wins registers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LwwMap {
+    fields: BTreeMap<String, Vec<u8>>,
+}
+
+impl LwwMap {
+    pub fn get(&self, field: &str) -> Option<&Vec<u8>> {
+        self.fields.get(field)
+    }
+
+    /// Iterates over fields in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Vec<u8>)> {
+        self.fields.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Applies an update. The fold applies events in linearization order, so the update applied
+    /// last is the last writer.
+    pub(crate) fn apply(&mut self, op: &MapOp) {
+        match op {
+            MapOp::Set(field, value) => {
+                self.fields.insert(field.clone(), value.clone());
+            }
+            MapOp::Remove(field) => {
+                self.fields.remove(field);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RgaElement {
+    id: SeqId,
+    ch: char,
+    deleted: bool,
+}
+
+/// Replicated growable array of characters.
+///
+/// There is no RGA sibling ordering (no per-insert timestamps compared at the anchor): each insert
+/// simply goes directly after its anchor, and convergence relies on every replica replaying the
+/// inserts in the DAG's deterministic linearization. An `Rga` updated in any other order can
+/// diverge.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rga {
+    elements: Vec<RgaElement>,
+}
+
+impl Rga {
+    /// The visible text (tombstones excluded).
+    pub fn text(&self) -> String {
+        self.elements
+            .iter()
+            .filter(|e| !e.deleted)
+            .map(|e| e.ch)
+            .collect()
+    }
+
+    /// Identifiers of the visible characters, in order. Clients use these as `after` anchors and
+    /// delete targets.
+    pub fn ids(&self) -> Vec<SeqId> {
+        self.elements
+            .iter()
+            .filter(|e| !e.deleted)
+            .map(|e| e.id)
+            .collect()
+    }
+
+    /// Applies an update authored by operation `op_index` of event `tag`.
+    ///
+    /// Inserts are placed directly after their anchor. Since the fold applies events in the same
+    /// linearization on every replica, concurrent inserts at one anchor end up in the same order
+    /// everywhere (the later one in the linearization first, as in classic RGA). An insert whose
+    /// anchor is unknown is ignored; anchors always lie in the inserter's causal past, so this only
+    /// happens for malformed operations.
+    pub(crate) fn apply(&mut self, op: &SequenceOp, tag: EventHash, op_index: u32) {
+        match op {
+            SequenceOp::Insert { after, text } => {
+                let pos = match after {
+                    None => 0,
+                    Some(anchor) => match self.elements.iter().position(|e| e.id == *anchor) {
+                        Some(idx) => idx + 1,
+                        None => return,
+                    },
+                };
+                let inserted = text.chars().enumerate().map(|(offset, ch)| RgaElement {
+                    id: SeqId {
+                        event: tag,
+                        op: op_index,
+                        offset: offset as u32,
+                    },
+                    ch,
+                    deleted: false,
+                });
+                self.elements.splice(pos..pos, inserted);
+            }
+            SequenceOp::Delete(ids) => {
+                for element in self.elements.iter_mut() {
+                    if ids.contains(&element.id) {
+                        element.deleted = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dag::SpacetimeDAG;
+    use crate::event::{Event, Operation};
+    use crate::spacetime::SpacetimeCoord;
+    use crate::state::{State, Value};
+
+    fn crdt_event(parents: &[EventHash], t: u128, key: &str, op: CrdtOp) -> Event {
+        Event::new(
+            parents.iter().cloned().collect(),
+            SpacetimeCoord {
+                t,
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Operation::Crdt(key.to_string(), op),
+        )
+    }
+
+    #[test]
+    fn or_set_concurrent_add_wins_over_remove() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis = dag.heads[0];
+
+        let add = crdt_event(&[genesis], 10, "crew", CrdtOp::Set(SetOp::Add(b"ada".to_vec())));
+        let add_hash = add.hash;
+        dag.add_event(add).unwrap();
+
+        // Earth removes Ada while Mars, concurrently, re-adds her.
+        let remove = crdt_event(&[add_hash], 20, "crew", CrdtOp::Set(SetOp::Remove(b"ada".to_vec())));
+        let readd = crdt_event(&[add_hash], 20, "crew", CrdtOp::Set(SetOp::Add(b"ada".to_vec())));
+        dag.add_event(remove).unwrap();
+        dag.add_event(readd).unwrap();
+
+        match State::from_dag(&dag).get("crew") {
+            Some(Value::OrSet(set)) => assert!(set.contains(b"ada")),
+            other => panic!("expected OR-set, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rga_keeps_concurrent_inserts() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis = dag.heads[0];
+
+        let base = crdt_event(
+            &[genesis],
+            10,
+            "log",
+            CrdtOp::Sequence(SequenceOp::Insert {
+                after: None,
+                text: "ac".to_string(),
+            }),
+        );
+        let anchor = SeqId {
+            event: base.hash,
+            op: 0,
+            offset: 0,
+        };
+        let base_hash = base.hash;
+        dag.add_event(base.clone()).unwrap();
+
+        let inserts: Vec<Event> = ["b", "B"]
+            .into_iter()
+            .map(|text| {
+                crdt_event(
+                    &[base_hash],
+                    20,
+                    "log",
+                    CrdtOp::Sequence(SequenceOp::Insert {
+                        after: Some(anchor),
+                        text: text.to_string(),
+                    }),
+                )
+            })
+            .collect();
+
+        // The two inserts have the same depth, so the one with the larger hash replays last and
+        // lands directly after the anchor, whichever order a replica receives them in.
+        let (first, second) = if inserts[0].hash > inserts[1].hash { ("b", "B") } else { ("B", "b") };
+        let expected = format!("a{first}{second}c");
+
+        let mut reversed = SpacetimeDAG::new();
+        reversed.add_event(base).unwrap();
+        for ev in inserts.iter().rev() {
+            reversed.add_event(ev.clone()).unwrap();
+        }
+        for ev in inserts {
+            dag.add_event(ev).unwrap();
+        }
+
+        for dag in [&dag, &reversed] {
+            match State::from_dag(dag).get("log") {
+                Some(Value::Sequence(rga)) => assert_eq!(rga.text(), expected),
+                other => panic!("expected sequence, got {other:?}"),
+            }
+            match dag.state().get("log") {
+                Some(Value::Sequence(rga)) => assert_eq!(rga.text(), expected),
+                other => panic!("expected sequence, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn pn_counter_sums_concurrent_updates() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis = dag.heads[0];
+
+        let up = crdt_event(&[genesis], 10, "fuel", CrdtOp::Counter(CounterOp::Increment(7)));
+        let down = crdt_event(&[genesis], 10, "fuel", CrdtOp::Counter(CounterOp::Decrement(10)));
+        let (up_hash, down_hash) = (up.hash, down.hash);
+        dag.add_event(up).unwrap();
+        dag.add_event(down).unwrap();
+        let merged = crdt_event(&[up_hash, down_hash], 20, "fuel", CrdtOp::Counter(CounterOp::Increment(1)));
+        dag.add_event(merged).unwrap();
+
+        match State::from_dag(&dag).get("fuel") {
+            Some(Value::PnCounter(counter)) => assert_eq!(counter.value(), -2),
+            other => panic!("expected PN-counter, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn lww_map_fields_resolve_independently() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis = dag.heads[0];
+        let set = |field: &str, value: &[u8]| CrdtOp::Map(MapOp::Set(field.to_string(), value.to_vec()));
+
+        let first = crdt_event(&[genesis], 10, "probe", set("mode", b"cruise"));
+        let first_hash = first.hash;
+        dag.add_event(first).unwrap();
+        // Concurrently, Earth switches the mode and Mars sets another field.
+        let mode = crdt_event(&[first_hash], 20, "probe", set("mode", b"orbit"));
+        let target = crdt_event(&[first_hash], 20, "probe", set("target", b"phobos"));
+        let mode_hash = mode.hash;
+        dag.add_event(mode).unwrap();
+        dag.add_event(target).unwrap();
+        let removed = crdt_event(&[mode_hash], 30, "probe", CrdtOp::Map(MapOp::Remove("mode".into())));
+        dag.add_event(removed).unwrap();
+
+        match State::from_dag(&dag).get("probe") {
+            Some(Value::LwwMap(map)) => {
+                assert_eq!(map.get("mode"), None, "the causally later remove wins");
+                assert_eq!(map.get("target").map(Vec::as_slice), Some(&b"phobos"[..]));
+                assert_eq!(map.len(), 1);
+            }
+            other => panic!("expected LWW map, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn inserts_in_one_batch_get_distinct_ids() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis = dag.heads[0];
+        let insert = |text: &str| Operation::Crdt("log".into(), CrdtOp::Sequence(SequenceOp::Insert { after: None, text: text.into() }));
+
+        let batch = Event::new(
+            [genesis].into_iter().collect(),
+            SpacetimeCoord { t: 10, x: 0.0, y: 0.0, z: 0.0 },
+            Operation::Batch(vec![insert("ab"), insert("cd")]),
+        );
+        let batch_hash = batch.hash;
+        dag.add_event(batch).unwrap();
+        // Deleting the first character of the first insert must not touch the second insert.
+        let first = SeqId { event: batch_hash, op: 0, offset: 0 };
+        dag.add_event(crdt_event(&[batch_hash], 20, "log", CrdtOp::Sequence(SequenceOp::Delete(vec![first])))).unwrap();
+
+        match State::from_dag(&dag).get("log") {
+            Some(Value::Sequence(rga)) => {
+                assert_eq!(rga.text(), "cdb");
+                assert_eq!(rga.ids().iter().map(|id| id.op).collect::<Vec<_>>(), [1, 1, 0]);
+            }
+            other => panic!("expected sequence, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Store-and-forward custody transfer.
+//!
+//! Borrowing from the Bundle Protocol's custody model: a node that sends an event keeps it until
+//! the next hop acknowledges taking custody, instead of fire-and-forget. Each peer has its own
+//! FIFO outbound queue; only the head of a queue is in flight, so a child event never overtakes
+//! the parents it depends on. A failed attempt (link down, peer unreachable, no acknowledgement) is
+//! retried with exponential backoff for as long as it takes.
+//!
+//! The queue can be backed by a file so that events awaiting custody survive a restart.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::protocol::ProtocolMessage;
+
+/// Identifies a queued bundle for status queries. Assigned sequentially per queue.
+pub type BundleId = u64;
+
+#[derive(Debug, Error)]
+pub enum CustodyError {
+    #[error("failed to access custody store: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("corrupt custody store: {0}")]
+    Decode(#[from] bincode::Error),
+}
+
+/// Where a queued bundle is in its journey to the next hop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Still held locally; `attempts` transmissions have failed so far.
+    Pending { attempts: u32 },
+    /// The next hop has acknowledged custody; this node no longer holds the bundle.
+    Delivered,
+}
+
+/// Backoff between transmission attempts of the same bundle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Delay after the first failure, in ns; doubled after each further failure.
+    pub initial_ns: u128,
+    /// Upper bound on the delay, in ns.
+    pub max_ns: u128,
+}
+
+impl Default for RetryPolicy {
+    /// One second, backing off to a minute.
+    fn default() -> Self {
+        Self {
+            initial_ns: 1_000_000_000,
+            max_ns: 60 * 1_000_000_000,
+        }
+    }
+}
+
+/// A message held for custody transfer to a peer addressed by `P`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bundle<P> {
+    pub id: BundleId,
+    pub peer: P,
+    pub message: ProtocolMessage,
+    pub attempts: u32,
+    /// Earliest coordinate time (ns) of the next attempt.
+    pub next_attempt: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "P: Serialize", deserialize = "P: Ord + DeserializeOwned"))]
+struct Store<P> {
+    next_id: BundleId,
+    queues: BTreeMap<P, VecDeque<Bundle<P>>>,
+}
+
+impl<P> Default for Store<P> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            queues: BTreeMap::new(),
+        }
+    }
+}
+
+/// Per-peer outbound queues of bundles awaiting custody acknowledgement, for peers addressed by
+/// `P` (a port, a UUID, ...).
+pub struct CustodyQueue<P> {
+    store: Store<P>,
+    path: Option<PathBuf>,
+    pub retry: RetryPolicy,
+}
+
+impl<P> Default for CustodyQueue<P> {
+    fn default() -> Self {
+        Self {
+            store: Store::default(),
+            path: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl<P: Copy + Ord + Serialize + DeserializeOwned> CustodyQueue<P> {
+    /// An in-memory queue.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A queue persisted at `path`, resuming whatever was pending there.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CustodyError> {
+        let path = path.as_ref().to_path_buf();
+        let store = match std::fs::read(&path) {
+            Ok(bytes) => bincode::deserialize(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Store::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            store,
+            path: Some(path),
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Queues `message` for `peer`, to be sent no earlier than `not_before` (ns).
+    pub fn enqueue(&mut self, peer: P, message: ProtocolMessage, not_before: u128) -> Result<BundleId, CustodyError> {
+        let id = self.store.next_id;
+        self.store.next_id += 1;
+        self.store.queues.entry(peer).or_default().push_back(Bundle {
+            id,
+            peer,
+            message,
+            attempts: 0,
+            next_attempt: not_before,
+        });
+        self.persist()?;
+        Ok(id)
+    }
+
+    /// The head-of-line bundle of every peer whose next attempt is due at `now`.
+    pub fn due(&self, now: u128) -> Vec<Bundle<P>> {
+        self.store
+            .queues
+            .values()
+            .filter_map(VecDeque::front)
+            .filter(|b| b.next_attempt <= now)
+            .cloned()
+            .collect()
+    }
+
+    /// Records that the next hop took custody of bundle `id`, releasing it.
+    pub fn acknowledge(&mut self, id: BundleId) -> Result<(), CustodyError> {
+        for queue in self.store.queues.values_mut() {
+            queue.retain(|b| b.id != id);
+        }
+        self.store.queues.retain(|_, q| !q.is_empty());
+        self.persist()
+    }
+
+    /// Records a failed attempt at `now`, scheduling the next one after the backoff.
+    pub fn failed(&mut self, id: BundleId, now: u128) -> Result<DeliveryStatus, CustodyError> {
+        let retry = self.retry;
+        let Some(bundle) = self.store.queues.values_mut().flatten().find(|b| b.id == id) else {
+            return Ok(DeliveryStatus::Delivered);
+        };
+        let backoff = retry.initial_ns.saturating_mul(1u128 << bundle.attempts.min(64));
+        bundle.next_attempt = now + backoff.min(retry.max_ns);
+        bundle.attempts += 1;
+        let status = DeliveryStatus::Pending { attempts: bundle.attempts };
+        self.persist()?;
+        Ok(status)
+    }
+
+    /// Delivery status of bundle `id`, or `None` if this queue never issued it.
+    pub fn status(&self, id: BundleId) -> Option<DeliveryStatus> {
+        if id >= self.store.next_id {
+            return None;
+        }
+        let pending = self.store.queues.values().flatten().find(|b| b.id == id);
+        Some(pending.map_or(DeliveryStatus::Delivered, |b| DeliveryStatus::Pending { attempts: b.attempts }))
+    }
+
+    /// Number of bundles held for `peer`.
+    pub fn pending(&self, peer: P) -> usize {
+        self.store.queues.get(&peer).map_or(0, VecDeque::len)
+    }
+
+    fn persist(&self) -> Result<(), CustodyError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // Write-then-rename so a crash never leaves a truncated store behind.
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, bincode::serialize(&self.store)?)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const S: u128 = 1_000_000_000;
+
+    fn hello(x: f64) -> ProtocolMessage {
+        ProtocolMessage::Hello { coords: (x, 0.0, 0.0), heads: Vec::new() }
+    }
+
+    #[test]
+    fn head_of_line_retries_with_backoff_until_custody_is_taken() {
+        let mut queue = CustodyQueue::<u16>::new();
+        let first = queue.enqueue(5001, hello(1.0), 0).unwrap();
+        let second = queue.enqueue(5001, hello(2.0), 0).unwrap();
+        let later = queue.enqueue(5002, hello(3.0), 10 * S).unwrap();
+
+        // One bundle in flight per peer, and nothing before its window.
+        let due: Vec<_> = queue.due(0).iter().map(|b| b.id).collect();
+        assert_eq!(due, [first]);
+
+        assert_eq!(queue.failed(first, 0).unwrap(), DeliveryStatus::Pending { attempts: 1 });
+        assert_eq!(queue.failed(first, S).unwrap(), DeliveryStatus::Pending { attempts: 2 });
+        assert!(queue.due(2 * S).is_empty(), "second failure backs off two seconds");
+        assert_eq!(queue.due(3 * S).len(), 1);
+
+        queue.acknowledge(first).unwrap();
+        assert_eq!(queue.status(first), Some(DeliveryStatus::Delivered));
+        let due: Vec<_> = queue.due(10 * S).iter().map(|b| b.id).collect();
+        assert_eq!(due, [second, later]);
+        assert_eq!(queue.status(99), None);
+    }
+
+    #[test]
+    fn pending_bundles_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("lightcone-custody-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut queue = CustodyQueue::<u16>::open(&path).unwrap();
+        let delivered = queue.enqueue(5001, hello(1.0), 0).unwrap();
+        let held = queue.enqueue(5001, hello(2.0), 0).unwrap();
+        queue.acknowledge(delivered).unwrap();
+        queue.failed(held, 0).unwrap();
+        drop(queue);
+
+        let reopened = CustodyQueue::<u16>::open(&path).unwrap();
+        assert_eq!(reopened.pending(5001), 1);
+        assert_eq!(reopened.status(held), Some(DeliveryStatus::Pending { attempts: 1 }));
+        assert_eq!(reopened.status(delivered), Some(DeliveryStatus::Delivered));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
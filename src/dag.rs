@@ -1,178 +1,709 @@
-//! Spacetime event DAG (CRDT core).
-//!
-//! Minkowski-KV models database mutations as *immutable events* embedded in spacetime. Each event
-//! references its causal predecessors (parents), forming an append-only directed acyclic graph.
-//!
-//! This structure behaves like an operation-based CRDT:
-//! - Events are immutable and content-addressed; replicas exchange events (gossip) rather than
-//!   shipping state.
-//! - Merging is monotonic: receiving an event only adds nodes/edges.
-//! - Conflicts are not “overwritten”; they become explicit concurrency in the graph.
-//!
-//! The key relativity-driven idea is how we interpret concurrency:
-//! - If two writes are *timelike* related (inside each other's light cone), there is a physically
-//!   enforceable causal order.
-//! - If two writes are *spacelike* separated, there is no causal order implied by physics alone,
-//!   and the DAG forks.
-//!
-//! The `heads` set is the database's **concurrent frontier**: the current “tips” of the graph that
-//! have no known successors. When independent replicas write concurrently, heads temporarily
-//! diverge; subsequent events (or application-level resolution) may join the branches.
-
-use std::collections::BTreeSet;
-
-use dashmap::DashMap;
-use petgraph::graph::NodeIndex;
-use petgraph::stable_graph::StableDiGraph;
-use thiserror::Error;
-
-/// Immutable, content-addressed operation record.
-///
-/// Events are the unit of replication in Minkowski-KV: replicas gossip events and rebuild/merge
-/// the DAG locally. Events are append-only; they are never mutated in place.
-pub(crate) use crate::event::Event;
-
-/// Stable identifier for an `Event` (typically a cryptographic hash of its contents).
-///
-/// We treat hashes as globally unique IDs for deduplication and for connecting parent links.
-pub(crate) use crate::event::EventHash;
-
-use crate::event::Operation;
-use crate::spacetime::SpacetimeCoord;
-
-#[derive(Debug, Error)]
-pub enum DagError {
-    /// An event references a parent that this replica does not yet have.
-    ///
-    /// In a gossip-based system this is normal: parents may arrive later, or may need to be
-    /// requested explicitly. The current implementation is strict and rejects the event.
-    #[error("Missing parent event")]
-    MissingParent,
-}
-
-/// Append-only event DAG used as the CRDT backbone of the database.
-///
-/// Design goals:
-/// - **Monotonic growth:** merges only add information.
-/// - **Explicit concurrency:** spacelike-separated writes produce multiple heads rather than a
-///   forced total order.
-/// - **Fast lookup:** events are indexed by `EventHash` for deduplication and parent resolution.
-///
-/// Note: The DAG records causality via explicit parent links. Spacetime (Minkowski) reasoning is
-/// used elsewhere to decide when events are allowed to arrive and whether an ordering is
-/// meaningful; the DAG's job is to preserve the partial order once events are admitted.
-pub struct SpacetimeDAG {
-    /// The underlying graph: nodes are events, edges point to parents.
-    pub graph: StableDiGraph<Event, ()>,
-    /// Maps content hashes to node indices for O(1)-ish parent resolution.
-    pub index_map: DashMap<EventHash, NodeIndex>,
-    /// The current concurrency frontier (tips of the DAG).
-    ///
-    /// Intuition: if two replicas write concurrently (no known causal relation), both writes remain
-    /// as heads until a later event references one or both as parents.
-    pub heads: Vec<EventHash>,
-}
-
-impl SpacetimeDAG {
-    /// Creates a new DAG with a single genesis event.
-    ///
-    /// Genesis anchors the graph so that every subsequent event has at least one ancestor.
-    pub fn new() -> Self {
-        let mut dag = Self {
-            graph: StableDiGraph::new(),
-            index_map: DashMap::new(),
-            heads: Vec::new(),
-        };
-
-        let genesis = Event::new(
-            BTreeSet::new(),
-            SpacetimeCoord {
-                t: 0,
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            Operation::Genesis,
-        );
-
-        let genesis_hash = genesis.hash;
-        let node = dag.graph.add_node(genesis);
-        dag.index_map.insert(genesis_hash, node);
-        dag.heads.push(genesis_hash);
-
-        dag
-    }
-
-    /// Appends an event to the DAG.
-    ///
-    /// This is a pure append operation: if all parents are present, we insert the event and connect
-    /// edges to its parents. We also update `heads` to reflect the new concurrency frontier.
-    ///
-    /// Current limitation:
-    /// - Events that arrive “before their parents” are rejected. In a full gossip implementation we
-    ///   would queue or request missing parents and retry.
-    pub fn add_event(&mut self, event: Event) -> Result<(), DagError> {
-        for parent in &event.parents {
-            if !self.index_map.contains_key(parent) {
-                return Err(DagError::MissingParent);
-            }
-        }
-
-        let event_hash = event.hash;
-        let node = self.graph.add_node(event);
-        self.index_map.insert(event_hash, node);
-
-        let parent_hashes: Vec<EventHash> = self.graph[node].parents.iter().cloned().collect();
-        for parent in parent_hashes {
-            if let Some(parent_idx) = self.index_map.get(&parent) {
-                self.graph.add_edge(node, *parent_idx, ());
-            }
-        }
-
-        self.heads.retain(|h| !self.graph[node].parents.contains(h));
-        self.heads.push(event_hash);
-
-        Ok(())
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn concurrent_heads_are_tracked() {
-        let mut dag = SpacetimeDAG::new();
-        let genesis_hash = dag.heads[0];
-
-        let mut parents = BTreeSet::new();
-        parents.insert(genesis_hash);
-
-        let earth_event = Event::new(
-            parents.clone(),
-            SpacetimeCoord {
-                t: 0,
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            Operation::Put("earth".to_string(), vec![1]),
-        );
-
-        let mars_event = Event::new(
-            parents,
-            SpacetimeCoord {
-                t: 0,
-                x: 5.4e10,
-                y: 0.0,
-                z: 0.0,
-            },
-            Operation::Put("mars".to_string(), vec![2]),
-        );
-
-        dag.add_event(earth_event).expect("earth should attach to genesis");
-        dag.add_event(mars_event).expect("mars should attach to genesis");
-
-        assert_eq!(dag.heads.len(), 2, "Two concurrent heads expected");
-    }
-}
+//! Spacetime event DAG (CRDT core).
+//!
+//! Minkowski-KV models database mutations as *immutable events* embedded in spacetime. Each event
+//! references its causal predecessors (parents), forming an append-only directed acyclic graph.
+//!
+//! This structure behaves like an operation-based CRDT:
+//! - Events are immutable and content-addressed; replicas exchange events (gossip) rather than
+//!   shipping state.
+//! - Merging is monotonic: receiving an event only adds nodes/edges.
+//! - Conflicts are not “overwritten”; they become explicit concurrency in the graph.
+//!
+//! The key relativity-driven idea is how we interpret concurrency:
+//! - If two writes are *timelike* related (inside each other's light cone), there is a physically
+//!   enforceable causal order.
+//! - If two writes are *spacelike* separated, there is no causal order implied by physics alone,
+//!   and the DAG forks.
+//!
+//! The `heads` set is the database's **concurrent frontier**: the current “tips” of the graph that
+//! have no known successors. When independent replicas write concurrently, heads temporarily
+//! diverge; subsequent events (or application-level resolution) may join the branches.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use dashmap::DashMap;
+use petgraph::graph::NodeIndex;
+use petgraph::stable_graph::StableDiGraph;
+use thiserror::Error;
+
+/// Immutable, content-addressed operation record.
+///
+/// Events are the unit of replication in Minkowski-KV: replicas gossip events and rebuild/merge
+/// the DAG locally. Events are append-only; they are never mutated in place.
+pub(crate) use crate::event::Event;
+
+/// Stable identifier for an `Event` (typically a cryptographic hash of its contents).
+///
+/// We treat hashes as globally unique IDs for deduplication and for connecting parent links.
+pub(crate) use crate::event::EventHash;
+
+use crate::event::{LimitError, Operation};
+use crate::spacetime::SpacetimeCoord;
+use crate::state::State;
+
+#[derive(Debug, Error)]
+pub enum DagError {
+    /// An event references a parent that this replica does not yet have.
+    ///
+    /// In a gossip-based system this is normal: parents may arrive later, or may need to be
+    /// requested explicitly. The current implementation is strict and rejects the event.
+    #[error("Missing parent event")]
+    MissingParent,
+    /// A query referenced an event this replica does not have.
+    #[error("Unknown event")]
+    UnknownEvent,
+    /// An event lists a parent outside its past light cone (spacelike-separated or in its future).
+    ///
+    /// The writer claims to have observed something whose signal could not yet have reached it,
+    /// which is physically impossible under the spacetime model.
+    #[error("Parent event lies outside the child's past light cone")]
+    AcausalParent,
+    /// A non-root event carries the [`Operation::Genesis`] payload.
+    ///
+    /// Only the shared root may anchor the frame; anything else claiming to is forged.
+    #[error("Only the root event may carry a genesis payload")]
+    ForgedGenesis,
+    /// The event exceeds a [decode limit](crate::event::MAX_PARENTS), so no peer would accept it.
+    #[error(transparent)]
+    Limit(#[from] LimitError),
+}
+
+/// Append-only event DAG used as the CRDT backbone of the database.
+///
+/// Design goals:
+/// - **Monotonic growth:** merges only add information.
+/// - **Explicit concurrency:** spacelike-separated writes produce multiple heads rather than a
+///   forced total order.
+/// - **Fast lookup:** events are indexed by `EventHash` for deduplication and parent resolution.
+///
+/// Note: The DAG records causality via explicit parent links. Spacetime (Minkowski) reasoning is
+/// used elsewhere to decide when events are allowed to arrive and whether an ordering is
+/// meaningful; the DAG's job is to preserve the partial order once events are admitted.
+pub struct SpacetimeDAG {
+    /// The underlying graph: nodes are events, edges point to parents.
+    pub graph: StableDiGraph<Event, ()>,
+    /// Maps content hashes to node indices for O(1)-ish parent resolution.
+    pub index_map: DashMap<EventHash, NodeIndex>,
+    /// The current concurrency frontier (tips of the DAG).
+    ///
+    /// Intuition: if two replicas write concurrently (no known causal relation), both writes remain
+    /// as heads until a later event references one or both as parents.
+    pub heads: Vec<EventHash>,
+    /// Length of the longest parent chain from genesis to each event (genesis has depth 0).
+    depths: HashMap<EventHash, u64>,
+    /// Events bucketed by coordinate time, so light-cone queries only scan the relevant half of
+    /// history.
+    time_index: BTreeMap<u128, Vec<EventHash>>,
+    /// Key/value state folded from every event, kept up to date by `add_event`.
+    state: State,
+    /// Linearization key `(depth, hash)` of the last event folded into `state`.
+    last_applied: Option<(u64, EventHash)>,
+    /// Extra clock uncertainty (nanoseconds) granted to every child, on top of its own error bounds,
+    /// when checking that its parents lie in its past light cone.
+    causal_tolerance_ns: u128,
+}
+
+impl Default for SpacetimeDAG {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpacetimeDAG {
+    /// Creates a new DAG with a single genesis event.
+    ///
+    /// Genesis anchors the graph so that every subsequent event has at least one ancestor.
+    pub fn new() -> Self {
+        let mut dag = Self {
+            graph: StableDiGraph::new(),
+            index_map: DashMap::new(),
+            heads: Vec::new(),
+            depths: HashMap::new(),
+            time_index: BTreeMap::new(),
+            state: State::new(),
+            last_applied: None,
+            causal_tolerance_ns: 0,
+        };
+
+        let genesis = Event::genesis();
+
+        let genesis_hash = genesis.hash;
+        let node = dag.graph.add_node(genesis);
+        dag.index_map.insert(genesis_hash, node);
+        dag.heads.push(genesis_hash);
+        dag.depths.insert(genesis_hash, 0);
+        dag.time_index.entry(0).or_default().push(genesis_hash);
+        dag.last_applied = Some((0, genesis_hash));
+
+        dag
+    }
+
+    /// Creates a new DAG that tolerates `tolerance_ns` of clock uncertainty at ingest.
+    ///
+    /// The tolerance is added to each child's own clock uncertainty, so writers whose clocks run
+    /// slightly behind are not rejected outright.
+    pub fn with_causal_tolerance(tolerance_ns: u128) -> Self {
+        Self {
+            causal_tolerance_ns: tolerance_ns,
+            ..Self::new()
+        }
+    }
+
+    /// Appends an event to the DAG.
+    ///
+    /// This is a pure append operation: if all parents are present, we insert the event and connect
+    /// edges to its parents. We also update `heads` to reflect the new concurrency frontier and fold
+    /// the event into the materialized [`State`]. Re-adding a known event is a no-op, so gossip
+    /// duplicates are harmless.
+    ///
+    /// Every parent must possibly lie in the event's past light cone, given both events' error
+    /// bounds and the configured clock tolerance; a parent that is *definitely* outside is rejected
+    /// with [`DagError::AcausalParent`]. Ambiguous cases near the cone boundary are admitted, since
+    /// they cannot be proven wrong. The root genesis event is exempt, since it anchors the frame
+    /// rather than recording a physical write; any other event carrying a genesis payload is
+    /// rejected with [`DagError::ForgedGenesis`], so the exemption cannot be claimed off the wire.
+    ///
+    /// Events over the [decode limits](crate::event::MAX_PARENTS) are rejected with
+    /// [`DagError::Limit`]: peers would refuse them on decode, so a local write must not get in.
+    ///
+    /// Events that arrive “before their parents” are rejected with [`DagError::MissingParent`];
+    /// [`Replica`](crate::replica::Replica) buffers them and requests the missing parents from the sender.
+    pub fn add_event(&mut self, event: Event) -> Result<(), DagError> {
+        if self.index_map.contains_key(&event.hash) {
+            return Ok(());
+        }
+
+        if matches!(event.payload, Operation::Genesis) {
+            return Err(DagError::ForgedGenesis);
+        }
+        event.check_limits()?;
+
+        for parent in &event.parents {
+            if !self.index_map.contains_key(parent) {
+                return Err(DagError::MissingParent);
+            }
+        }
+
+        for parent in &event.parents {
+            let parent = self.get(parent).ok_or(DagError::MissingParent)?;
+            if !is_root(parent) && !self.admits_parent(parent, &event) {
+                return Err(DagError::AcausalParent);
+            }
+        }
+
+        let event_hash = event.hash;
+        let node = self.graph.add_node(event);
+        self.index_map.insert(event_hash, node);
+
+        let parent_hashes: Vec<EventHash> = self.graph[node].parents.iter().cloned().collect();
+        for parent in parent_hashes {
+            if let Some(parent_idx) = self.index_map.get(&parent) {
+                self.graph.add_edge(node, *parent_idx, ());
+            }
+        }
+
+        self.heads.retain(|h| !self.graph[node].parents.contains(h));
+        self.heads.push(event_hash);
+
+        let depth = self.graph[node]
+            .parents
+            .iter()
+            .filter_map(|p| self.depths.get(p))
+            .max()
+            .map_or(0, |d| d + 1);
+        self.depths.insert(event_hash, depth);
+        self.time_index
+            .entry(self.graph[node].coords.t)
+            .or_default()
+            .push(event_hash);
+        self.fold(node, (depth, event_hash));
+
+        Ok(())
+    }
+
+    /// Whether `parent` may be timelike- or lightlike-earlier than `child`, allowing for both
+    /// events' error bounds plus the DAG's clock tolerance.
+    fn admits_parent(&self, parent: &Event, child: &Event) -> bool {
+        let mut child = child.uncertain_coords();
+        child.uncertainty.time_ns = child.uncertainty.time_ns.saturating_add(self.causal_tolerance_ns);
+        parent.uncertain_coords().may_precede(&child)
+    }
+
+    /// The key/value state materialized from every event in the DAG.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Materializes the state as of a causal frontier: the frontier events and their causal past.
+    ///
+    /// This answers "what did a replica whose heads were `frontier` see?", e.g. for incident
+    /// forensics. Every frontier hash must be known to this replica.
+    pub fn state_at(&self, frontier: &[EventHash]) -> Result<State, DagError> {
+        let mut subset = HashSet::new();
+        for hash in frontier {
+            if !self.index_map.contains_key(hash) {
+                return Err(DagError::UnknownEvent);
+            }
+            subset.insert(*hash);
+            subset.extend(self.ancestors(hash));
+        }
+        Ok(State::from_subset(self, &subset))
+    }
+
+    /// Materializes the state as observed at spacetime point `point`.
+    ///
+    /// An event is visible if its coordinates lie in `point`'s past light cone *and* all of its
+    /// parents are visible: an observer cannot have applied an effect whose causes have not yet
+    /// reached it. Genesis is the frame anchor rather than a physical write and is always visible.
+    pub fn state_observed_at(&self, point: &SpacetimeCoord) -> State {
+        let in_cone: HashSet<EventHash> =
+            self.past_light_cone(point).iter().map(|ev| ev.hash).collect();
+        let mut visible = HashSet::new();
+        for idx in self.linearize() {
+            let event = &self.graph[idx];
+            let anchored = is_root(event);
+            if anchored
+                || (in_cone.contains(&event.hash)
+                    && event.parents.iter().all(|p| visible.contains(p)))
+            {
+                visible.insert(event.hash);
+            }
+        }
+        State::from_subset(self, &visible)
+    }
+
+    /// Returns every event that could have influenced `point`: events whose coordinates lie inside
+    /// or on its past light cone, ordered by coordinate time.
+    ///
+    /// This is a purely physical query; it ignores parent links. Compare with [`Self::ancestors`],
+    /// which is what the writer *claims* to have seen.
+    pub fn past_light_cone(&self, point: &SpacetimeCoord) -> Vec<&Event> {
+        self.time_index
+            .range(..=point.t)
+            .flat_map(|(_, hashes)| hashes)
+            .filter_map(|hash| self.get(hash))
+            .filter(|ev| ev.coords.is_in_past_light_cone_of(point))
+            .collect()
+    }
+
+    /// Returns every event that `point` could influence: events whose coordinates lie inside or on
+    /// its future light cone, ordered by coordinate time.
+    pub fn future_light_cone(&self, point: &SpacetimeCoord) -> Vec<&Event> {
+        self.time_index
+            .range(point.t..)
+            .flat_map(|(_, hashes)| hashes)
+            .filter_map(|hash| self.get(hash))
+            .filter(|ev| point.is_in_past_light_cone_of(&ev.coords))
+            .collect()
+    }
+
+    /// Flags parent links that physics forbids, as `(child, parent)` pairs.
+    ///
+    /// A parent must lie in its child's past light cone; otherwise the writer claims to have seen
+    /// an event whose signal could not yet have reached it (a spacelike or future parent). Only
+    /// links that are definitely outside the cone given the events' error bounds are reported (the
+    /// DAG's ingest tolerance is not applied). Links to genesis are exempt, since genesis anchors the
+    /// frame rather than recording a write.
+    pub fn acausal_links(&self) -> Vec<(EventHash, EventHash)> {
+        let mut links = Vec::new();
+        for child in self.graph.node_weights() {
+            for parent_hash in &child.parents {
+                let Some(parent) = self.get(parent_hash) else {
+                    continue;
+                };
+                if !is_root(parent)
+                    && !parent.uncertain_coords().may_precede(&child.uncertain_coords())
+                {
+                    links.push((child.hash, parent.hash));
+                }
+            }
+        }
+        links
+    }
+
+    /// Folds a newly added event into `state`.
+    ///
+    /// The common case is an event that sorts after everything already folded (a causal successor,
+    /// or a concurrent event with a larger hash); it is applied incrementally. An event that sorts
+    /// *before* the last folded one is spliced into the history of the keys it writes, and only
+    /// those keys' later writes are re-applied (see [`State::apply_late`]).
+    fn fold(&mut self, node: NodeIndex, key: (u64, EventHash)) {
+        let mut state = std::mem::take(&mut self.state);
+        if self.last_applied.is_none_or(|last| key > last) {
+            state.apply(self, &self.graph[node]);
+            self.last_applied = Some(key);
+        } else {
+            state.apply_late(self, &self.graph[node]);
+        }
+        self.state = state;
+    }
+
+    /// Length of the longest parent chain from genesis to a known event.
+    pub(crate) fn depth(&self, hash: &EventHash) -> u64 {
+        self.depths[hash]
+    }
+
+    /// Looks up an event by its content hash.
+    pub fn get(&self, hash: &EventHash) -> Option<&Event> {
+        let idx = *self.index_map.get(hash)?;
+        self.graph.node_weight(idx)
+    }
+
+    /// Returns the hashes of every event in the causal past of `hash` (excluding `hash` itself).
+    ///
+    /// The causal past is the transitive closure of parent links. Unknown hashes have an empty past.
+    pub fn ancestors(&self, hash: &EventHash) -> HashSet<EventHash> {
+        let mut seen = HashSet::new();
+        let Some(start) = self.index_map.get(hash).map(|idx| *idx) else {
+            return seen;
+        };
+
+        let mut stack = vec![start];
+        while let Some(idx) = stack.pop() {
+            for parent in self.graph.neighbors(idx) {
+                if seen.insert(self.graph[parent].hash) {
+                    stack.push(parent);
+                }
+            }
+        }
+        seen
+    }
+
+    /// Returns every event in a deterministic total order that extends the causal partial order.
+    ///
+    /// Events are sorted by `(depth, hash)`, where depth is the length of the longest parent chain
+    /// back to genesis. A descendant is always deeper than its ancestors, so causes precede effects;
+    /// spacelike-concurrent events are ordered by hash, which every replica agrees on.
+    pub fn linearize(&self) -> Vec<NodeIndex> {
+        let mut order: Vec<NodeIndex> = self.graph.node_indices().collect();
+        order.sort_by_key(|idx| {
+            let hash = self.graph[*idx].hash;
+            (self.depths[&hash], hash)
+        });
+        order
+    }
+}
+
+/// Whether `event` is the shared genesis root, identified by its hash rather than its payload.
+fn is_root(event: &Event) -> bool {
+    event.parents.is_empty() && event.hash == Event::genesis().hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    use crate::spacetime::Uncertainty;
+
+    #[test]
+    fn concurrent_heads_are_tracked() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis_hash = dag.heads[0];
+
+        let mut parents = BTreeSet::new();
+        parents.insert(genesis_hash);
+
+        let earth_event = Event::new(
+            parents.clone(),
+            SpacetimeCoord {
+                t: 0,
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Operation::Put("earth".to_string(), vec![1]),
+        );
+
+        let mars_event = Event::new(
+            parents,
+            SpacetimeCoord {
+                t: 0,
+                x: 5.4e10,
+                y: 0.0,
+                z: 0.0,
+            },
+            Operation::Put("mars".to_string(), vec![2]),
+        );
+
+        dag.add_event(earth_event).expect("earth should attach to genesis");
+        dag.add_event(mars_event).expect("mars should attach to genesis");
+
+        assert_eq!(dag.heads.len(), 2, "Two concurrent heads expected");
+    }
+
+    #[test]
+    fn historical_reads_see_only_the_past() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis_hash = dag.heads[0];
+        let at = |t: u128| SpacetimeCoord {
+            t,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+
+        let v1 = Event::new(BTreeSet::from([genesis_hash]), at(1_000), Operation::Put("k".into(), b"v1".to_vec()));
+        let v1_hash = v1.hash;
+        let v2 = Event::new(BTreeSet::from([v1_hash]), at(2_000), Operation::Put("k".into(), b"v2".to_vec()));
+        dag.add_event(v1).unwrap();
+        dag.add_event(v2).unwrap();
+
+        let then = dag.state_at(&[v1_hash]).expect("v1 is known");
+        assert_eq!(then.get("k"), Some(&crate::state::Value::Bytes(b"v1".to_vec())));
+        assert!(matches!(dag.state_at(&[[9u8; 32]]), Err(DagError::UnknownEvent)));
+
+        let observed = dag.state_observed_at(&at(1_500));
+        assert_eq!(observed.get("k"), Some(&crate::state::Value::Bytes(b"v1".to_vec())));
+        // 1 km away at the same instant, light from v1 has not arrived yet.
+        let far = SpacetimeCoord { x: 1_000.0, ..at(1_500) };
+        assert!(dag.state_observed_at(&far).is_empty());
+    }
+
+    #[test]
+    fn acausal_parent_is_rejected() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis_hash = dag.heads[0];
+        let coord = |t: u128, x: f64| SpacetimeCoord { t, x, y: 0.0, z: 0.0 };
+
+        let earth = Event::new(BTreeSet::from([genesis_hash]), coord(1_000, 0.0), Operation::Merge);
+        let earth_hash = earth.hash;
+        dag.add_event(earth).unwrap();
+
+        let future_parent = Event::new(BTreeSet::from([earth_hash]), coord(999, 0.0), Operation::Merge);
+        let spacelike = Event::new(BTreeSet::from([earth_hash]), coord(1_001, 1_000.0), Operation::Merge);
+        assert!(matches!(dag.add_event(future_parent), Err(DagError::AcausalParent)));
+        assert!(matches!(dag.add_event(spacelike), Err(DagError::AcausalParent)));
+        assert_eq!(dag.graph.node_count(), 2, "rejected events must not be inserted");
+    }
+
+    #[test]
+    fn forged_genesis_is_rejected() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis_hash = dag.heads[0];
+        let coord = |t: u128, x: f64| SpacetimeCoord { t, x, y: 0.0, z: 0.0 };
+
+        // A "genesis" far in the future, then a child a light-year off it: neither may get in.
+        let forged = Event::new(BTreeSet::from([genesis_hash]), coord(u128::MAX / 2, 0.0), Operation::Genesis);
+        assert!(matches!(dag.add_event(forged.clone()), Err(DagError::ForgedGenesis)));
+
+        // Even a forged root already in the DAG (e.g. from an older replica) gets no exemption.
+        let node = dag.graph.add_node(forged.clone());
+        dag.index_map.insert(forged.hash, node);
+        dag.depths.insert(forged.hash, 1);
+        let child = Event::new(BTreeSet::from([forged.hash]), coord(1_000, 9.46e15), Operation::Merge);
+        assert!(matches!(dag.add_event(child), Err(DagError::AcausalParent)));
+        assert!(dag.acausal_links().is_empty());
+    }
+
+    #[test]
+    fn degenerate_error_bounds_cannot_smuggle_in_a_parent() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis_hash = dag.heads[0];
+        let earth = Event::new(BTreeSet::from([genesis_hash]), SpacetimeCoord { t: 1_000, x: 0.0, y: 0.0, z: 0.0 }, Operation::Merge);
+        let earth_hash = earth.hash;
+        dag.add_event(earth).unwrap();
+
+        // A light-year away and 1 ns later, with error bounds that would make the pair ambiguous.
+        let far = SpacetimeCoord { t: 1_001, x: 9.46e15, y: 0.0, z: 0.0 };
+        let child = |coords, time_ns, radius| {
+            Event::with_uncertainty(BTreeSet::from([earth_hash]), coords, Uncertainty { time_ns, radius }, Operation::Merge)
+        };
+        for forged in [
+            child(far, 0, f64::NAN),
+            child(far, 0, f64::INFINITY),
+            child(far, 0, -1.0),
+            child(far, u128::MAX, 0.0),
+            child(SpacetimeCoord { x: f64::NAN, ..far }, 0, 0.0),
+        ] {
+            assert!(matches!(dag.add_event(forged.clone()), Err(DagError::Limit(_))), "{forged:?}");
+            assert!(bincode::deserialize::<Event>(&bincode::serialize(&forged).unwrap()).is_err());
+        }
+        assert!(matches!(dag.add_event(child(far, 0, 0.0)), Err(DagError::AcausalParent)));
+    }
+
+    #[test]
+    fn light_cones_and_acausal_links() {
+        // One hour of clock slack lets the acausal link below through ingest so the audit can see it.
+        let mut dag = SpacetimeDAG::with_causal_tolerance(3_600 * 1_000_000_000);
+        let genesis_hash = dag.heads[0];
+        let coord = |t: u128, x: f64| SpacetimeCoord { t, x, y: 0.0, z: 0.0 };
+
+        // Mars claims Earth as a parent, yet is 1 km away and only 1 ns later: spacelike.
+        let earth = Event::new(BTreeSet::from([genesis_hash]), coord(1_000, 0.0), Operation::Merge);
+        let earth_hash = earth.hash;
+        let mars = Event::new(BTreeSet::from([earth_hash]), coord(1_001, 1_000.0), Operation::Merge);
+        let mars_hash = mars.hash;
+        dag.add_event(earth).unwrap();
+        dag.add_event(mars).unwrap();
+
+        let probe = coord(1_001, 0.0);
+        let past: Vec<_> = dag.past_light_cone(&probe).iter().map(|ev| ev.hash).collect();
+        assert!(past.contains(&earth_hash));
+        assert!(!past.contains(&mars_hash));
+
+        let future: Vec<_> = dag.future_light_cone(&coord(0, 0.0)).iter().map(|ev| ev.hash).collect();
+        assert!(future.contains(&earth_hash));
+        assert!(!future.contains(&mars_hash));
+
+        assert_eq!(dag.acausal_links(), vec![(mars_hash, earth_hash)]);
+    }
+
+    #[test]
+    fn incremental_state_matches_full_fold() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis_hash = dag.heads[0];
+
+        // Concurrent writes to one key arrive in both hash orders across these events, exercising
+        // both the in-order path and the late-arrival path.
+        let mut events: Vec<Event> = (0..8)
+            .map(|i| {
+                Event::new(
+                    BTreeSet::from([genesis_hash]),
+                    SpacetimeCoord {
+                        t: 10,
+                        x: 0.0,
+                        y: 0.0,
+                        z: 0.0,
+                    },
+                    Operation::Put("k".to_string(), vec![i]),
+                )
+            })
+            .collect();
+        events.sort_by_key(|ev| std::cmp::Reverse(ev.hash));
+        for ev in events {
+            dag.add_event(ev.clone()).expect("parents present");
+            dag.add_event(ev).expect("duplicates are ignored");
+        }
+
+        let rebuilt = State::from_dag(&dag);
+        assert_eq!(dag.state().entry("k"), rebuilt.entry("k"));
+        assert_eq!(dag.graph.node_count(), 9);
+    }
+
+    mod merge {
+        //! Property tests for the CRDT claims in the module docs: whatever order events arrive in,
+        //! and however often, every replica ends with the same events, heads and state.
+
+        use super::*;
+        use crate::crdt::{CrdtOp, SequenceOp, SetOp};
+        use crate::protocol::ProtocolMessage;
+        use crate::replica::Replica;
+        use crate::spacetime::{set_speed_of_light, Uncertainty, DEFAULT_C, SPEED_OF_LIGHT_TEST_LOCK};
+        use proptest::prelude::*;
+        use proptest::sample::Index;
+        use uuid::Uuid;
+
+        fn key(k: u8) -> String {
+            ["a", "b", "c"][k as usize % 3].to_string()
+        }
+
+        /// Payloads that touch a handful of shared keys, so concurrent events conflict often.
+        fn operation() -> BoxedStrategy<Operation> {
+            let leaf = prop_oneof![
+                (any::<u8>(), any::<u8>()).prop_map(|(k, v)| Operation::Put(key(k), vec![v])),
+                any::<u8>().prop_map(|k| Operation::Delete(key(k))),
+                (any::<u8>(), any::<u8>()).prop_map(|(k, v)| Operation::Append(key(k), vec![v])),
+                (any::<u8>(), -5..5i64).prop_map(|(k, n)| Operation::Increment(key(k), n)),
+                (any::<u8>(), any::<u8>()).prop_map(|(k, v)| Operation::CompareAndSet { key: key(k), expected: None, value: vec![v] }),
+                (any::<u8>(), any::<bool>(), 0..3u8).prop_map(|(k, add, v)| {
+                    let op = if add { SetOp::Add(vec![v]) } else { SetOp::Remove(vec![v]) };
+                    Operation::Crdt(key(k), CrdtOp::Set(op))
+                }),
+                (any::<u8>(), "[a-z]{1,3}").prop_map(|(k, text)| Operation::Crdt(key(k), CrdtOp::Sequence(SequenceOp::Insert { after: None, text }))),
+            ];
+            prop_oneof![
+                4 => leaf.clone(),
+                1 => prop::collection::vec(leaf, 1..3).prop_map(Operation::Batch),
+            ]
+            .boxed()
+        }
+
+        /// A random DAG, parents before children. Each event draws one or two parents among the
+        /// events before it (genesis included) and, when `cas` is set, turns into a compare-and-set
+        /// against one of them. Events are a second apart and a few hundred meters from each
+        /// other, so at the physical c every parent lies in its child's past light cone.
+        fn history() -> impl Strategy<Value = Vec<Event>> {
+            let spec = (prop::collection::vec(any::<Index>(), 1..3), operation(), 0.0..500.0f64, prop::option::of((any::<u8>(), any::<Index>())));
+            prop::collection::vec(spec, 1..24).prop_map(|specs| {
+                let mut events = vec![Event::genesis()];
+                for (i, (parents, payload, x, cas)) in specs.into_iter().enumerate() {
+                    let payload = match cas {
+                        Some((k, target)) => Operation::CompareAndSet { key: key(k), expected: Some(target.get(&events).hash), value: vec![i as u8] },
+                        None => payload,
+                    };
+                    let parents = parents.iter().map(|p| p.get(&events).hash).collect();
+                    let coords = SpacetimeCoord { t: (i as u128 + 1) * 1_000_000_000, x, y: 0.0, z: 0.0 };
+                    events.push(Event::with_id(Uuid::from_u128(i as u128 + 1), parents, coords, Uncertainty::EXACT, payload));
+                }
+                events.split_off(1)
+            })
+        }
+
+        /// The events shuffled and cut into gossip batches, with one batch delivered twice.
+        fn delivery(events: Vec<Event>) -> impl Strategy<Value = Vec<Vec<Event>>> {
+            let n = events.len();
+            (Just(events).prop_shuffle(), prop::collection::vec(1..5usize, n), any::<Index>()).prop_map(|(events, sizes, again)| {
+                let mut rest = events.as_slice();
+                let mut batches = Vec::new();
+                for size in sizes {
+                    if rest.is_empty() {
+                        break;
+                    }
+                    let (batch, tail) = rest.split_at(size.min(rest.len()));
+                    batches.push(batch.to_vec());
+                    rest = tail;
+                }
+                let again = again.get(&batches).clone();
+                batches.push(again);
+                batches
+            })
+        }
+
+        fn replay(batches: Vec<Vec<Event>>) -> Replica<u8> {
+            let mut replica = Replica::new(0, Vec::new());
+            for batch in batches {
+                // Requests for missing parents are dropped: a later batch carries them anyway.
+                let _ = replica.handle(1, ProtocolMessage::Gossip(batch));
+            }
+            replica
+        }
+
+        fn events(dag: &SpacetimeDAG) -> BTreeSet<EventHash> {
+            dag.index_map.iter().map(|e| *e.key()).collect()
+        }
+
+        fn heads(dag: &SpacetimeDAG) -> BTreeSet<EventHash> {
+            dag.heads.iter().copied().collect()
+        }
+
+        fn entries(state: &State) -> Vec<(String, crate::state::Entry)> {
+            state.scan("").map(|(k, e)| (k.to_string(), e.clone())).collect()
+        }
+
+        proptest! {
+            #[test]
+            fn delivery_order_does_not_matter(
+                (history, first, second) in history().prop_flat_map(|h| (Just(h.clone()), delivery(h.clone()), delivery(h)))
+            ) {
+                // The histories are causal at the physical c; other tests slow light down.
+                let _c = SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+                set_speed_of_light(DEFAULT_C);
+
+                let mut reference = SpacetimeDAG::new();
+                for event in &history {
+                    reference.add_event(event.clone()).unwrap();
+                }
+
+                for replica in [replay(first), replay(second)] {
+                    prop_assert_eq!(replica.orphans(), 0);
+                    prop_assert_eq!(events(&replica.dag), events(&reference));
+                    prop_assert_eq!(heads(&replica.dag), heads(&reference));
+                    prop_assert_eq!(entries(replica.dag.state()), entries(reference.state()));
+                    // The incrementally maintained state must also match a fold from scratch.
+                    prop_assert_eq!(entries(replica.dag.state()), entries(&State::from_dag(&replica.dag)));
+                }
+            }
+        }
+    }
+}
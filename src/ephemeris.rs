@@ -0,0 +1,176 @@
+//! Tabular ephemeris import.
+//!
+//! To test against realistic mission geometry, node positions can be loaded from a simple CSV
+//! ephemeris: one row per sample, `body,t,x,y,z`, with `t` in seconds from the ephemeris epoch and
+//! positions in meters (heliocentric frame). Lines starting with `#` and an optional `body,...`
+//! header are ignored. Each body becomes a sampled [`Trajectory`], linearly interpolated between
+//! rows, that can be attached to nodes of the in-process [`Cluster`](crate::simulation::Cluster) or
+//! the QUIC [`Network`](crate::network::Network).
+//!
+//! A sample covering one Earth–Mars synodic period (Earth, Moon and Mars on idealized circular
+//! orbits) is bundled; see [`Ephemeris::sample`].
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::spacetime::SpacetimeCoord;
+use crate::trajectory::{InvalidSamples, Samples, Trajectory};
+
+/// The bundled Earth/Moon/Mars sample, in the format accepted by [`Ephemeris::parse`].
+const SAMPLE: &str = include_str!("../data/ephemeris/earth_moon_mars.csv");
+
+/// Day of the sample's first opposition (Earth between the Sun and Mars).
+pub const SAMPLE_OPPOSITION_DAY: u32 = 0;
+/// Approximate day of the sample's solar conjunction (Sun between Earth and Mars).
+pub const SAMPLE_CONJUNCTION_DAY: u32 = 390;
+
+#[derive(Debug, Error)]
+pub enum EphemerisError {
+    #[error("failed to read ephemeris: {0}")]
+    Io(#[from] std::io::Error),
+    /// A row could not be parsed.
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+    /// The requested body has no samples.
+    #[error("unknown body: {0}")]
+    UnknownBody(String),
+    /// A body's samples do not form a trajectory.
+    #[error("body {body}: {source}")]
+    InvalidSamples { body: String, source: InvalidSamples },
+}
+
+/// Per-body trajectories loaded from a tabular ephemeris.
+#[derive(Debug, Clone, Default)]
+pub struct Ephemeris {
+    bodies: BTreeMap<String, Trajectory>,
+}
+
+impl Ephemeris {
+    /// Parses ephemeris rows from `text`.
+    pub fn parse(text: &str) -> Result<Self, EphemerisError> {
+        let mut samples: BTreeMap<String, Vec<SpacetimeCoord>> = BTreeMap::new();
+
+        for (idx, raw) in text.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("body,") {
+                continue;
+            }
+            let err = |message: &str| EphemerisError::Parse {
+                line: idx + 1,
+                message: message.to_string(),
+            };
+
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [body, t, x, y, z] = fields[..] else {
+                return Err(err("expected 5 columns: body,t,x,y,z"));
+            };
+            let num = |field: &str, name: &str| {
+                field
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|v| v.is_finite())
+                    .ok_or_else(|| err(&format!("invalid {name}: {field:?}")))
+            };
+
+            let t = num(t, "t")?;
+            if t < 0.0 {
+                return Err(err("t must not be negative"));
+            }
+            samples.entry(body.to_string()).or_default().push(SpacetimeCoord {
+                t: (t * 1e9).round() as u128,
+                x: num(x, "x")?,
+                y: num(y, "y")?,
+                z: num(z, "z")?,
+            });
+        }
+
+        let bodies = samples
+            .into_iter()
+            .map(|(body, samples)| match Samples::try_from(samples) {
+                Ok(samples) => Ok((body, Trajectory::Sampled(samples))),
+                Err(source) => Err(EphemerisError::InvalidSamples { body, source }),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { bodies })
+    }
+
+    /// Reads and parses an ephemeris file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, EphemerisError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// The bundled Earth/Moon/Mars sample over one synodic period (~780 days, sampled daily).
+    ///
+    /// Opposition falls on [`SAMPLE_OPPOSITION_DAY`] and solar conjunction near
+    /// [`SAMPLE_CONJUNCTION_DAY`].
+    pub fn sample() -> Self {
+        Self::parse(SAMPLE).expect("bundled ephemeris is well-formed")
+    }
+
+    /// Shifts every sample so the ephemeris epoch falls at coordinate time `epoch_ns`.
+    ///
+    /// Useful for replaying an ephemeris against wall-clock coordinate time.
+    pub fn with_epoch(mut self, epoch_ns: u128) -> Self {
+        for trajectory in self.bodies.values_mut() {
+            if let Trajectory::Sampled(samples) = trajectory {
+                for sample in &mut samples.0 {
+                    sample.t = sample.t.saturating_add(epoch_ns);
+                }
+            }
+        }
+        self
+    }
+
+    /// The trajectory of `body`.
+    pub fn trajectory(&self, body: &str) -> Result<&Trajectory, EphemerisError> {
+        self.bodies
+            .get(body)
+            .ok_or_else(|| EphemerisError::UnknownBody(body.to_string()))
+    }
+
+    /// Names of all bodies, in sorted order.
+    pub fn bodies(&self) -> impl Iterator<Item = &str> {
+        self.bodies.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trajectory::distance;
+
+    const DAY_NS: u128 = 86_400 * 1_000_000_000;
+
+    #[test]
+    fn sample_spans_opposition_and_conjunction() {
+        let eph = Ephemeris::sample();
+        assert_eq!(eph.bodies().collect::<Vec<_>>(), ["Earth", "Mars", "Moon"]);
+
+        let earth = eph.trajectory("Earth").unwrap();
+        let mars = eph.trajectory("Mars").unwrap();
+        let separation = |day: u32| {
+            let t = day as u128 * DAY_NS;
+            distance(&earth.position_at(t), &mars.position_at(t))
+        };
+
+        // ~0.52 AU at opposition, ~2.52 AU at conjunction.
+        assert!((separation(SAMPLE_OPPOSITION_DAY) / 7.8e10 - 1.0).abs() < 0.01);
+        assert!((separation(SAMPLE_CONJUNCTION_DAY) / 3.77e11 - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn malformed_rows_report_line_numbers() {
+        let err = Ephemeris::parse("# header\nEarth,0,1,2\n").unwrap_err();
+        assert!(matches!(err, EphemerisError::Parse { line: 2, .. }));
+        assert!(matches!(
+            Ephemeris::parse("Earth,0,1,2,3\n").unwrap().trajectory("Pluto"),
+            Err(EphemerisError::UnknownBody(_))
+        ));
+        for bad in ["nan", "inf", "-inf", "1e999"] {
+            let err = Ephemeris::parse(&format!("Earth,0,1,2,3\nMars,0,1,{bad},3\n")).unwrap_err();
+            assert!(matches!(err, EphemerisError::Parse { line: 2, .. }), "{bad}: {err}");
+        }
+    }
+}
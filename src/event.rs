@@ -1,121 +1,344 @@
-use std::cmp::Ordering;
-use std::collections::BTreeSet;
-
-use blake3::Hasher;
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
-
-use crate::spacetime::SpacetimeCoord;
-
-/// Content-addressed identifier for an [`Event`].
-///
-/// In Minkowski-KV, events are immutable and replicated by gossip. Replicas use `EventHash` to:
-/// - deduplicate received events (same hash => same content),
-/// - connect parent links when building the causal DAG.
-///
-/// Implementation detail: this is currently a 32-byte BLAKE3 digest.
-pub type EventHash = [u8; 32];
-
-/// The semantic operation carried by an [`Event`].
-///
-/// This is the “what happened” component. The “when/where” (spacetime coordinates) and the causal
-/// structure (parents) live on the surrounding [`Event`].
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum Operation {
-    /// Insert or overwrite a key with a value.
-    Put(String, Vec<u8>),
-    /// Remove a key.
-    Delete(String),
-    /// Placeholder for application-defined conflict resolution / join semantics.
-    Merge,
-    /// The root operation anchoring the DAG.
-    Genesis,
-}
-
-/// Immutable, content-addressed database event.
-///
-/// Think of an `Event` as a CRDT “block”: once created, it is never mutated. Replicas exchange
-/// events and rebuild a local view of the database by replaying/merging them.
-///
-/// Causality and the DAG:
-/// - `parents` contains the hashes of the events this event *directly depends on*.
-/// - These parent links form a directed acyclic graph (DAG) of causality.
-/// - Concurrency is explicit: if two replicas emit events with the same parent set (or otherwise
-///   without knowledge of each other), the DAG forks and both can remain as heads.
-///
-/// Hashing and deduplication:
-/// - `hash` is derived from the event content (id, parents, coords, payload).
-/// - On the wire, we only need enough information to recompute/identify events; receivers can use
-///   the hash as a stable key for storage and dedup.
-///
-/// Note: `coords` provide the spacetime embedding used by the simulation to enforce a light-cone
-/// arrival constraint. They do not, by themselves, impose a total order.
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Event {
-    /// Random event identifier (used as part of the content hash).
-    pub id: Uuid,
-    /// Hashes of direct causal predecessors.
-    pub parents: BTreeSet<EventHash>,
-    /// Spacetime coordinate of where/when this event was authored (in the chosen frame).
-    pub coords: SpacetimeCoord,
-    /// Application-level operation payload.
-    pub payload: Operation,
-    /// Content address of this event.
-    ///
-    /// This is skipped during serde serialization and recomputed on creation.
-    #[serde(skip)]
-    pub hash: EventHash,
-}
-
-impl Event {
-    /// Constructs a new immutable event and computes its content hash.
-    pub fn new(parents: BTreeSet<EventHash>, coords: SpacetimeCoord, payload: Operation) -> Self {
-        let id = Uuid::new_v4();
-        let hash = Self::compute_hash(&id, &parents, &coords, &payload);
-
-        Self {
-            id,
-            parents,
-            coords,
-            payload,
-            hash,
-        }
-    }
-
-    fn compute_hash(
-        id: &Uuid,
-        parents: &BTreeSet<EventHash>,
-        coords: &SpacetimeCoord,
-        payload: &Operation,
-    ) -> EventHash {
-        let mut hasher = Hasher::new();
-
-        hasher.update(id.as_bytes());
-
-        for parent in parents {
-            hasher.update(parent);
-        }
-
-        hasher.update(&coords.t.to_le_bytes());
-        hasher.update(&coords.x.to_le_bytes());
-        hasher.update(&coords.y.to_le_bytes());
-        hasher.update(&coords.z.to_le_bytes());
-
-        let payload_bytes = bincode::serialize(payload).expect("Failed to serialize payload for hashing");
-        hasher.update(&payload_bytes);
-
-        *hasher.finalize().as_bytes()
-    }
-}
-
-impl PartialEq for Event {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
-    }
-}
-
-impl PartialOrd for Event {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.coords.partial_cmp(&other.coords)
-    }
-}
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use blake3::Hasher;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::crdt::CrdtOp;
+use crate::spacetime::{SpacetimeCoord, UncertainCoord, Uncertainty};
+
+/// Content-addressed identifier for an [`Event`].
+///
+/// In Minkowski-KV, events are immutable and replicated by gossip. Replicas use `EventHash` to:
+/// - deduplicate received events (same hash => same content),
+/// - connect parent links when building the causal DAG.
+///
+/// Implementation detail: this is currently a 32-byte BLAKE3 digest.
+pub type EventHash = [u8; 32];
+
+/// Most parents a decoded event may list.
+///
+/// Local writes take the heads as parents, so this also bounds how many concurrent branches a
+/// replica merges in one write; any remaining heads are merged by the next.
+pub const MAX_PARENTS: usize = 256;
+
+/// Largest encoded [`Operation`] a decoded event may carry, in bytes.
+pub const MAX_PAYLOAD_BYTES: u64 = 16 * 1024;
+
+/// Deepest nesting of [`Operation::Batch`]es a decoded event may carry (a plain batch is 1).
+///
+/// Checked while decoding, since a deeply nested batch would overflow the stack before any
+/// validation of the finished event could run.
+pub const MAX_NESTING: usize = 8;
+
+/// Largest clock error bound an event may claim, in nanoseconds (one day).
+///
+/// The bound widens the light-cone check and the delivery gate, so an unbounded one would let a
+/// peer wave any parent through or hold messages forever.
+pub const MAX_CLOCK_ERROR_NS: u128 = 86_400 * 1_000_000_000;
+
+/// A decoded event that exceeds one of the decode limits.
+#[derive(Debug, Error)]
+pub enum LimitError {
+    #[error("event lists {0} parents, more than {MAX_PARENTS}")]
+    TooManyParents(usize),
+    #[error("event payload of {0} bytes exceeds {MAX_PAYLOAD_BYTES}")]
+    PayloadTooLarge(u64),
+    #[error("event payload nests batches {0} deep, more than {MAX_NESTING}")]
+    NestedTooDeep(usize),
+    #[error("event coordinates are not finite")]
+    NonFiniteCoordinates,
+    #[error("event position error {0} is not a finite, non-negative distance")]
+    InvalidRadius(f64),
+    #[error("event clock error of {0} ns exceeds {MAX_CLOCK_ERROR_NS}")]
+    ClockErrorTooLarge(u128),
+}
+
+/// The semantic operation carried by an [`Event`].
+///
+/// This is the “what happened” component. The “when/where” (spacetime coordinates) and the causal
+/// structure (parents) live on the surrounding [`Event`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Operation {
+    /// Insert or overwrite a key with a value.
+    Put(String, Vec<u8>),
+    /// Remove a key.
+    Delete(String),
+    /// Append a value to the list stored under a key.
+    ///
+    /// Spacelike-concurrent appends are all kept; they are ordered by the materializer's
+    /// deterministic linearization of the DAG.
+    Append(String, Vec<u8>),
+    /// Add a signed delta to the counter stored under a key.
+    ///
+    /// Increments commute, so concurrent increments from different replicas all take effect.
+    Increment(String, i64),
+    /// Causal compare-and-set: write `value` only if the version of `key` visible in this event's
+    /// causal past is `expected` (`None` meaning the key is absent there).
+    ///
+    /// The condition is evaluated against the event's *parents*, not against whatever the
+    /// receiving replica has materialized, so every replica reaches the same verdict.
+    CompareAndSet {
+        key: String,
+        expected: Option<EventHash>,
+        value: Vec<u8>,
+    },
+    /// Apply several operations atomically: either every operation takes effect, or (if any
+    /// compare-and-set fails) none of them do.
+    Batch(#[serde(deserialize_with = "nested")] Vec<Operation>),
+    /// Update the CRDT (counter, set, map or text sequence) stored under a key.
+    Crdt(String, CrdtOp),
+    /// Placeholder for application-defined conflict resolution / join semantics.
+    Merge,
+    /// The root operation anchoring the DAG.
+    Genesis,
+}
+
+/// Immutable, content-addressed database event.
+///
+/// Think of an `Event` as a CRDT “block”: once created, it is never mutated. Replicas exchange
+/// events and rebuild a local view of the database by replaying/merging them.
+///
+/// Causality and the DAG:
+/// - `parents` contains the hashes of the events this event *directly depends on*.
+/// - These parent links form a directed acyclic graph (DAG) of causality.
+/// - Concurrency is explicit: if two replicas emit events with the same parent set (or otherwise
+///   without knowledge of each other), the DAG forks and both can remain as heads.
+///
+/// Hashing and deduplication:
+/// - `hash` is derived from the event content (id, parents, coords, uncertainty, payload).
+/// - On the wire, we only need enough information to recompute/identify events; receivers can use
+///   the hash as a stable key for storage and dedup.
+///
+/// Note: `coords` provide the spacetime embedding used by the simulation to enforce a light-cone
+/// arrival constraint. They do not, by themselves, impose a total order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "WireEvent")]
+pub struct Event {
+    /// Random event identifier (used as part of the content hash).
+    pub id: Uuid,
+    /// Hashes of direct causal predecessors.
+    pub parents: BTreeSet<EventHash>,
+    /// Spacetime coordinate of where/when this event was authored (in the chosen frame).
+    pub coords: SpacetimeCoord,
+    /// Error bounds on `coords`, as known to the author.
+    pub uncertainty: Uncertainty,
+    /// Application-level operation payload.
+    pub payload: Operation,
+    /// Content address of this event.
+    ///
+    /// This is skipped during serde serialization and recomputed on creation and on decode.
+    #[serde(skip)]
+    pub hash: EventHash,
+}
+
+impl Event {
+    /// Constructs a new immutable event and computes its content hash.
+    pub fn new(parents: BTreeSet<EventHash>, coords: SpacetimeCoord, payload: Operation) -> Self {
+        Self::with_uncertainty(parents, coords, Uncertainty::EXACT, payload)
+    }
+
+    /// Constructs an event whose author only knows its coordinates within `uncertainty`.
+    pub fn with_uncertainty(
+        parents: BTreeSet<EventHash>,
+        coords: SpacetimeCoord,
+        uncertainty: Uncertainty,
+        payload: Operation,
+    ) -> Self {
+        Self::with_id(Uuid::new_v4(), parents, coords, uncertainty, payload)
+    }
+
+    /// Constructs an event with a caller-chosen `id`, so a seeded simulation can reproduce it.
+    pub fn with_id(
+        id: Uuid,
+        parents: BTreeSet<EventHash>,
+        coords: SpacetimeCoord,
+        uncertainty: Uncertainty,
+        payload: Operation,
+    ) -> Self {
+        let hash = Self::compute_hash(&id, &parents, &coords, &uncertainty, &payload);
+
+        Self {
+            id,
+            parents,
+            coords,
+            uncertainty,
+            payload,
+            hash,
+        }
+    }
+
+    /// The genesis event shared by every replica: fixed id, origin, no parents.
+    ///
+    /// It must be identical everywhere, or replicas would root their DAGs in different events and
+    /// never converge on the same heads.
+    pub fn genesis() -> Self {
+        let id = Uuid::nil();
+        let parents = BTreeSet::new();
+        let coords = SpacetimeCoord { t: 0, x: 0.0, y: 0.0, z: 0.0 };
+        let payload = Operation::Genesis;
+        let hash = Self::compute_hash(&id, &parents, &coords, &Uncertainty::EXACT, &payload);
+        Self {
+            id,
+            parents,
+            coords,
+            uncertainty: Uncertainty::EXACT,
+            payload,
+            hash,
+        }
+    }
+
+    /// Checks the event against the decode limits, so a local write is refused up front rather than
+    /// by every peer it is gossiped to.
+    pub fn check_limits(&self) -> Result<(), LimitError> {
+        check_limits(&self.parents, &self.coords, &self.uncertainty, &self.payload)?;
+        let depth = self.payload.nesting();
+        if depth > MAX_NESTING {
+            return Err(LimitError::NestedTooDeep(depth));
+        }
+        Ok(())
+    }
+
+    /// The event's coordinates together with their error bounds.
+    pub fn uncertain_coords(&self) -> UncertainCoord {
+        UncertainCoord {
+            coord: self.coords,
+            uncertainty: self.uncertainty,
+        }
+    }
+
+    fn compute_hash(
+        id: &Uuid,
+        parents: &BTreeSet<EventHash>,
+        coords: &SpacetimeCoord,
+        uncertainty: &Uncertainty,
+        payload: &Operation,
+    ) -> EventHash {
+        let mut hasher = Hasher::new();
+
+        hasher.update(id.as_bytes());
+
+        for parent in parents {
+            hasher.update(parent);
+        }
+
+        hasher.update(&coords.t.to_le_bytes());
+        hasher.update(&coords.x.to_le_bytes());
+        hasher.update(&coords.y.to_le_bytes());
+        hasher.update(&coords.z.to_le_bytes());
+        hasher.update(&uncertainty.time_ns.to_le_bytes());
+        hasher.update(&uncertainty.radius.to_le_bytes());
+
+        let payload_bytes = bincode::serialize(payload).expect("Failed to serialize payload for hashing");
+        hasher.update(&payload_bytes);
+
+        *hasher.finalize().as_bytes()
+    }
+}
+
+/// The serialized fields of an [`Event`]; decoding goes through here so the hash is recomputed
+/// and the decode limits are enforced.
+#[derive(Deserialize)]
+struct WireEvent {
+    id: Uuid,
+    parents: BTreeSet<EventHash>,
+    coords: SpacetimeCoord,
+    uncertainty: Uncertainty,
+    payload: Operation,
+}
+
+impl TryFrom<WireEvent> for Event {
+    type Error = LimitError;
+
+    fn try_from(wire: WireEvent) -> Result<Self, LimitError> {
+        // Nesting was already bounded while the payload decoded.
+        check_limits(&wire.parents, &wire.coords, &wire.uncertainty, &wire.payload)?;
+        let hash = Event::compute_hash(&wire.id, &wire.parents, &wire.coords, &wire.uncertainty, &wire.payload);
+        Ok(Self {
+            id: wire.id,
+            parents: wire.parents,
+            coords: wire.coords,
+            uncertainty: wire.uncertainty,
+            payload: wire.payload,
+            hash,
+        })
+    }
+}
+
+/// The limits shared by decoding and [`Event::check_limits`]: parent count, payload size, and
+/// coordinates and error bounds the light-cone check can reason about.
+fn check_limits(
+    parents: &BTreeSet<EventHash>,
+    coords: &SpacetimeCoord,
+    uncertainty: &Uncertainty,
+    payload: &Operation,
+) -> Result<(), LimitError> {
+    if parents.len() > MAX_PARENTS {
+        return Err(LimitError::TooManyParents(parents.len()));
+    }
+    if ![coords.x, coords.y, coords.z].iter().all(|v| v.is_finite()) {
+        return Err(LimitError::NonFiniteCoordinates);
+    }
+    if !(uncertainty.radius.is_finite() && uncertainty.radius >= 0.0) {
+        return Err(LimitError::InvalidRadius(uncertainty.radius));
+    }
+    if uncertainty.time_ns > MAX_CLOCK_ERROR_NS {
+        return Err(LimitError::ClockErrorTooLarge(uncertainty.time_ns));
+    }
+    let payload_bytes = bincode::serialized_size(payload).unwrap_or(u64::MAX);
+    if payload_bytes > MAX_PAYLOAD_BYTES {
+        return Err(LimitError::PayloadTooLarge(payload_bytes));
+    }
+    Ok(())
+}
+
+impl Operation {
+    /// How deeply batches nest in this operation (0 for anything but a batch).
+    fn nesting(&self) -> usize {
+        match self {
+            Operation::Batch(ops) => 1 + ops.iter().map(Operation::nesting).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+}
+
+thread_local! {
+    /// How many batches are open in the operation currently being decoded on this thread.
+    static BATCH_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Decodes the operations of a batch, refusing to nest deeper than [`MAX_NESTING`].
+fn nested<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Operation>, D::Error> {
+    /// Closes the batch again however decoding of its contents ends.
+    struct Open;
+    impl Drop for Open {
+        fn drop(&mut self) {
+            BATCH_DEPTH.with(|depth| depth.set(depth.get() - 1));
+        }
+    }
+
+    let depth = BATCH_DEPTH.with(|depth| {
+        depth.set(depth.get() + 1);
+        depth.get()
+    });
+    let _open = Open;
+    if depth > MAX_NESTING {
+        return Err(de::Error::custom(format!("batches nested deeper than {MAX_NESTING}")));
+    }
+    Vec::deserialize(deserializer)
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.coords.partial_cmp(&other.coords)
+    }
+}
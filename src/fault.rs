@@ -0,0 +1,378 @@
+//! Network fault injection.
+//!
+//! A [`FaultInjector`] decides, per link and from a seeded [`SimRng`], what happens to each message
+//! on its way: it may be lost, duplicated, delayed past later traffic, corrupted (and discarded by
+//! the receiver's integrity check), or wait its turn behind earlier messages on a bandwidth-limited
+//! link. The in-process
+//! [`Cluster`](crate::simulation::Cluster) and the [`Simulator`](crate::simulator::Simulator) apply
+//! it hop by hop; [`FaultyTransport`] puts the same faults in front of a real transport.
+//!
+//! Whole groups of nodes can also be cut off from the rest for a while with a [`Partition`].
+//!
+//! The replica protocol is expected to converge through all of it: orphans are buffered and their
+//! parents pulled, and anti-entropy digests repair whatever was lost.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use futures::future::BoxFuture;
+use tokio::sync::mpsc;
+
+use crate::protocol::ProtocolMessage;
+use crate::simulator::SimRng;
+use crate::spacetime::now_ns;
+use crate::transport::{Inbound, Transport};
+
+/// Faults on one link. Probabilities are per message and per hop; the default is a perfect link.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LinkFaults {
+    /// Probability that a message is lost.
+    pub loss: f64,
+    /// Probability that a message is delivered twice.
+    pub duplicate: f64,
+    /// Probability that a copy is held back by up to `reorder_window_ns`, so later traffic can
+    /// overtake it.
+    pub reorder: f64,
+    pub reorder_window_ns: u128,
+    /// Probability that a copy is corrupted in transit. The links modelled here (QUIC, BPv7 with
+    /// CRC32C) detect corruption, so the receiver discards the copy.
+    pub corrupt: f64,
+    /// Link rate in bytes/s; messages queue behind each other. `None` is unlimited, and a rate
+    /// that is not positive carries nothing, so every message is lost.
+    pub bandwidth: Option<f64>,
+}
+
+/// What happens to traffic that would cross a partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PartitionPolicy {
+    /// The message is lost.
+    #[default]
+    Drop,
+    /// The message waits at the cut and is sent once the partition heals.
+    Hold,
+}
+
+/// A network cut: during `during` (coordinate time, ns), no message crosses between `isolated` and
+/// the rest of the nodes.
+#[derive(Debug, Clone)]
+pub struct Partition<Id> {
+    pub isolated: HashSet<Id>,
+    pub during: Range<u128>,
+    pub policy: PartitionPolicy,
+}
+
+impl<Id: Copy + Eq + Hash> Partition<Id> {
+    /// Cuts `isolated` off from everyone else during `during`, dropping traffic across the cut.
+    pub fn isolate(isolated: impl IntoIterator<Item = Id>, during: Range<u128>) -> Self {
+        Self {
+            isolated: isolated.into_iter().collect(),
+            during,
+            policy: PartitionPolicy::Drop,
+        }
+    }
+
+    /// Whether a message sent from `from` to `to` at `t` would cross the cut.
+    pub fn separates(&self, from: Id, to: Id, t: u128) -> bool {
+        self.during.contains(&t) && self.isolated.contains(&from) != self.isolated.contains(&to)
+    }
+}
+
+/// How often each fault has fired.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    pub lost: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    /// Copies that failed the link's integrity check and were discarded by the receiver.
+    pub corrupted: u64,
+    /// Messages dropped at a partition.
+    pub partitioned: u64,
+    /// Messages held at a partition until it healed.
+    pub held: u64,
+}
+
+/// Per-link fault configuration plus the state (randomness, link occupancy) needed to apply it.
+pub struct FaultInjector<Id> {
+    /// Faults on links without an entry of their own.
+    pub default: LinkFaults,
+    links: HashMap<(Id, Id), LinkFaults>,
+    /// Scheduled cuts, in addition to the per-link faults.
+    pub partitions: Vec<Partition<Id>>,
+    /// When each directed link finishes transmitting what it has already accepted.
+    busy_until: HashMap<(Id, Id), u128>,
+    rng: SimRng,
+    pub stats: FaultStats,
+}
+
+impl<Id: Copy + Eq + Hash + Ord> FaultInjector<Id> {
+    /// Perfect links until configured otherwise, with every fault decision drawn from `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            default: LinkFaults::default(),
+            links: HashMap::new(),
+            partitions: Vec::new(),
+            busy_until: HashMap::new(),
+            rng: SimRng::new(seed),
+            stats: FaultStats::default(),
+        }
+    }
+
+    fn key(a: Id, b: Id) -> (Id, Id) {
+        if a <= b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    /// Sets the faults on the link between `a` and `b`, in both directions.
+    pub fn set_link(&mut self, a: Id, b: Id, faults: LinkFaults) {
+        self.links.insert(Self::key(a, b), faults);
+    }
+
+    /// Reverts the link between `a` and `b` to the default faults.
+    pub fn clear_link(&mut self, a: Id, b: Id) {
+        self.links.remove(&Self::key(a, b));
+    }
+
+    pub fn faults(&self, a: Id, b: Id) -> &LinkFaults {
+        self.links.get(&Self::key(a, b)).unwrap_or(&self.default)
+    }
+
+    /// Sends `msg` over the single link `from` → `to` at `t_send`, with propagation delay
+    /// `light_ns`. Returns every copy that reaches `to`, with its arrival time.
+    pub fn transmit(&mut self, from: Id, to: Id, t_send: u128, light_ns: u128, msg: ProtocolMessage) -> Vec<(u128, ProtocolMessage)> {
+        let faults = *self.faults(from, to);
+
+        // Held traffic may run straight into a later partition, so keep checking until none applies.
+        let mut t_send = t_send;
+        while let Some(cut) = self.partitions.iter().find(|p| p.separates(from, to, t_send)) {
+            match cut.policy {
+                PartitionPolicy::Drop => {
+                    self.stats.partitioned += 1;
+                    return Vec::new();
+                }
+                PartitionPolicy::Hold => {
+                    self.stats.held += 1;
+                    t_send = cut.during.end;
+                }
+            }
+        }
+
+        // A lost message has still occupied the link while it was being sent.
+        let mut departure = t_send;
+        if let Some(rate) = faults.bandwidth {
+            if rate.is_nan() || rate <= 0.0 {
+                self.stats.lost += 1;
+                return Vec::new();
+            }
+            let busy = self.busy_until.entry((from, to)).or_insert(0);
+            let start = t_send.max(*busy);
+            // Saturates for a rate so low the message would take longer than time can represent.
+            departure = start.saturating_add((msg.wire_size() as f64 / rate * 1e9).round() as u128);
+            *busy = departure;
+        }
+
+        if self.rng.next_f64() < faults.loss {
+            self.stats.lost += 1;
+            return Vec::new();
+        }
+        let copies = if self.rng.next_f64() < faults.duplicate {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+
+        let mut delivered = Vec::with_capacity(copies);
+        for _ in 0..copies {
+            let mut arrival = departure.saturating_add(light_ns);
+            if self.rng.next_f64() < faults.reorder {
+                self.stats.reordered += 1;
+                arrival = arrival.saturating_add((self.rng.next_f64() * faults.reorder_window_ns as f64) as u128);
+            }
+            // A corrupted copy fails the frame's integrity check and never reaches the replica;
+            // delivering it would let a flipped bit pass as an event nobody wrote.
+            if self.rng.next_f64() < faults.corrupt {
+                self.stats.corrupted += 1;
+                continue;
+            }
+            delivered.push((arrival, msg.clone()));
+        }
+        delivered
+    }
+}
+
+/// Wraps a real [`Transport`] with the faults of a [`FaultInjector`], for exercising replicas
+/// over flaky links on an actual network stack.
+///
+/// A lost message is reported as a failed send, as a missing custody acknowledgement would be;
+/// delayed and duplicated copies are handed to the inner transport once their time has come.
+pub struct FaultyTransport<T: Transport> {
+    inner: T,
+    local: T::Peer,
+    faults: Mutex<FaultInjector<T::Peer>>,
+}
+
+impl<T: Transport> FaultyTransport<T>
+where
+    T::Peer: Ord,
+{
+    /// Wraps `inner`, whose own address is `local`.
+    pub fn new(inner: T, local: T::Peer, faults: FaultInjector<T::Peer>) -> Self {
+        Self {
+            inner,
+            local,
+            faults: Mutex::new(faults),
+        }
+    }
+
+    pub fn stats(&self) -> FaultStats {
+        self.faults.lock().expect("fault injector poisoned").stats
+    }
+}
+
+impl<T: Transport> Transport for FaultyTransport<T>
+where
+    T::Peer: Ord,
+{
+    type Peer = T::Peer;
+
+    fn send(&self, peer: T::Peer, msg: ProtocolMessage) -> BoxFuture<'_, Result<()>> {
+        let now = now_ns();
+        let copies = self.faults.lock().expect("fault injector poisoned").transmit(self.local, peer, now, 0, msg);
+        Box::pin(async move {
+            if copies.is_empty() {
+                bail!("message to {peer} lost by fault injection");
+            }
+            let mut result = Ok(());
+            for (at, copy) in copies {
+                let wait = at.saturating_sub(now_ns()).min(u64::MAX as u128) as u64;
+                tokio::time::sleep(Duration::from_nanos(wait)).await;
+                result = result.and(self.inner.send(peer, copy).await);
+            }
+            result
+        })
+    }
+
+    fn serve(&self, inbound: mpsc::Sender<Inbound<T::Peer>>) -> BoxFuture<'_, Result<()>> {
+        self.inner.serve(inbound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest() -> ProtocolMessage {
+        ProtocolMessage::Digest { heads: vec![[7; 32]] }
+    }
+
+    #[test]
+    fn faults_are_reproducible_from_the_seed() {
+        let faults = LinkFaults { loss: 0.3, duplicate: 0.3, reorder: 0.3, reorder_window_ns: 1_000, corrupt: 0.3, ..LinkFaults::default() };
+        let run = |seed| {
+            let mut injector = FaultInjector::new(seed);
+            injector.set_link(1u8, 2, faults);
+            let arrivals: Vec<u128> = (0..200).flat_map(|t| injector.transmit(1, 2, t, 10, digest())).map(|(at, _)| at).collect();
+            (arrivals, injector.stats)
+        };
+
+        let (arrivals, stats) = run(1);
+        assert_eq!((arrivals.clone(), stats), run(1));
+        assert!(stats.lost > 30 && stats.duplicated > 30 && stats.reordered > 30 && stats.corrupted > 30, "{stats:?}");
+        assert!(arrivals.windows(2).any(|w| w[1] < w[0]), "some copies overtake later traffic");
+
+        // Links without an entry of their own are perfect.
+        assert_eq!(FaultInjector::<u8>::new(1).transmit(2, 3, 5, 10, digest()).len(), 1);
+    }
+
+    #[test]
+    fn partitions_drop_or_hold_traffic_across_the_cut_until_they_heal() {
+        let mut injector = FaultInjector::new(0);
+        injector.partitions.push(Partition::isolate(["earth", "moon"], 10..30));
+        injector.partitions.push(Partition { policy: PartitionPolicy::Hold, ..Partition::isolate(["venus"], 40..50) });
+
+        assert!(injector.transmit("earth", "mars", 10, 5, digest()).is_empty());
+        assert_eq!(injector.transmit("earth", "moon", 10, 5, digest()).len(), 1, "same side of the cut");
+        assert_eq!(injector.transmit("mars", "moon", 30, 5, digest())[0].0, 35, "healed");
+        assert_eq!(injector.transmit("venus", "earth", 45, 5, digest())[0].0, 55, "held until 50");
+        assert_eq!((injector.stats.partitioned, injector.stats.held), (1, 1));
+    }
+
+    #[test]
+    fn bandwidth_queues_messages_behind_each_other() {
+        let mut injector = FaultInjector::new(0);
+        let size = digest().wire_size() as f64;
+        // One message per second.
+        injector.set_link("earth", "mars", LinkFaults { bandwidth: Some(size), ..LinkFaults::default() });
+
+        const S: u128 = 1_000_000_000;
+        let first = injector.transmit("earth", "mars", 0, 5 * S, digest());
+        let second = injector.transmit("earth", "mars", 0, 5 * S, digest());
+        let back = injector.transmit("mars", "earth", 0, 5 * S, digest());
+        assert_eq!(first[0].0, 6 * S);
+        assert_eq!(second[0].0, 7 * S, "waits for the first to finish transmitting");
+        assert_eq!(back[0].0, 6 * S, "each direction has its own capacity");
+    }
+
+    #[test]
+    fn links_without_capacity_lose_everything_instead_of_overflowing() {
+        let mut injector = FaultInjector::new(0);
+        injector.set_link("earth", "mars", LinkFaults { bandwidth: Some(0.0), ..LinkFaults::default() });
+        injector.set_link("earth", "moon", LinkFaults { bandwidth: Some(-1.0), ..LinkFaults::default() });
+        injector.set_link("mars", "moon", LinkFaults { bandwidth: Some(1e-300), ..LinkFaults::default() });
+
+        assert!(injector.transmit("earth", "mars", 0, 5, digest()).is_empty());
+        assert!(injector.transmit("earth", "moon", 0, 5, digest()).is_empty());
+        assert_eq!(injector.stats.lost, 2);
+        assert_eq!(injector.transmit("mars", "moon", 0, 5, digest())[0].0, u128::MAX, "saturates");
+    }
+
+    #[test]
+    fn corrupted_copies_are_discarded() {
+        let mut injector = FaultInjector::new(0);
+        injector.default = LinkFaults { corrupt: 1.0, ..LinkFaults::default() };
+        assert!(injector.transmit(1u8, 2, 0, 5, digest()).is_empty());
+        assert_eq!(injector.stats.corrupted, 1);
+    }
+
+    /// Records what reaches the wire instead of sending it.
+    struct Recorder(Mutex<Vec<ProtocolMessage>>);
+
+    impl Transport for Recorder {
+        type Peer = u8;
+
+        fn send(&self, _peer: u8, msg: ProtocolMessage) -> BoxFuture<'_, Result<()>> {
+            self.0.lock().unwrap().push(msg);
+            Box::pin(async { Ok(()) })
+        }
+
+        fn serve(&self, _inbound: mpsc::Sender<Inbound<u8>>) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn faulty_transport_reports_loss_and_sends_duplicates_twice() {
+        let faulty = |faults| {
+            let mut injector = FaultInjector::new(0);
+            injector.default = faults;
+            FaultyTransport::new(Recorder(Mutex::new(Vec::new())), 1, injector)
+        };
+
+        let lossy = faulty(LinkFaults { loss: 1.0, ..LinkFaults::default() });
+        assert!(lossy.send(2, digest()).await.is_err(), "a lost message is a failed send");
+        assert!(lossy.inner.0.lock().unwrap().is_empty());
+        assert_eq!(lossy.stats().lost, 1);
+
+        let doubled = faulty(LinkFaults { duplicate: 1.0, ..LinkFaults::default() });
+        doubled.send(2, digest()).await.unwrap();
+        assert_eq!(doubled.inner.0.lock().unwrap().len(), 2);
+        assert_eq!(doubled.stats().duplicated, 1);
+    }
+}
@@ -0,0 +1,239 @@
+//! Consistency checking for simulation histories, in the spirit of Jepsen's Elle.
+//!
+//! A [`History`] records every client operation of a run: each write with the event it produced,
+//! and each read with the replica's heads at the time and the value it returned. [`History::check`]
+//! then rebuilds the reference DAG from the recorded writes alone and verifies, per node:
+//!
+//! - **session guarantees**: every operation sees at least the causal past of the node's previous
+//!   operation. Depending on which kinds of operations the offending pair are, that is
+//!   read-your-writes, monotonic reads, writes-follow-reads or monotonic writes; together they make
+//!   each session causally consistent.
+//! - **read values**: a read returns exactly what the DAG semantics give for the events it could
+//!   see ([`SpacetimeDAG::state_at`] of its heads), so conflict resolution is the same everywhere.
+//! - **no phantoms**: nothing is observed that no client wrote (e.g. an event forged by a peer).
+//! - **convergence**: at the end every replica holds the same causal history.
+//!
+//! Each [`Anomaly`] names the smallest set of operations that exhibits it: a consecutive pair from
+//! one session, a single read, or two replicas.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use uuid::Uuid;
+
+use crate::dag::SpacetimeDAG;
+use crate::event::{Event, EventHash};
+use crate::state::Value;
+
+/// Position of an operation in its [`History`].
+pub type OpId = usize;
+
+#[derive(Debug, Clone)]
+pub enum OpKind {
+    Write { event: Event },
+    Read {
+        key: String,
+        /// The replica's heads when it served the read.
+        frontier: Vec<EventHash>,
+        observed: Option<Value>,
+    },
+}
+
+/// One client operation, as executed.
+#[derive(Debug, Clone)]
+pub struct Op {
+    pub id: OpId,
+    pub node: Uuid,
+    /// Coordinate time (ns).
+    pub at: u128,
+    pub kind: OpKind,
+}
+
+/// A violation found by [`History::check`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anomaly {
+    /// A node's operation did not see `missing`, which its previous operation `earlier` had seen
+    /// (or written). `guarantee` names the broken session guarantee.
+    Session { guarantee: &'static str, earlier: OpId, later: OpId, missing: EventHash },
+    /// A read returned something other than what its visible events materialize to.
+    Value { read: OpId, expected: Option<Value>, observed: Option<Value> },
+    /// `node` observed an event that no recorded write produced, at operation `op` or (if `None`)
+    /// in its final state.
+    Phantom { node: Uuid, op: Option<OpId>, event: EventHash },
+    /// Replica `b` ended without `missing`, which replica `a` holds.
+    Divergence { a: Uuid, b: Uuid, missing: EventHash },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let short = |h: &EventHash| h[..4].iter().map(|b| format!("{b:02x}")).collect::<String>();
+        match self {
+            Self::Session { guarantee, earlier, later, missing } => {
+                write!(f, "{guarantee}: op {later} does not see {} from op {earlier}", short(missing))
+            }
+            Self::Value { read, expected, observed } => write!(f, "read {read} returned {observed:?}, expected {expected:?}"),
+            Self::Phantom { node, op: Some(op), event } => write!(f, "op {op} on {node} observed unwritten event {}", short(event)),
+            Self::Phantom { node, op: None, event } => write!(f, "{node} ended with unwritten event {}", short(event)),
+            Self::Divergence { a, b, missing } => write!(f, "{b} never received {} held by {a}", short(missing)),
+        }
+    }
+}
+
+/// The client operations of a run, in execution order.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    pub ops: Vec<Op>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `node` wrote `event` at `at`.
+    pub fn write(&mut self, node: Uuid, at: u128, event: Event) -> OpId {
+        self.push(node, at, OpKind::Write { event })
+    }
+
+    /// Records that `node`, with heads `frontier`, read `observed` under `key` at `at`.
+    pub fn read(&mut self, node: Uuid, at: u128, key: &str, frontier: Vec<EventHash>, observed: Option<Value>) -> OpId {
+        self.push(node, at, OpKind::Read { key: key.to_string(), frontier, observed })
+    }
+
+    fn push(&mut self, node: Uuid, at: u128, kind: OpKind) -> OpId {
+        let id = self.ops.len();
+        self.ops.push(Op { id, node, at, kind });
+        id
+    }
+
+    /// Checks the history against the DAG semantics. `finals` gives each replica's heads at the
+    /// end of the run; pass an empty slice to skip the convergence check.
+    pub fn check(&self, finals: &[(Uuid, Vec<EventHash>)]) -> Vec<Anomaly> {
+        // Writes are recorded after they were applied, so parents always come first.
+        let mut reference = SpacetimeDAG::new();
+        for op in &self.ops {
+            if let OpKind::Write { event } = &op.kind {
+                let _ = reference.add_event(event.clone());
+            }
+        }
+        let known = |h: &EventHash| reference.index_map.contains_key(h);
+        let closure = |frontier: &[EventHash]| {
+            let mut past: HashSet<EventHash> = HashSet::new();
+            for head in frontier {
+                past.insert(*head);
+                past.extend(reference.ancestors(head));
+            }
+            past
+        };
+
+        let mut anomalies = Vec::new();
+        // Per node: the previous operation and the causal past it had seen.
+        let mut sessions: Vec<(Uuid, OpId, HashSet<EventHash>)> = Vec::new();
+
+        for op in &self.ops {
+            let seen = match &op.kind {
+                OpKind::Write { event } => {
+                    // Built on something never written (and so itself missing from the reference).
+                    if let Some(phantom) = event.parents.iter().find(|h| !known(h)) {
+                        anomalies.push(Anomaly::Phantom { node: op.node, op: Some(op.id), event: *phantom });
+                        continue;
+                    }
+                    let mut past = closure(&event.parents.iter().copied().collect::<Vec<_>>());
+                    past.insert(event.hash);
+                    past
+                }
+                OpKind::Read { key, frontier, observed } => {
+                    if let Some(phantom) = frontier.iter().find(|h| !known(h)) {
+                        anomalies.push(Anomaly::Phantom { node: op.node, op: Some(op.id), event: *phantom });
+                        continue;
+                    }
+                    let expected = reference.state_at(frontier).ok().and_then(|s| s.get(key).cloned());
+                    if expected != *observed {
+                        anomalies.push(Anomaly::Value { read: op.id, expected, observed: observed.clone() });
+                    }
+                    closure(frontier)
+                }
+            };
+
+            match sessions.iter_mut().find(|(node, ..)| *node == op.node) {
+                Some((_, earlier, past)) => {
+                    // Report the smallest missing hash, so the counterexample is deterministic.
+                    if let Some(missing) = past.iter().filter(|h| !seen.contains(*h)).min() {
+                        let guarantee = match (&self.ops[*earlier].kind, &op.kind) {
+                            (OpKind::Write { .. }, OpKind::Read { .. }) => "read-your-writes",
+                            (OpKind::Read { .. }, OpKind::Read { .. }) => "monotonic reads",
+                            (OpKind::Read { .. }, OpKind::Write { .. }) => "writes-follow-reads",
+                            (OpKind::Write { .. }, OpKind::Write { .. }) => "monotonic writes",
+                        };
+                        anomalies.push(Anomaly::Session { guarantee, earlier: *earlier, later: op.id, missing: *missing });
+                    }
+                    *earlier = op.id;
+                    *past = seen;
+                }
+                None => sessions.push((op.node, op.id, seen)),
+            }
+        }
+
+        let mut pasts = Vec::new();
+        for (node, heads) in finals {
+            match heads.iter().find(|h| !known(h)) {
+                Some(phantom) => anomalies.push(Anomaly::Phantom { node: *node, op: None, event: *phantom }),
+                None => pasts.push((*node, closure(heads))),
+            }
+        }
+        if let Some(((a, first), rest)) = pasts.split_first() {
+            for (b, past) in rest {
+                let one_way = first.iter().filter(|h| !past.contains(*h)).min().map(|m| (*a, *b, *m));
+                let other_way = || past.iter().filter(|h| !first.contains(*h)).min().map(|m| (*b, *a, *m));
+                if let Some((a, b, missing)) = one_way.or_else(other_way) {
+                    anomalies.push(Anomaly::Divergence { a, b, missing });
+                }
+            }
+        }
+        anomalies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Operation;
+    use crate::spacetime::SpacetimeCoord;
+    use std::collections::BTreeSet;
+
+    fn put(parents: &[&Event], value: &[u8]) -> Event {
+        let parents: BTreeSet<_> = parents.iter().map(|p| p.hash).collect();
+        Event::new(parents, SpacetimeCoord { t: 1, x: 0.0, y: 0.0, z: 0.0 }, Operation::Put("k".into(), value.to_vec()))
+    }
+
+    #[test]
+    fn stale_and_wrong_reads_are_reported_as_minimal_counterexamples() {
+        let (a, b) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let genesis = Event::genesis();
+        let first = put(&[&genesis], b"1");
+        let second = put(&[&first], b"2");
+        let bytes = |v: &[u8]| Some(Value::Bytes(v.to_vec()));
+
+        let mut history = History::new();
+        let w1 = history.write(a, 10, first.clone());
+        // A forgets its own write.
+        let r1 = history.read(a, 20, "k", vec![genesis.hash], None);
+        let w2 = history.write(b, 30, second.clone());
+        // B sees its write but returns the value it replaced, as a broken conflict resolution would.
+        let r2 = history.read(b, 40, "k", vec![second.hash], bytes(b"1"));
+        // Then goes back in time.
+        let r3 = history.read(b, 50, "k", vec![first.hash], bytes(b"1"));
+
+        let anomalies = history.check(&[(a, vec![first.hash]), (b, vec![second.hash])]);
+        assert_eq!(
+            anomalies,
+            [
+                Anomaly::Session { guarantee: "read-your-writes", earlier: w1, later: r1, missing: first.hash },
+                Anomaly::Value { read: r2, expected: bytes(b"2"), observed: bytes(b"1") },
+                Anomaly::Session { guarantee: "monotonic reads", earlier: r2, later: r3, missing: second.hash },
+                Anomaly::Divergence { a: b, b: a, missing: second.hash },
+            ]
+        );
+        assert!(w2 < r2);
+    }
+}
@@ -0,0 +1,56 @@
+//! Lightcone (formerly Minkowski-KV): a relativistic distributed key-value store.
+//!
+//! The library exposes the building blocks used by the demo binary and by the in-process
+//! simulation:
+//! - `spacetime` / `event` / `dag`: the causal data model (coordinates, immutable events, CRDT DAG).
+//! - `state` / `crdt`: the deterministic fold that turns a DAG into key/value state, and the CRDT
+//!   types a key can hold.
+//! - `physics` / `trajectory`: the light-speed gate that buffers messages until they may causally
+//!   arrive, and the node motion it is computed from.
+//! - `ephemeris` / `occlusion`: tabular position import for realistic solar-system topologies, and
+//!   the bodies that block line of sight within them.
+//! - `transport` / `network` / `bundle`: the pluggable transport trait, its QUIC and BPv7
+//!   (bundles over UDP) implementations, and the light-speed gate and custody queue that wrap them.
+//! - `protocol` / `custody`: the wire format, and the store-and-forward queue that holds outbound
+//!   events until a peer takes custody.
+//! - `routing` / `contact`: minimum-latency relay paths around blocked or downed links, and
+//!   Contact Graph Routing over scheduled contact plans.
+//! - `replica` / `node`: the sans-IO replica state machine, and the async driver that runs it over
+//!   any transport.
+//! - `simulation` / `simulator` / `fault`: an in-process cluster with an in-memory transport, a
+//!   deterministic discrete-event simulator that runs replicas under virtual time from a seed, and
+//!   the per-link faults and scheduled partitions both can inject.
+//! - `scenario`: declarative scenario files (nodes, faults, scripted writes, expectations) run on
+//!   the simulator.
+//! - `history`: a checker that verifies a simulation's recorded reads and writes against the DAG
+//!   semantics and reports minimal counterexamples.
+//! - `clock`: the system, manual and simulated time sources that time-gated components read.
+//! - `app` / `tui` / `action`: the interactive terminal front-end.
+
+pub mod action;
+pub mod app;
+pub mod bundle;
+pub mod clock;
+pub mod contact;
+pub mod crdt;
+pub mod custody;
+pub mod dag;
+pub mod ephemeris;
+pub mod event;
+pub mod fault;
+pub mod history;
+pub mod network;
+pub mod node;
+pub mod occlusion;
+pub mod physics;
+pub mod protocol;
+pub mod replica;
+pub mod routing;
+pub mod scenario;
+pub mod simulation;
+pub mod simulator;
+pub mod spacetime;
+pub mod state;
+pub mod trajectory;
+pub mod transport;
+pub mod tui;
@@ -6,44 +6,60 @@
 //!   speed of light so propagation delays are observable at human timescales.
 //!
 //! Architectural overview:
-//! - A QUIC transport (`network`) moves bytes and decodes messages.
-//! - A physics gate (`physics`) enforces relativistic causality by buffering messages until their
-//!   earliest allowed arrival time $t_{arrival} = t_{received} + d/c$.
-//! - The UI/application loop (`tui` + `app`) consumes *arrived* messages and updates the CRDT DAG.
+//! - A transport (QUIC by default, or BPv7 bundles over UDP with `--transport bp`) moves bytes and
+//!   decodes messages.
+//! - A physics gate (`network::Network` over `physics`) enforces relativistic causality by buffering
+//!   inbound messages until their earliest allowed arrival time $t_{arrival} = t_{received} + d/c$.
+//! - Outbound messages wait in a persistent custody queue (`custody`) until the peer acknowledges
+//!   them; the periodic digests are sent best-effort instead, since the next one replaces them.
+//! - A [`Node`] runs the same replica logic as the simulations on top of that stack: it says hello,
+//!   exchanges digests and requests, and ingests *arrived* gossip into its CRDT DAG.
+//! - The UI/application loop (`tui` + `app`) issues writes to the node and displays its state.
 //!
 //! The emphasis is that “consistency” is not tied to wall-clock time; it is tied to causal
 //! structure (parents) and spacetime separation (Minkowski interval / light cone constraints).
-
-mod spacetime;
-mod event;
-mod dag;
-mod simulation;
-mod node;
-mod action;
-mod app;
-mod tui;
-mod protocol;
-mod physics;
-mod network;
+//!
+//! `lightcone simulate <file>` instead runs a scenario file under virtual time and prints a report,
+//! exiting with status 1 if any expectation failed.
 
 use anyhow::Result;
 use std::env;
 use std::sync::Arc;
-use std::collections::BTreeSet;
-
-use crate::action::Action;
-use crate::app::App;
-use crate::tui::Tui;
-use crate::network::{make_server_endpoint, Network, NetworkHandle};
-use crate::physics::PhysicsLayer;
+use std::time::Duration;
+
+use lightcone::action::Action;
+use lightcone::app::App;
+use lightcone::tui::Tui;
+use lightcone::bundle::UdpTransport;
+use lightcone::clock::{Clock, SystemClock};
+use lightcone::network::{make_server_endpoint, run_custody, CustodialTransport, Network, QuicTransport};
+use lightcone::node::{Node, NodeCommand};
+use lightcone::transport::Transport;
+use lightcone::physics::{GateMode, PhysicsLayer};
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
-use crate::event::{Event, Operation};
-use crate::spacetime::SpacetimeCoord;
-use crate::protocol::ProtocolMessage;
+use lightcone::event::Operation;
+use lightcone::contact::{ContactPlan, ScheduledTransport};
+use lightcone::custody::CustodyQueue;
+use lightcone::ephemeris::Ephemeris;
+use lightcone::occlusion::{Occluder, OcclusionModel};
+use lightcone::spacetime::{now_ns, set_speed_of_light, SpacetimeCoord, DEFAULT_C};
+use lightcone::trajectory::Trajectory;
+use lightcone::scenario::Scenario;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("simulate") {
+        let path = args.get(2).ok_or_else(|| anyhow::anyhow!("usage: lightcone simulate <scenario>"))?;
+        let report = Scenario::load(path)?.run();
+        println!("{report}");
+        if !report.passed() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let _ = rustls::crypto::ring::default_provider().install_default();
 
     // Ensure the terminal is restored even if we panic inside the TUI loop.
@@ -54,11 +70,8 @@ async fn main() -> Result<()> {
         original_hook(panic_info);
     }));
 
-    let mut tui = Tui::new()?;
-    let mut app = App::new();
-
-    let port = env::args()
-        .nth(1)
+    let port = args
+        .get(1)
         .and_then(|s| s.parse::<u16>().ok())
         .unwrap_or(5000);
 
@@ -72,51 +85,116 @@ async fn main() -> Result<()> {
     // (At physical c, 300 meters would be ~1 microsecond.)
     const SPEED_OF_LIGHT: f64 = 100.0;
 
-    let endpoint = make_server_endpoint(&format!("127.0.0.1:{port}"))?;
-    let net_handle = NetworkHandle::new(endpoint.clone());
+    // `--ephemeris <file|sample>` replays real solar-system geometry instead: the node follows the
+    // body named after it (Earth, Mars, ...), the ephemeris epoch is "now", and c is physical.
+    let ephemeris_arg = args
+        .iter()
+        .position(|a| a == "--ephemeris")
+        .and_then(|i| args.get(i + 1));
+    let (trajectory, c, occlusion) = match ephemeris_arg {
+        Some(source) => {
+            let ephemeris = if source == "sample" { Ephemeris::sample() } else { Ephemeris::load(source)? };
+            let trajectory = ephemeris.with_epoch(now_ns()).trajectory(&id)?.clone();
+            let occlusion = OcclusionModel { occluders: vec![Occluder::sun()], ..OcclusionModel::default() };
+            (trajectory, DEFAULT_C, occlusion)
+        }
+        None => (Trajectory::Static(my_coords), SPEED_OF_LIGHT, OcclusionModel::default()),
+    };
+    set_speed_of_light(c);
+
+    // `--contacts <file>` restricts transmission to planned contact windows (epoch = startup).
+    let contacts = match args.iter().position(|a| a == "--contacts").and_then(|i| args.get(i + 1)) {
+        Some(path) => Some(ContactPlan::load(path)?.resolve(port_of)?.with_epoch(now_ns())?),
+        None => None,
+    };
+
+    // Outbound events are held until the peer takes custody, surviving restarts via this file.
+    let custody_path = match args.iter().position(|a| a == "--custody").and_then(|i| args.get(i + 1)) {
+        Some(path) => path.into(),
+        None => env::temp_dir().join(format!("lightcone-{port}.custody")),
+    };
+    let custody = Arc::new(Mutex::new(CustodyQueue::open(&custody_path)?));
+
+    let mut tui = Tui::new()?;
+    let mut app = App::new();
+
+    let transport: Arc<dyn Transport<Peer = u16>> = match args.iter().position(|a| a == "--transport").and_then(|i| args.get(i + 1)).map(String::as_str) {
+        None | Some("quic") => Arc::new(QuicTransport::new(make_server_endpoint(&format!("127.0.0.1:{port}"))?)),
+        Some("bp") => Arc::new(UdpTransport::bind(port).await?),
+        Some(other) => anyhow::bail!("unknown transport {other:?} (expected quic or bp)"),
+    };
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    // The transport itself refuses to transmit outside the plan's windows, so queued messages wait
+    // for the next contact towards their peer.
+    let transport: Arc<dyn Transport<Peer = u16>> = match contacts {
+        Some(plan) => Arc::new(ScheduledTransport::new(transport, port, plan, clock.clone())),
+        None => transport,
+    };
 
-    let (net_tx, mut net_rx) = mpsc::unbounded_channel();
-    let physics = Arc::new(Mutex::new(PhysicsLayer::new(SPEED_OF_LIGHT)));
+    let mut physics = PhysicsLayer::with_clock(c, occlusion, clock.clone());
+    // `--gate coordinate` releases events at their emission time plus light time, rather than light
+    // time after the bytes happened to arrive.
+    match args.iter().position(|a| a == "--gate").and_then(|i| args.get(i + 1)).map(String::as_str) {
+        None | Some("receipt") => {}
+        Some("coordinate") => physics.set_mode(GateMode::Coordinate),
+        Some(other) => anyhow::bail!("unknown gate {other:?} (expected receipt or coordinate)"),
+    }
+    let physics = Arc::new(Mutex::new(physics));
 
-    let network = Network::new(endpoint, physics.clone(), net_tx, (my_coords.x, my_coords.y));
+    // The node sees inbound messages only once they have *causally arrived* (via PhysicsLayer), and
+    // its outbound messages wait in the custody queue until the peer acknowledges them.
+    let gated = Network::new(transport.clone(), physics, trajectory.clone());
+    let node_transport = Arc::new(CustodialTransport::new(gated, custody.clone(), clock.clone()));
+    let peer = if port == 5000 { 5001 } else { 5000 };
+    let (node, commands, replica) = Node::new(port, trajectory.position_at(now_ns()), node_transport, vec![peer]);
+    tokio::spawn(node.run());
 
-    // Run the network actor concurrently with the UI/application loop.
-    // The network task only forwards messages once they have *causally arrived* (via PhysicsLayer).
-    tokio::spawn(async move {
-        let _ = network.run().await;
-    });
+    let (app_tx, mut app_rx) = mpsc::unbounded_channel();
+    tokio::spawn(run_custody(transport, custody, app_tx, clock));
+    // Anti-entropy: peers pull whatever a lost or refused message left them missing.
+    let mut sync = tokio::time::interval(SYNC_INTERVAL);
 
     while !app.should_quit {
         tokio::select! {
             Some(action) = tui.action_rx.recv() => {
                 match action {
                     Action::Render => {
+                        app.heads = replica.lock().await.dag.heads.len();
                         tui.draw(&app)?;
                     }
                     Action::Broadcast(text) => {
-                        let target_port = if port == 5000 { 5001 } else { 5000 };
-
-                        let parents: BTreeSet<_> = app.dag.heads.iter().cloned().collect();
-                        let event = Event::new(parents, my_coords.clone(), Operation::Put(id.clone(), text.clone().into_bytes()));
-
-                        if let Err(e) = app.dag.add_event(event.clone()) {
-                            eprintln!("local dag add error: {e:?}");
+                        // Stamp the write with the current coordinate time so the DAG's light-cone
+                        // check can order it against what this node has already observed.
+                        let coords = trajectory.position_at(now_ns());
+                        let payload = Operation::Put(id.clone(), text.clone().into_bytes());
+                        if commands.send(NodeCommand::CreateEvent { coords, payload }).await.is_err() {
+                            anyhow::bail!("node stopped");
                         }
-
-                        if let Err(e) = net_handle.send_gossip(target_port, ProtocolMessage::Gossip(event.clone())).await {
-                            eprintln!("broadcast error: {e:?}");
-                        }
-
-                        app.update(Action::NewEvent(ProtocolMessage::Gossip(event)));
+                        app.update(Action::Broadcast(text));
                     }
                     other => app.update(other),
                 }
             }
-            Some(net_action) = net_rx.recv() => {
-                app.update(net_action);
+            _ = sync.tick() => {
+                let _ = commands.send(NodeCommand::Sync).await;
+            }
+            Some(delivery) = app_rx.recv() => {
+                app.update(delivery);
             }
         }
     }
 
     Ok(())
 }
+
+/// How often the node advertises its heads to its peer.
+const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Resolves a contact-plan node name to the demo port it runs on (`Earth`, `Mars`, `Node-<port>`).
+fn port_of(name: &str) -> Option<u16> {
+    match name {
+        "Earth" => Some(5000),
+        "Mars" => Some(5001),
+        _ => name.strip_prefix("Node-")?.parse().ok(),
+    }
+}
@@ -1,217 +1,478 @@
-use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Duration;
-
-use anyhow::Result;
-use quinn::{ClientConfig as QuinnClientConfig, Endpoint, ServerConfig};
-use quinn::crypto::rustls::QuicClientConfig as QuinnRustlsClientConfig;
-use rcgen::generate_simple_self_signed;
-use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerifier, ServerCertVerified};
-use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, UnixTime};
-use rustls::{ClientConfig as RustlsClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
-use tokio::sync::mpsc::UnboundedSender;
-use tokio::time::interval;
-
-use crate::action::Action;
-use crate::physics::PhysicsLayer;
-use crate::protocol::ProtocolMessage;
-
-/// Creates a QUIC endpoint bound to `addr` that can both accept incoming connections and initiate
-/// outgoing ones.
-///
-/// Why QUIC / `quinn`?
-/// - QUIC gives us multiplexed streams with TLS built-in, avoiding head-of-line blocking and making
-///   it natural to model “messages” as uni-directional streams.
-/// - `quinn` is a mature async QUIC implementation in Rust that integrates cleanly with Tokio.
-///
-/// Security trade-off (intentional for simulation):
-/// - We generate a fresh self-signed certificate and configure the client side to *skip certificate
-///   verification*. This keeps local multi-node simulations frictionless (no PKI ceremony), but it
-///   is **not** appropriate for real networks.
-pub fn make_server_endpoint(addr: &str) -> Result<Endpoint> {
-    let server_config = make_server_config()?;
-    let addr: SocketAddr = addr.parse()?;
-    let mut endpoint = Endpoint::server(server_config, addr)?;
-
-    // Simulation convenience: accept self-signed certs without verification.
-    let mut client_config = RustlsClientConfig::builder()
-        .with_root_certificates(RootCertStore::empty())
-        .with_no_client_auth();
-    client_config
-        .dangerous()
-        .set_certificate_verifier(Arc::new(SkipServerVerification));
-
-    let client_crypto = QuinnRustlsClientConfig::try_from(Arc::new(client_config))?;
-    endpoint.set_default_client_config(QuinnClientConfig::new(Arc::new(client_crypto)));
-    Ok(endpoint)
-}
-
-fn make_server_config() -> Result<ServerConfig> {
-    let cert = generate_simple_self_signed(["localhost".to_string()])?;
-    let cert_der: CertificateDer<'static> = CertificateDer::from(cert.cert.der().clone());
-    let key_der: PrivateKeyDer<'static> = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()).into();
-
-    let mut server_config = quinn::ServerConfig::with_single_cert(vec![cert_der], key_der)?;
-    let mut transport = quinn::TransportConfig::default();
-    transport.keep_alive_interval(Some(Duration::from_secs(10)));
-    server_config.transport_config(Arc::new(transport));
-
-    Ok(server_config)
-}
-
-#[derive(Debug)]
-struct SkipServerVerification;
-
-impl ServerCertVerifier for SkipServerVerification {
-    fn verify_server_cert(
-        &self,
-        _end_entity: &CertificateDer<'_>,
-        _intermediates: &[CertificateDer<'_>],
-        _server_name: &rustls::pki_types::ServerName<'_>,
-        _ocsp_response: &[u8],
-        _now: UnixTime,
-    ) -> Result<ServerCertVerified, rustls::Error> {
-        Ok(ServerCertVerified::assertion())
-    }
-
-    fn verify_tls12_signature(
-        &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        Ok(HandshakeSignatureValid::assertion())
-    }
-
-    fn verify_tls13_signature(
-        &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        Ok(HandshakeSignatureValid::assertion())
-    }
-
-    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        vec![
-            SignatureScheme::ECDSA_NISTP256_SHA256,
-            SignatureScheme::ECDSA_NISTP384_SHA384,
-            SignatureScheme::ED25519,
-            SignatureScheme::RSA_PSS_SHA256,
-            SignatureScheme::RSA_PKCS1_SHA256,
-        ]
-    }
-}
-
-pub struct Network {
-    /// QUIC endpoint used for both listening and dialing.
-    pub endpoint: Endpoint,
-    /// Shared physics gate that buffers messages until their causal arrival time.
-    pub physics: Arc<tokio::sync::Mutex<PhysicsLayer>>,
-    /// Channel back into the application event loop.
-    pub app_tx: UnboundedSender<Action>,
-    /// Local (x, y) position in meters (2D simplification used by the current simulation).
-    pub my_coords: (f64, f64),
-}
-
-impl Network {
-    /// Constructs the network task.
-    ///
-    /// The network layer’s responsibility is deliberately narrow:
-    /// 1) move bytes via QUIC,
-    /// 2) decode protocol messages,
-    /// 3) compute sender/receiver separation,
-    /// 4) pass messages into `PhysicsLayer` so causality is enforced *outside* the transport.
-    pub fn new(endpoint: Endpoint, physics: Arc<tokio::sync::Mutex<PhysicsLayer>>, app_tx: UnboundedSender<Action>, my_coords: (f64, f64)) -> Self {
-        Self { endpoint, physics, app_tx, my_coords }
-    }
-
-    /// Runs the network loop.
-    ///
-    /// This loop interleaves two concerns:
-    /// - Accept inbound QUIC connections and ingest any received protocol messages.
-    /// - Periodically poll the physics buffer and forward any causally-arrived messages to the app.
-    ///
-    /// Design note: QUIC delivery is *not* treated as “arrival”. Arrival is defined by the
-    /// relativistic model: events outside the light cone must be buffered until
-    /// $t_{arrival} = t_{received} + d/c$.
-    pub async fn run(self) -> Result<()> {
-        let mut tick_interval = interval(Duration::from_millis(50));
-        loop {
-            tokio::select! {
-                _ = tick_interval.tick() => {
-                    let mut physics = self.physics.lock().await;
-                    for msg in physics.drain_arrived() {
-                        let _ = self.app_tx.send(Action::NewEvent(msg));
-                    }
-                }
-                connecting = self.endpoint.accept() => {
-                    if let Some(connecting) = connecting {
-                        let physics = self.physics.clone();
-                        let app_tx = self.app_tx.clone();
-                        let my_coords = self.my_coords;
-                        tokio::spawn(async move {
-                            if let Err(e) = handle_connection(connecting, physics.clone(), my_coords).await {
-                                eprintln!("[network] connection error: {e:?}");
-                            }
-                            let mut physics = physics.lock().await;
-                            for msg in physics.drain_arrived() {
-                                let _ = app_tx.send(Action::NewEvent(msg));
-                            }
-                        });
-                    }
-                }
-            }
-        }
-    }
-}
-
-async fn handle_connection(connecting: quinn::Incoming, physics: Arc<tokio::sync::Mutex<PhysicsLayer>>, my_coords: (f64, f64)) -> Result<()> {
-    let connection = connecting.await?;
-    println!("[network] connected: {}", connection.remote_address());
-
-    while let Ok(mut uni) = connection.accept_uni().await {
-        let data = uni.read_to_end(64 * 1024).await?;
-        let msg: ProtocolMessage = bincode::deserialize(&data)?;
-        let dist = match &msg {
-            ProtocolMessage::Gossip(event) => {
-                let dx = event.coords.x - my_coords.0;
-                let dy = event.coords.y - my_coords.1;
-                (dx * dx + dy * dy).sqrt()
-            }
-            _ => 0.0,
-        };
-        let mut physics = physics.lock().await;
-        println!("[network] ingest message: {:?} (dist={:.2})", msg, dist);
-        physics.ingest(msg, dist);
-    }
-
-    Ok(())
-}
-
-#[derive(Clone)]
-pub struct NetworkHandle {
-    endpoint: Endpoint,
-}
-
-impl NetworkHandle {
-    /// Convenience wrapper for sending protocol messages using the shared endpoint.
-    pub fn new(endpoint: Endpoint) -> Self {
-        Self { endpoint }
-    }
-
-    /// Sends a gossip message to a local target.
-    ///
-    /// This is intentionally minimal: Minkowski-KV’s “interesting” behavior is in the DAG and the
-    /// physics gate, not in elaborate transport routing.
-    pub async fn send_gossip(&self, target_port: u16, msg: ProtocolMessage) -> Result<()> {
-        let addr: SocketAddr = format!("127.0.0.1:{target_port}").parse()?;
-        let conn = self.endpoint.connect(addr, "localhost")?.await?;
-        let mut stream = conn.open_uni().await?;
-        let bytes = bincode::serialize(&msg)?;
-        stream.write_all(&bytes).await?;
-        stream.finish()?;
-        tokio::time::sleep(Duration::from_millis(500)).await;
-        Ok(())
-    }
-}
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use quinn::{ClientConfig as QuinnClientConfig, Endpoint, ServerConfig};
+use quinn::crypto::rustls::QuicClientConfig as QuinnRustlsClientConfig;
+use rcgen::generate_simple_self_signed;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerifier, ServerCertVerified};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, UnixTime};
+use rustls::{ClientConfig as RustlsClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+use crate::action::Action;
+use crate::clock::Clock;
+use crate::custody::{CustodyQueue, DeliveryStatus};
+use crate::physics::PhysicsLayer;
+use crate::protocol::{self, ProtocolMessage, MAX_MESSAGE_BYTES};
+use crate::trajectory::Trajectory;
+use crate::transport::{Inbound, Transport};
+
+/// Creates a QUIC endpoint bound to `addr` that can both accept incoming connections and initiate
+/// outgoing ones.
+///
+/// Why QUIC / `quinn`?
+/// - QUIC gives us multiplexed streams with TLS built-in, avoiding head-of-line blocking and making
+///   it natural to model each “message” as its own stream, answered with a custody acknowledgement.
+/// - `quinn` is a mature async QUIC implementation in Rust that integrates cleanly with Tokio.
+///
+/// Security trade-off (intentional for simulation):
+/// - We generate a fresh self-signed certificate and configure the client side to *skip certificate
+///   verification*. This keeps local multi-node simulations frictionless (no PKI ceremony), but it
+///   is **not** appropriate for real networks.
+pub fn make_server_endpoint(addr: &str) -> Result<Endpoint> {
+    let server_config = make_server_config()?;
+    let addr: SocketAddr = addr.parse()?;
+    let mut endpoint = Endpoint::server(server_config, addr)?;
+
+    // Simulation convenience: accept self-signed certs without verification.
+    let mut client_config = RustlsClientConfig::builder()
+        .with_root_certificates(RootCertStore::empty())
+        .with_no_client_auth();
+    client_config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(SkipServerVerification));
+
+    let client_crypto = QuinnRustlsClientConfig::try_from(Arc::new(client_config))?;
+    endpoint.set_default_client_config(QuinnClientConfig::new(Arc::new(client_crypto)));
+    Ok(endpoint)
+}
+
+fn make_server_config() -> Result<ServerConfig> {
+    let cert = generate_simple_self_signed(["localhost".to_string()])?;
+    let cert_der: CertificateDer<'static> = cert.cert.der().clone();
+    let key_der: PrivateKeyDer<'static> = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()).into();
+
+    let mut server_config = quinn::ServerConfig::with_single_cert(vec![cert_der], key_der)?;
+    let mut transport = quinn::TransportConfig::default();
+    transport.keep_alive_interval(Some(Duration::from_secs(10)));
+    server_config.transport_config(Arc::new(transport));
+
+    Ok(server_config)
+}
+
+#[derive(Debug)]
+struct SkipServerVerification;
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ED25519,
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ]
+    }
+}
+
+/// A physics gate shared between a [`Network`] and whoever inspects it, holding each message with
+/// its sender.
+pub type SharedGate<P> = Arc<Mutex<PhysicsLayer<(P, ProtocolMessage)>>>;
+
+/// Wraps a [`Transport`] with the light-speed gate: every inbound message is held in a
+/// [`PhysicsLayer`] until it may causally arrive, and only then handed to the node.
+pub struct Network<T: Transport> {
+    inner: T,
+    /// Shared physics gate that buffers messages, with their senders, until their causal arrival
+    /// time.
+    pub physics: SharedGate<T::Peer>,
+    /// This node's position over coordinate time.
+    pub trajectory: Trajectory,
+}
+
+impl<T: Transport> Network<T> {
+    /// Constructs the gated transport.
+    ///
+    /// The network layer’s responsibility is deliberately narrow:
+    /// 1) take decoded protocol messages from the inner [`Transport`],
+    /// 2) compute sender/receiver separation,
+    /// 3) pass messages into `PhysicsLayer` so causality is enforced *outside* the transport,
+    /// 4) tell the transport whether custody was taken.
+    pub fn new(inner: T, physics: SharedGate<T::Peer>, trajectory: Trajectory) -> Self {
+        Self { inner, physics, trajectory }
+    }
+
+    /// Hands `msg` from `from` to the physics gate, returning whether it was accepted.
+    async fn ingest(&self, from: T::Peer, msg: ProtocolMessage) -> bool {
+        let mut physics = self.physics.lock().await;
+        println!("[network] ingest message: {:?}", msg);
+        match &msg {
+            ProtocolMessage::Gossip(events) if !events.is_empty() => {
+                // The batch's newest event was emitted at its own coordinates; we receive it
+                // wherever we are once the light-time equation says the signal catches up with us.
+                let newest = events.iter().max_by_key(|e| e.coords.t).expect("batch is not empty");
+                let (emission, uncertainty) = (newest.coords, newest.uncertainty);
+                if !physics.ingest_path((from, msg), &emission, &self.trajectory, uncertainty) {
+                    // Refuse custody: the sender keeps the event and retries later.
+                    println!("[network] dropped message: line of sight blocked");
+                    return false;
+                }
+            }
+            _ => physics.ingest((from, msg), 0.0),
+        }
+        true
+    }
+}
+
+impl<T: Transport> Transport for Network<T> {
+    type Peer = T::Peer;
+
+    fn send(&self, peer: T::Peer, msg: ProtocolMessage) -> BoxFuture<'_, Result<()>> {
+        self.inner.send(peer, msg)
+    }
+
+    /// Runs the network loop.
+    ///
+    /// This loop interleaves two concerns:
+    /// - Ingest protocol messages received by the inner transport, deciding custody at receipt.
+    /// - Periodically poll the physics buffer and forward any causally-arrived messages to `inbound`.
+    ///
+    /// Design note: transport delivery is *not* treated as “arrival”. Arrival is defined by the
+    /// relativistic model: events outside the light cone must be buffered until
+    /// $t_{arrival} = t_{received} + d/c$.
+    ///
+    /// Ticks follow the physics layer's [`Clock`], so under a manual or simulated clock the loop
+    /// releases messages exactly when that clock says they have arrived.
+    fn serve(&self, inbound: mpsc::Sender<Inbound<T::Peer>>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let (received_tx, mut received) = mpsc::channel(64);
+            let serving = self.inner.serve(received_tx);
+            tokio::pin!(serving);
+            let clock = self.physics.lock().await.clock();
+            let mut next_tick = clock.now();
+            loop {
+                tokio::select! {
+                    result = &mut serving => return result,
+                    _ = clock.sleep_until(next_tick) => {
+                        next_tick = clock.now() + TICK_NS;
+                        let arrived = self.physics.lock().await.drain_arrived();
+                        for (from, message) in arrived {
+                            // Custody was already decided on receipt.
+                            let (msg, _) = Inbound::new(from, message);
+                            inbound.send(msg).await?;
+                        }
+                    }
+                    msg = received.recv() => {
+                        let Some(Inbound { from, message, custody }) = msg else {
+                            return Ok(());
+                        };
+                        let _ = custody.send(self.ingest(from, message).await);
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// The QUIC transport: one bidirectional stream per message, answered with a custody
+/// acknowledgement on the same stream.
+#[derive(Clone)]
+pub struct QuicTransport {
+    endpoint: Endpoint,
+}
+
+impl QuicTransport {
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self { endpoint }
+    }
+
+    /// Sends a message to a local target and waits for it to take custody.
+    ///
+    /// This is intentionally minimal: Minkowski-KV’s “interesting” behavior is in the DAG and the
+    /// physics gate, not in elaborate transport routing. Returns an error if the peer cannot be
+    /// reached or does not acknowledge custody within [`CUSTODY_TIMEOUT`].
+    pub async fn send_gossip(&self, target_port: u16, msg: ProtocolMessage) -> Result<()> {
+        timeout(CUSTODY_TIMEOUT, async {
+            let addr: SocketAddr = format!("127.0.0.1:{target_port}").parse()?;
+            let conn = self.endpoint.connect(addr, "localhost")?.await?;
+            let (mut stream, mut reply) = conn.open_bi().await?;
+            stream.write_all(&protocol::encode(&msg)).await?;
+            stream.finish()?;
+            let reply = reply.read_to_end(1024).await?;
+            if reply.is_empty() {
+                bail!("peer refused custody");
+            }
+            let ack: ProtocolMessage = protocol::decode(&reply)?;
+            if !matches!(ack, ProtocolMessage::CustodyAck) {
+                bail!("unexpected reply: {ack:?}");
+            }
+            Ok(())
+        })
+        .await
+        .context("custody acknowledgement timed out")?
+    }
+}
+
+impl Transport for QuicTransport {
+    type Peer = u16;
+
+    fn send(&self, peer: u16, msg: ProtocolMessage) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.send_gossip(peer, msg))
+    }
+
+    fn serve(&self, inbound: mpsc::Sender<Inbound<u16>>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            while let Some(connecting) = self.endpoint.accept().await {
+                let inbound = inbound.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(connecting, inbound).await {
+                        eprintln!("[network] connection error: {e:?}");
+                    }
+                });
+            }
+            Ok(())
+        })
+    }
+}
+
+async fn handle_connection(connecting: quinn::Incoming, inbound: mpsc::Sender<Inbound<u16>>) -> Result<()> {
+    let connection = connecting.await?;
+    println!("[network] connected: {}", connection.remote_address());
+    // Peers dial from the endpoint they listen on, so the remote port identifies the node.
+    let from = connection.remote_address().port();
+
+    while let Ok((mut reply, mut incoming)) = connection.accept_bi().await {
+        let data = incoming.read_to_end(MAX_MESSAGE_BYTES).await?;
+        let (msg, decision) = Inbound::new(from, protocol::decode(&data)?);
+        inbound.send(msg).await?;
+        if decision.await.unwrap_or(false) {
+            reply.write_all(&protocol::encode(&ProtocolMessage::CustodyAck)).await?;
+        }
+        reply.finish()?;
+    }
+
+    Ok(())
+}
+
+/// Hands outbound messages to a [`CustodyQueue`] rather than the wire, so they survive failed
+/// attempts and restarts; [`run_custody`] drains the queue over the real transport.
+///
+/// A send completes once the message is stored, i.e. once this node has taken custody of it.
+/// Digests are the exception: the next sync replaces a lost one, so they go straight to `inner`
+/// rather than piling up in the store while a peer is unreachable. Inbound traffic is served by
+/// `inner` unchanged.
+pub struct CustodialTransport<T: Transport> {
+    inner: T,
+    queue: Arc<Mutex<CustodyQueue<T::Peer>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T: Transport> CustodialTransport<T>
+where
+    T::Peer: Ord + Serialize + DeserializeOwned,
+{
+    pub fn new(inner: T, queue: Arc<Mutex<CustodyQueue<T::Peer>>>, clock: Arc<dyn Clock>) -> Self {
+        Self { inner, queue, clock }
+    }
+}
+
+impl<T: Transport> Transport for CustodialTransport<T>
+where
+    T::Peer: Ord + Serialize + DeserializeOwned,
+{
+    type Peer = T::Peer;
+
+    fn send(&self, peer: T::Peer, msg: ProtocolMessage) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            if matches!(msg, ProtocolMessage::Digest { .. }) {
+                return self.inner.send(peer, msg).await;
+            }
+            self.queue.lock().await.enqueue(peer, msg, self.clock.now())?;
+            Ok(())
+        })
+    }
+
+    fn serve(&self, inbound: mpsc::Sender<Inbound<T::Peer>>) -> BoxFuture<'_, Result<()>> {
+        self.inner.serve(inbound)
+    }
+}
+
+/// Drains `queue` over `transport` forever: sends each peer's head-of-line bundle when it is due on
+/// `clock`, releases it once the peer takes custody, and backs off on failure. Status changes are
+/// reported to the application as [`Action::Delivery`].
+pub async fn run_custody<P>(
+    transport: Arc<dyn Transport<Peer = P>>,
+    queue: Arc<Mutex<CustodyQueue<P>>>,
+    app_tx: UnboundedSender<Action>,
+    clock: Arc<dyn Clock>,
+) where
+    P: Copy + Ord + Hash + Display + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    let in_flight = Arc::new(Mutex::new(HashSet::new()));
+    loop {
+        clock.sleep_until(clock.now() + CUSTODY_TICK_NS).await;
+        let due = queue.lock().await.due(clock.now());
+        for bundle in due {
+            if !in_flight.lock().await.insert(bundle.id) {
+                continue;
+            }
+            let (transport, queue, app_tx, in_flight, clock) =
+                (transport.clone(), queue.clone(), app_tx.clone(), in_flight.clone(), clock.clone());
+            tokio::spawn(async move {
+                let sent = transport.send(bundle.peer, bundle.message).await;
+                let mut queue = queue.lock().await;
+                let status = match sent {
+                    Ok(()) => queue.acknowledge(bundle.id).map(|_| DeliveryStatus::Delivered),
+                    Err(e) => {
+                        eprintln!("[custody] bundle {} to {}: {e:#}", bundle.id, bundle.peer);
+                        queue.failed(bundle.id, clock.now())
+                    }
+                };
+                match status {
+                    Ok(status) => {
+                        let _ = app_tx.send(Action::Delivery { id: bundle.id, peer: bundle.peer.to_string(), status });
+                    }
+                    Err(e) => eprintln!("[custody] store error: {e}"),
+                }
+                in_flight.lock().await.remove(&bundle.id);
+            });
+        }
+    }
+}
+
+/// Interval (ns) at which the network loop polls the physics gate.
+const TICK_NS: u128 = 50_000_000;
+
+/// Interval (ns) at which the custody loop looks for due bundles.
+const CUSTODY_TICK_NS: u128 = 250_000_000;
+
+/// How long a transport waits for a peer to connect and take custody.
+pub const CUSTODY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    use crate::clock::{ManualClock, SystemClock};
+    use crate::event::{Event, Operation};
+    use crate::node::{Node, NodeCommand};
+    use crate::occlusion::OcclusionModel;
+    use crate::simulation::{Cluster, InMemoryTransport};
+    use crate::spacetime::{set_speed_of_light, SpacetimeCoord, DEFAULT_C, SPEED_OF_LIGHT_TEST_LOCK};
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn a_node_behind_the_gate_ingests_gossip_only_once_it_has_arrived() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.lock().await;
+        set_speed_of_light(100.0);
+
+        let at = |x: f64| SpacetimeCoord { t: 0, x, y: 0.0, z: 0.0 };
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let (tx_a, rx_a) = mpsc::channel(16);
+        let (tx_b, rx_b) = mpsc::channel(16);
+        let mut cluster = Cluster::new();
+        cluster.register_node(a, tx_a, at(0.0));
+        cluster.register_node(b, tx_b, at(10.0));
+        let cluster = Arc::new(cluster);
+
+        // B's gate runs on a clock that only moves when the test says so.
+        let clock = ManualClock::new(0);
+        let physics = Arc::new(Mutex::new(PhysicsLayer::with_clock(100.0, OcclusionModel::default(), clock.clone())));
+        let gated = Network::new(InMemoryTransport::new(b, cluster.clone(), rx_b), physics, Trajectory::Static(at(10.0)));
+        let (node_b, _cmd_b, replica_b) = Node::new(b, at(10.0), Arc::new(gated), vec![a]);
+        let (node_a, cmd_a, _replica_a) = Node::new(a, at(0.0), Arc::new(InMemoryTransport::new(a, cluster.clone(), rx_a)), vec![b]);
+        tokio::spawn(node_a.run());
+        tokio::spawn(node_b.run());
+
+        let payload = Operation::Put("k".into(), b"v".to_vec());
+        cmd_a.send(NodeCommand::CreateEvent { coords: at(0.0), payload }).await.unwrap();
+
+        // The bytes are through the in-memory link after 0.1 s, but the gate still holds them.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(replica_b.lock().await.dag.graph.node_count(), 1, "only genesis");
+
+        clock.advance(1_000_000_000);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(replica_b.lock().await.dag.graph.node_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn custody_carries_uuid_peers_and_leaves_digests_out_of_the_store() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.lock().await;
+        set_speed_of_light(DEFAULT_C);
+
+        let at = |x: f64| SpacetimeCoord { t: 0, x, y: 0.0, z: 0.0 };
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let (tx_a, rx_a) = mpsc::channel(16);
+        let (tx_b, rx_b) = mpsc::channel(16);
+        let mut cluster = Cluster::new();
+        cluster.register_node(a, tx_a, at(0.0));
+        cluster.register_node(b, tx_b, at(10.0));
+        let cluster = Arc::new(cluster);
+
+        let (node_b, _cmd_b, replica_b) = Node::new(b, at(10.0), Arc::new(InMemoryTransport::new(b, cluster.clone(), rx_b)), vec![a]);
+        tokio::spawn(node_b.run());
+
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let queue = Arc::new(Mutex::new(CustodyQueue::new()));
+        let wire: Arc<dyn Transport<Peer = Uuid>> = Arc::new(InMemoryTransport::new(a, cluster.clone(), rx_a));
+        let custodial = CustodialTransport::new(wire.clone(), queue.clone(), clock.clone());
+
+        custodial.send(b, ProtocolMessage::Digest { heads: Vec::new() }).await.unwrap();
+        assert_eq!(queue.lock().await.pending(b), 0, "digests are sent, not stored");
+
+        let event = Event::new(BTreeSet::from([Event::genesis().hash]), at(0.0), Operation::Merge);
+        custodial.send(b, ProtocolMessage::Gossip(vec![event])).await.unwrap();
+        assert_eq!(queue.lock().await.pending(b), 1);
+
+        let (app_tx, mut app_rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_custody(wire, queue.clone(), app_tx, clock));
+        match app_rx.recv().await {
+            Some(Action::Delivery { peer, status, .. }) => assert_eq!((peer, status), (b.to_string(), DeliveryStatus::Delivered)),
+            other => panic!("expected a delivery, got {other:?}"),
+        }
+        assert_eq!(queue.lock().await.pending(b), 0);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(replica_b.lock().await.dag.graph.node_count(), 2);
+    }
+}
@@ -1,161 +1,223 @@
-use std::collections::BTreeSet;
-use std::sync::Arc;
-
-use tokio::select;
-use tokio::sync::{mpsc, Mutex};
-use uuid::Uuid;
-
-use crate::dag::SpacetimeDAG;
-use crate::event::{Event, EventHash, Operation};
-use crate::simulation::{Cluster, Message, NetworkPacket};
-use crate::spacetime::SpacetimeCoord;
-
-pub enum NodeCommand {
-    CreateEvent {
-        coords: SpacetimeCoord,
-        payload: Operation,
-    },
-}
-
-pub struct Node {
-    pub id: Uuid,
-    pub dag: Arc<Mutex<SpacetimeDAG>>,
-    pub coords: SpacetimeCoord,
-    pub inbox: mpsc::Receiver<Message>,
-    pub cluster: Arc<Cluster>,
-    pub cluster_handle: crate::simulation::NodeHandle,
-    pub peers: Vec<Uuid>,
-    pub command_rx: mpsc::Receiver<NodeCommand>,
-}
-
-impl Node {
-    pub fn new(
-        id: Uuid,
-        coords: SpacetimeCoord,
-        inbox: mpsc::Receiver<Message>,
-        cluster: Arc<Cluster>,
-        cluster_handle: crate::simulation::NodeHandle,
-        peers: Vec<Uuid>,
-    ) -> (Self, mpsc::Sender<NodeCommand>, Arc<Mutex<SpacetimeDAG>>) {
-        let dag = Arc::new(Mutex::new(SpacetimeDAG::new()));
-        let (command_tx, command_rx) = mpsc::channel(16);
-
-        (
-            Self {
-                id,
-                dag: dag.clone(),
-                coords,
-                inbox,
-                cluster,
-                cluster_handle,
-                peers,
-                command_rx,
-            },
-            command_tx,
-            dag,
-        )
-    }
-
-    pub async fn run(mut self) {
-        println!("Node {} starting with coords ({}, {}, {})", self.id, self.coords.x, self.coords.y, self.coords.z);
-        loop {
-            select! {
-                maybe_msg = self.inbox.recv() => {
-                    if let Some(msg) = maybe_msg {
-                        self.handle_message(msg).await;
-                    } else {
-                        break;
-                    }
-                }
-                maybe_cmd = self.command_rx.recv() => {
-                    if let Some(cmd) = maybe_cmd {
-                        self.handle_command(cmd).await;
-                    } else {
-                        break;
-                    }
-                }
-            }
-        }
-    }
-
-    async fn handle_message(&mut self, msg: Message) {
-        match msg.payload {
-            NetworkPacket::Gossip(events) => self.handle_gossip(events).await,
-            NetworkPacket::AntiEntropyRequest(_) => {
-                // Not implemented in this prototype.
-            }
-        }
-    }
-
-    async fn handle_command(&mut self, cmd: NodeCommand) {
-        match cmd {
-            NodeCommand::CreateEvent { coords, payload } => {
-                self.create_event(coords, payload).await;
-            }
-        }
-    }
-
-    async fn create_event(&mut self, coords: SpacetimeCoord, payload: Operation) {
-        let mut dag = self.dag.lock().await;
-        let parents: BTreeSet<_> = dag.heads.iter().cloned().collect();
-        let mut ancestors: Vec<Event> = Vec::new();
-
-        // Collect parent events so peers can ingest without parent-missing failures.
-        for parent_hash in &parents {
-            if let Some(idx) = dag.index_map.get(parent_hash) {
-                ancestors.push(dag.graph[*idx].clone());
-            }
-        }
-
-        let event = Event::new(parents, coords, payload);
-        let event_hash = event.hash;
-
-        if let Err(err) = dag.add_event(event.clone()) {
-            println!("Node {} failed to add local event ({}): {:?}", self.id, fmt_hash(&event_hash), err);
-            return;
-        }
-
-        // Build gossip payload with ancestors first, then the new event.
-        let mut gossip_events = ancestors;
-        gossip_events.push(event.clone());
-
-        drop(dag);
-
-        for peer in &self.peers {
-            let msg = Message {
-                sender: self.id,
-                receiver: *peer,
-                send_time: coords.t,
-                payload: NetworkPacket::Gossip(gossip_events.clone()),
-            };
-
-            if let Err(e) = self.cluster.route_message(msg) {
-                println!("Node {} failed to route gossip to {}: {}", self.id, peer, e);
-            }
-        }
-
-        println!("Node {} created event {:?} (hash {})", self.id, event.payload, fmt_hash(&event_hash));
-    }
-
-    async fn handle_gossip(&mut self, events: Vec<Event>) {
-        let mut dag = self.dag.lock().await;
-        let mut added = 0usize;
-        for ev in events {
-            println!("Processing incoming event: {}", fmt_hash(&ev.hash));
-            match dag.add_event(ev) {
-                Ok(_) => added += 1,
-                Err(err) => println!("Node {} failed to add event: {:?}", self.id, err),
-            }
-        }
-        println!(
-            "Node {} received {} events. Current Heads: {}",
-            self.id,
-            added,
-            dag.heads.len()
-        );
-    }
-}
-
-fn fmt_hash(hash: &EventHash) -> String {
-    hash.iter().map(|b| format!("{:02x}", b)).collect::<String>()
-}
+use std::sync::Arc;
+
+use tokio::select;
+use tokio::sync::{mpsc, Mutex};
+use uuid::Uuid;
+
+use crate::event::{EventHash, Operation};
+use crate::protocol::ProtocolMessage;
+use crate::replica::{Outbox, Replica};
+use crate::spacetime::SpacetimeCoord;
+use crate::transport::{Inbound, Transport};
+
+/// A replica shared between its [`Node`] and whoever inspects it.
+pub type SharedReplica<P> = Arc<Mutex<Replica<P>>>;
+
+pub enum NodeCommand {
+    CreateEvent {
+        coords: SpacetimeCoord,
+        payload: Operation,
+    },
+    /// Advertise our heads to every peer so they can pull what they are missing.
+    Sync,
+}
+
+/// Drives a [`Replica`] over any [`Transport`]: feeds it inbound messages and local commands, and
+/// sends whatever it replies.
+pub struct Node<T: Transport> {
+    pub id: T::Peer,
+    pub replica: SharedReplica<T::Peer>,
+    pub coords: SpacetimeCoord,
+    pub transport: Arc<T>,
+    pub command_rx: mpsc::Receiver<NodeCommand>,
+}
+
+impl<T: Transport + 'static> Node<T> {
+    pub fn new(
+        id: T::Peer,
+        coords: SpacetimeCoord,
+        transport: Arc<T>,
+        peers: Vec<T::Peer>,
+    ) -> (Self, mpsc::Sender<NodeCommand>, SharedReplica<T::Peer>) {
+        let replica = Arc::new(Mutex::new(Replica::new(id, peers)));
+        let (command_tx, command_rx) = mpsc::channel(16);
+
+        (
+            Self {
+                id,
+                replica: replica.clone(),
+                coords,
+                transport,
+                command_rx,
+            },
+            command_tx,
+            replica,
+        )
+    }
+
+    pub async fn run(mut self) {
+        println!("Node {} starting with coords ({}, {}, {})", self.id, self.coords.x, self.coords.y, self.coords.z);
+        let (inbound_tx, mut inbound) = mpsc::channel(64);
+        let transport = self.transport.clone();
+        tokio::spawn(async move {
+            if let Err(e) = transport.serve(inbound_tx).await {
+                println!("Transport stopped: {e:?}");
+            }
+        });
+
+        let hello = self.replica.lock().await.hello(&self.coords);
+        self.send_all(hello);
+
+        loop {
+            select! {
+                maybe_msg = inbound.recv() => {
+                    if let Some(msg) = maybe_msg {
+                        self.handle_message(msg).await;
+                    } else {
+                        break;
+                    }
+                }
+                maybe_cmd = self.command_rx.recv() => {
+                    if let Some(cmd) = maybe_cmd {
+                        self.handle_command(cmd).await;
+                    } else {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_message(&mut self, msg: Inbound<T::Peer>) {
+        // Received events are stored (in the DAG or the orphan buffer), so custody is always taken.
+        let _ = msg.custody.send(true);
+        if let ProtocolMessage::Gossip(events) = &msg.message {
+            for ev in events {
+                println!("Processing incoming event: {}", fmt_hash(&ev.hash));
+            }
+        }
+        let mut replica = self.replica.lock().await;
+        let out = replica.handle(msg.from, msg.message);
+        let (heads, orphans) = (replica.dag.heads.len(), replica.orphans());
+        drop(replica);
+        println!("Node {} heads: {}, orphans: {}", self.id, heads, orphans);
+        self.send_all(out);
+    }
+
+    async fn handle_command(&mut self, cmd: NodeCommand) {
+        match cmd {
+            NodeCommand::CreateEvent { coords, payload } => {
+                let written = self.replica.lock().await.write(Uuid::new_v4(), coords, payload);
+                match written {
+                    Ok((event, out)) => {
+                        println!("Node {} created event {:?} (hash {})", self.id, event.payload, fmt_hash(&event.hash));
+                        self.send_all(out);
+                    }
+                    Err(err) => println!("Node {} failed to add local event: {:?}", self.id, err),
+                }
+            }
+            NodeCommand::Sync => {
+                let out = self.replica.lock().await.sync();
+                self.send_all(out);
+            }
+        }
+    }
+
+    /// Sends each message on a separate task: a transport may hold it for the light time.
+    fn send_all(&self, out: Outbox<T::Peer>) {
+        for (peer, msg) in out {
+            let (transport, id) = (self.transport.clone(), self.id);
+            tokio::spawn(async move {
+                if let Err(e) = transport.send(peer, msg).await {
+                    println!("Node {} failed to send to {}: {}", id, peer, e);
+                }
+            });
+        }
+    }
+}
+
+fn fmt_hash(hash: &EventHash) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{Cluster, InMemoryTransport};
+    use crate::spacetime::{set_speed_of_light, SPEED_OF_LIGHT_TEST_LOCK};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn replicas_converge_over_the_in_memory_transport() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.lock().await;
+        set_speed_of_light(100.0);
+
+        let at = |x: f64| SpacetimeCoord { t: 0, x, y: 0.0, z: 0.0 };
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let (tx_a, rx_a) = mpsc::channel(16);
+        let (tx_b, rx_b) = mpsc::channel(16);
+        let mut cluster = Cluster::new();
+        cluster.register_node(a, tx_a, at(0.0));
+        cluster.register_node(b, tx_b, at(10.0));
+        let cluster = Arc::new(cluster);
+
+        let transport_a = Arc::new(InMemoryTransport::new(a, cluster.clone(), rx_a));
+        let transport_b = Arc::new(InMemoryTransport::new(b, cluster.clone(), rx_b));
+        let (node_a, cmd_a, replica_a) = Node::new(a, at(0.0), transport_a, vec![b]);
+        let (node_b, _cmd_b, replica_b) = Node::new(b, at(10.0), transport_b, vec![a]);
+        tokio::spawn(node_a.run());
+        tokio::spawn(node_b.run());
+
+        for value in [b"one".to_vec(), b"two".to_vec()] {
+            let payload = Operation::Put("k".into(), value);
+            cmd_a.send(NodeCommand::CreateEvent { coords: at(0.0), payload }).await.unwrap();
+        }
+
+        // 10 m at c = 100 m/s: 0.1 s per message.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let (replica_a, replica_b) = (replica_a.lock().await, replica_b.lock().await);
+        let (dag_a, dag_b) = (&replica_a.dag, &replica_b.dag);
+        assert_eq!(dag_b.graph.node_count(), 3, "shared genesis plus both writes");
+        assert_eq!(dag_a.heads, dag_b.heads);
+        assert_eq!(dag_b.state().get("k"), Some(&crate::state::Value::Bytes(b"two".to_vec())));
+    }
+
+    #[tokio::test]
+    async fn late_joiner_pulls_history_through_the_handshake() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.lock().await;
+        set_speed_of_light(100.0);
+
+        let at = |x: f64| SpacetimeCoord { t: 0, x, y: 0.0, z: 0.0 };
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let (tx_a, rx_a) = mpsc::channel(16);
+        let (tx_b, rx_b) = mpsc::channel(16);
+        let mut cluster = Cluster::new();
+        cluster.register_node(a, tx_a, at(0.0));
+        cluster.register_node(b, tx_b, at(10.0));
+        let cluster = Arc::new(cluster);
+
+        // A never pushes (no peers); B has to pull everything after saying hello.
+        let transport_a = Arc::new(InMemoryTransport::new(a, cluster.clone(), rx_a));
+        let (node_a, cmd_a, replica_a) = Node::new(a, at(0.0), transport_a, vec![]);
+        tokio::spawn(node_a.run());
+        for key in ["x", "y", "z"] {
+            let payload = Operation::Put(key.into(), b"v".to_vec());
+            cmd_a.send(NodeCommand::CreateEvent { coords: at(0.0), payload }).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let transport_b = Arc::new(InMemoryTransport::new(b, cluster.clone(), rx_b));
+        let (node_b, _cmd_b, replica_b) = Node::new(b, at(10.0), transport_b, vec![a]);
+        tokio::spawn(node_b.run());
+
+        // Hello, digest, request, gossip: four 100 ms hops, since the reply to the one request
+        // carries the whole three-event chain. Pulling it an ancestor per round trip would take
+        // another four hops.
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        let (replica_a, replica_b) = (replica_a.lock().await, replica_b.lock().await);
+        let (dag_a, dag_b) = (&replica_a.dag, &replica_b.dag);
+        assert_eq!(dag_b.graph.node_count(), 4);
+        assert_eq!(dag_a.heads, dag_b.heads);
+    }
+}
@@ -0,0 +1,219 @@
+//! Line-of-sight occlusion.
+//!
+//! A signal cannot pass through the Sun or a planet. An [`OcclusionModel`] holds a set of occluding
+//! spheres that move along their own trajectories and decides, for a signal between two nodes,
+//! whether the straight-line path is clear. A blocked signal is either dropped or held at the
+//! sender until the link clears, depending on the model's [`BlockedPolicy`].
+//!
+//! The Sun is a special case in practice: long before the geometric disk blocks a link, the solar
+//! corona drowns it in noise. Mission operators avoid Sun–Earth–probe angles below about two
+//! degrees, which is what [`SOLAR_EXCLUSION_RADIUS`] models and what makes Mars solar conjunction a
+//! roughly two-week blackout.
+
+use crate::spacetime::{speed_of_light, SpacetimeCoord};
+use crate::trajectory::{arrival_time_at_c, distance, Trajectory};
+
+/// Effective radius (meters) of the Sun's radio exclusion zone: the miss distance at 1 AU that
+/// corresponds to a ~2° Sun–Earth–probe angle.
+pub const SOLAR_EXCLUSION_RADIUS: f64 = 5.2e9;
+
+/// A sphere that blocks signals passing through it.
+#[derive(Debug, Clone)]
+pub struct Occluder {
+    pub name: String,
+    /// Blocking radius in meters.
+    pub radius: f64,
+    pub trajectory: Trajectory,
+}
+
+impl Occluder {
+    /// The Sun at the origin of a heliocentric frame, with its radio exclusion zone.
+    pub fn sun() -> Self {
+        Self {
+            name: "Sun".to_string(),
+            radius: SOLAR_EXCLUSION_RADIUS,
+            trajectory: Trajectory::Static(SpacetimeCoord {
+                t: 0,
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }),
+        }
+    }
+}
+
+/// What to do with a signal whose line of sight is blocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockedPolicy {
+    /// Discard the signal.
+    Drop,
+    /// Hold the signal at the sender and retry every `step_ns`, giving up after `max_hold_ns`.
+    ///
+    /// A `step_ns` of zero never makes progress, so it behaves like [`BlockedPolicy::Drop`].
+    Hold { step_ns: u128, max_hold_ns: u128 },
+}
+
+impl BlockedPolicy {
+    /// When to retry a signal first sent at `t_emit` and still blocked at `t`, if at all.
+    fn retry_at(&self, t_emit: u128, t: u128) -> Option<u128> {
+        match *self {
+            Self::Hold { step_ns, max_hold_ns } if step_ns > 0 => {
+                t.checked_add(step_ns).filter(|next| next - t_emit <= max_hold_ns)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for BlockedPolicy {
+    /// Retry hourly for up to 60 days, enough to ride out a solar conjunction.
+    fn default() -> Self {
+        Self::Hold {
+            step_ns: 3_600 * 1_000_000_000,
+            max_hold_ns: 60 * 86_400 * 1_000_000_000,
+        }
+    }
+}
+
+/// Outcome of scheduling a signal through an [`OcclusionModel`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkState {
+    /// The signal leaves at `emission` and reaches the receiver at `arrival` (both nanoseconds).
+    ///
+    /// `emission` is later than the requested send time if the signal had to be held.
+    Clear { emission: u128, arrival: u128 },
+    /// The signal is dropped because `occluder` blocks the path.
+    Blocked { occluder: String },
+}
+
+/// The set of occluding bodies a propagation model must route around.
+#[derive(Debug, Clone, Default)]
+pub struct OcclusionModel {
+    pub occluders: Vec<Occluder>,
+    pub policy: BlockedPolicy,
+}
+
+impl OcclusionModel {
+    /// Returns the first occluder blocking a signal from `emission` to `reception`, if any.
+    ///
+    /// Each occluder is placed at its position halfway through the flight. Occluders that contain
+    /// either endpoint are ignored: a ground station is not blocked by the planet it stands on.
+    pub fn blocker(&self, emission: &SpacetimeCoord, reception: &SpacetimeCoord) -> Option<&Occluder> {
+        let midpoint = emission.t + (reception.t.saturating_sub(emission.t)) / 2;
+        self.occluders.iter().find(|occluder| {
+            let center = occluder.trajectory.position_at(midpoint);
+            distance(&center, emission) > occluder.radius
+                && distance(&center, reception) > occluder.radius
+                && segment_passes_within(emission, reception, &center, occluder.radius)
+        })
+    }
+
+    /// Schedules a signal `sender` wants to emit at `t_emit` towards `receiver`.
+    pub fn schedule(&self, sender: &Trajectory, receiver: &Trajectory, t_emit: u128) -> LinkState {
+        self.schedule_at_c(sender, receiver, t_emit, speed_of_light())
+    }
+
+    /// Like [`Self::schedule`], for a signal travelling at `c` (m/s) rather than the configured
+    /// speed of light.
+    pub fn schedule_at_c(&self, sender: &Trajectory, receiver: &Trajectory, t_emit: u128, c: f64) -> LinkState {
+        let mut t = t_emit;
+        loop {
+            let emission = sender.position_at(t);
+            let arrival = arrival_time_at_c(&emission, receiver, c);
+            let Some(occluder) = self.blocker(&emission, &receiver.position_at(arrival)) else {
+                return LinkState::Clear { emission: t, arrival };
+            };
+
+            match self.policy.retry_at(t_emit, t) {
+                Some(next) => t = next,
+                None => {
+                    return LinkState::Blocked {
+                        occluder: occluder.name.clone(),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether the segment `a`–`b` passes within `radius` of `center`.
+fn segment_passes_within(a: &SpacetimeCoord, b: &SpacetimeCoord, center: &SpacetimeCoord, radius: f64) -> bool {
+    let ab = (b.x - a.x, b.y - a.y, b.z - a.z);
+    let ac = (center.x - a.x, center.y - a.y, center.z - a.z);
+    let len_sq = ab.0 * ab.0 + ab.1 * ab.1 + ab.2 * ab.2;
+    let s = if len_sq > 0.0 {
+        ((ac.0 * ab.0 + ac.1 * ab.1 + ac.2 * ab.2) / len_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let closest = SpacetimeCoord {
+        t: a.t,
+        x: a.x + ab.0 * s,
+        y: a.y + ab.1 * s,
+        z: a.z + ab.2 * s,
+    };
+    distance(&closest, center) <= radius
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ephemeris::{Ephemeris, SAMPLE_CONJUNCTION_DAY};
+    use crate::spacetime::{set_speed_of_light, DEFAULT_C, SPEED_OF_LIGHT_TEST_LOCK};
+
+    const DAY_NS: u128 = 86_400 * 1_000_000_000;
+
+    #[test]
+    fn mars_solar_conjunction_blackout() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        set_speed_of_light(DEFAULT_C);
+        let eph = Ephemeris::sample();
+        let earth = eph.trajectory("Earth").unwrap();
+        let mars = eph.trajectory("Mars").unwrap();
+        let day = |d: u32| d as u128 * DAY_NS;
+        let conjunction = day(SAMPLE_CONJUNCTION_DAY);
+
+        let dropping = OcclusionModel {
+            occluders: vec![Occluder::sun()],
+            policy: BlockedPolicy::Drop,
+        };
+        assert!(matches!(dropping.schedule(earth, mars, day(370)), LinkState::Clear { .. }));
+        assert_eq!(
+            dropping.schedule(earth, mars, conjunction),
+            LinkState::Blocked {
+                occluder: "Sun".to_string()
+            }
+        );
+
+        // Held from inside the blackout, the signal leaves once the Sun has moved out of the way.
+        let holding = OcclusionModel {
+            occluders: vec![Occluder::sun()],
+            ..OcclusionModel::default()
+        };
+        match holding.schedule(earth, mars, day(385)) {
+            LinkState::Clear { emission, .. } => {
+                let held_days = (emission - day(385)) as f64 / DAY_NS as f64;
+                assert!((8.0..16.0).contains(&held_days), "held {held_days} days");
+            }
+            other => panic!("expected the link to clear, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn zero_step_hold_drops_instead_of_spinning() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        set_speed_of_light(DEFAULT_C);
+        let eph = Ephemeris::sample();
+        let (earth, mars) = (eph.trajectory("Earth").unwrap(), eph.trajectory("Mars").unwrap());
+
+        let model = OcclusionModel {
+            occluders: vec![Occluder::sun()],
+            policy: BlockedPolicy::Hold {
+                step_ns: 0,
+                max_hold_ns: u128::MAX,
+            },
+        };
+        let conjunction = SAMPLE_CONJUNCTION_DAY as u128 * DAY_NS;
+        assert!(matches!(model.schedule(earth, mars, conjunction), LinkState::Blocked { .. }));
+    }
+}
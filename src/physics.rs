@@ -1,114 +1,275 @@
-use std::cmp::Ordering;
-use std::collections::BinaryHeap;
-use std::time::{Duration, Instant};
-
-use crate::protocol::ProtocolMessage;
-
-/// A message that has been delayed by the simulated speed-of-light constraint.
-///
-/// In Minkowski-KV, network transport can deliver bytes “immediately” (especially on loopback),
-/// but the simulation layer must still enforce relativistic causality. We do that by attaching an
-/// `available_at` timestamp to each received message, based on the sender/receiver separation.
-#[derive(Debug, Clone)]
-pub struct PendingPacket {
-    /// The earliest wall-clock instant at which this message is allowed to enter the application.
-    pub available_at: Instant,
-    /// The decoded protocol payload.
-    pub msg: ProtocolMessage,
-}
-
-impl Eq for PendingPacket {}
-
-impl PartialEq for PendingPacket {
-    fn eq(&self, other: &Self) -> bool {
-        self.available_at.eq(&other.available_at)
-    }
-}
-
-impl PartialOrd for PendingPacket {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        // `BinaryHeap` is a max-heap; we reverse the ordering to pop the earliest deadline first.
-        other.available_at.partial_cmp(&self.available_at)
-    }
-}
-
-impl Ord for PendingPacket {
-    fn cmp(&self, other: &Self) -> Ordering {
-        // `BinaryHeap` is a max-heap; we reverse the ordering to pop the earliest deadline first.
-        other.available_at.cmp(&self.available_at)
-    }
-}
-
-/// Enforces a speed-of-light delivery constraint on incoming protocol messages.
-///
-/// Conceptually, the QUIC layer models “reliable signal transmission”, while this layer models the
-/// *causal* constraint: information can only propagate at (or below) $c$.
-///
-/// Given a sender/receiver separation $d$ (meters) and configured $c$ (m/s), we compute a minimum
-/// propagation delay $\Delta t = d/c$ and buffer the message until:
-///
-/// $t_{arrival} = t_{received} + d/c$
-///
-/// Trade-offs:
-/// - We use `Instant` (monotonic wall clock) rather than event timestamps. That keeps the
-///   implementation deterministic with respect to local scheduling, but it means this layer models
-///   *propagation delay* rather than *coordinate-time transforms*.
-/// - This is a single-process, single-host simulation primitive; a real deployment would measure
-///   distance and time in a shared frame (or use consensus/clock sync) and would not be able to
-///   “cheat” by delaying already-received bytes.
-pub struct PhysicsLayer {
-    buffer: BinaryHeap<PendingPacket>,
-    c: f64,
-}
-
-impl PhysicsLayer {
-    /// Creates a new physics layer with a configured speed of light.
-    ///
-    /// The caller is expected to set `c` to a *simulation-friendly* value; using the physical
-    /// constant ($\approx 3\times 10^8$ m/s) makes local tests look instantaneous at human scales.
-    pub fn new(c: f64) -> Self {
-        Self {
-            buffer: BinaryHeap::new(),
-            c,
-        }
-    }
-
-    /// Ingests a message that was received “on the wire”, and schedules it for causal delivery.
-    ///
-    /// `dist` is the separation between sender and receiver in meters. The transport layer should
-    /// compute this from node coordinates carried by the protocol (e.g., event coordinates).
-    ///
-    /// This method does not block; it records a deadline and returns immediately.
-    pub fn ingest(&mut self, msg: ProtocolMessage, dist: f64) {
-        let delay = dist / self.c;
-        let delay = if delay.is_sign_negative() { 0.0 } else { delay };
-        let available_at = Instant::now() + Duration::from_secs_f64(delay);
-        self.buffer.push(PendingPacket { available_at, msg });
-    }
-
-    /// Drains all messages whose causal deadline has passed.
-    ///
-    /// This is the “light cone gate”: messages outside the receiver's light cone remain buffered
-    /// until their propagation time has elapsed.
-    pub fn drain(&mut self) -> Vec<ProtocolMessage> {
-        let now = Instant::now();
-        let mut ready = Vec::new();
-        while let Some(top) = self.buffer.peek() {
-            if top.available_at <= now {
-                let pkt = self.buffer.pop().expect("peek followed by pop");
-                ready.push(pkt.msg);
-            } else {
-                break;
-            }
-        }
-        ready
-    }
-
-    /// Compatibility alias for the network loop.
-    ///
-    /// The name emphasizes intent: deliver only messages that have *arrived* according to the
-    /// simulated causal model.
-    pub fn drain_arrived(&mut self) -> Vec<ProtocolMessage> {
-        self.drain()
-    }
-}
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use crate::clock::{Clock, SystemClock};
+use crate::protocol::ProtocolMessage;
+use crate::occlusion::{LinkState, OcclusionModel};
+use crate::spacetime::{SpacetimeCoord, Uncertainty};
+use crate::trajectory::Trajectory;
+
+/// A message that has been delayed by the simulated speed-of-light constraint.
+///
+/// `M` is whatever the gate carries: a bare [`ProtocolMessage`] by default, or the message together
+/// with its sender when the receiver needs to reply.
+///
+/// In Minkowski-KV, network transport can deliver bytes “immediately” (especially on loopback),
+/// but the simulation layer must still enforce relativistic causality. We do that by attaching an
+/// `available_at` timestamp to each received message, based on the sender/receiver separation.
+#[derive(Debug, Clone)]
+pub struct PendingPacket<M = ProtocolMessage> {
+    /// The earliest time (ns, on the layer's [`Clock`]) at which this message is allowed to enter
+    /// the application.
+    pub available_at: u128,
+    /// The decoded protocol payload.
+    pub msg: M,
+}
+
+impl<M> Eq for PendingPacket<M> {}
+
+impl<M> PartialEq for PendingPacket<M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.available_at.eq(&other.available_at)
+    }
+}
+
+impl<M> PartialOrd for PendingPacket<M> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<M> Ord for PendingPacket<M> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` is a max-heap; we reverse the ordering to pop the earliest deadline first.
+        other.available_at.cmp(&self.available_at)
+    }
+}
+
+/// What a [`PhysicsLayer`] measures a message's light time from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GateMode {
+    /// Hold each message for its light time after its bytes were received.
+    #[default]
+    Receipt,
+    /// Release each message once the receiver's clock reaches the emission time plus the light
+    /// time, however early or late its bytes turned up. Requires the clock and event coordinates to
+    /// share a frame, and applies only to messages with known emission coordinates (see
+    /// [`PhysicsLayer::ingest_path`]); anything else is still gated from receipt.
+    Coordinate,
+}
+
+/// Enforces a speed-of-light delivery constraint on incoming protocol messages.
+///
+/// Conceptually, the QUIC layer models “reliable signal transmission”, while this layer models the
+/// *causal* constraint: information can only propagate at (or below) $c$.
+///
+/// Given a sender/receiver separation $d$ (meters) and configured $c$ (m/s), we compute a minimum
+/// propagation delay $\Delta t = d/c$ and buffer the message until:
+///
+/// $t_{arrival} = t_{received} + d/c$
+///
+/// Trade-offs:
+/// - We use the time of receipt on an injected [`Clock`] rather than event timestamps. That keeps
+///   the implementation deterministic with respect to local scheduling, but it means this layer
+///   models *propagation delay* rather than *coordinate-time transforms*. The clock is the system
+///   clock by default; tests and replays substitute a manual or simulated one.
+/// - In [`GateMode::Coordinate`] the deadline is instead $t_{emit} + d/c$ on the receiver's clock,
+///   so transport jitter cannot reorder messages the spacetime model has already ordered.
+/// - This is a single-process, single-host simulation primitive; a real deployment would measure
+///   distance and time in a shared frame (or use consensus/clock sync) and would not be able to
+///   “cheat” by delaying already-received bytes.
+pub struct PhysicsLayer<M = ProtocolMessage> {
+    buffer: BinaryHeap<PendingPacket<M>>,
+    c: f64,
+    /// Bodies that can block the line of sight from a sender to this node.
+    occlusion: OcclusionModel,
+    clock: Arc<dyn Clock>,
+    mode: GateMode,
+}
+
+impl<M> PhysicsLayer<M> {
+    /// Creates a new physics layer with a configured speed of light.
+    ///
+    /// The caller is expected to set `c` to a *simulation-friendly* value; using the physical
+    /// constant ($\approx 3\times 10^8$ m/s) makes local tests look instantaneous at human scales.
+    pub fn new(c: f64) -> Self {
+        Self::with_clock(c, OcclusionModel::default(), Arc::new(SystemClock))
+    }
+
+    /// Creates a physics layer that also enforces line-of-sight occlusion (see
+    /// [`Self::ingest_path`]).
+    pub fn with_occlusion(c: f64, occlusion: OcclusionModel) -> Self {
+        Self::with_clock(c, occlusion, Arc::new(SystemClock))
+    }
+
+    /// Creates a physics layer that reads time from `clock` instead of the system clock.
+    pub fn with_clock(c: f64, occlusion: OcclusionModel, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            buffer: BinaryHeap::new(),
+            c,
+            occlusion,
+            clock,
+            mode: GateMode::default(),
+        }
+    }
+
+    /// Chooses what light times are measured from.
+    pub fn set_mode(&mut self, mode: GateMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> GateMode {
+        self.mode
+    }
+
+    /// The clock this layer gates against.
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// When the earliest buffered message becomes deliverable, if any is buffered.
+    pub fn next_deadline(&self) -> Option<u128> {
+        self.buffer.peek().map(|p| p.available_at)
+    }
+
+    /// Ingests a message that was received “on the wire”, and schedules it for causal delivery.
+    ///
+    /// `dist` is the separation between sender and receiver in meters. The transport layer should
+    /// compute this from node coordinates carried by the protocol (e.g., event coordinates).
+    ///
+    /// This method does not block; it records a deadline and returns immediately.
+    pub fn ingest(&mut self, msg: M, dist: f64) {
+        self.ingest_uncertain(msg, dist, Uncertainty::EXACT);
+    }
+
+    /// Like [`Self::ingest`], but for a sender whose coordinates are only known within
+    /// `uncertainty`.
+    ///
+    /// The gate errs on the side of causality: the message is held until it has *definitely*
+    /// arrived, i.e. for the farthest admissible separation plus the clock error.
+    pub fn ingest_uncertain(&mut self, msg: M, dist: f64, uncertainty: Uncertainty) {
+        self.schedule(msg, seconds_to_ns(dist / self.c), uncertainty);
+    }
+
+    /// Ingests a message emitted at `emission` towards a receiver moving along `receiver`.
+    ///
+    /// Unlike [`Self::ingest`], this knows the actual signal path, so it can check it against the
+    /// configured occluders. A blocked message is held until the path clears (the sender is assumed
+    /// to retransmit from where it emitted) or dropped, per the occlusion policy. Returns `false`
+    /// if the message was dropped.
+    pub fn ingest_path(
+        &mut self,
+        msg: M,
+        emission: &SpacetimeCoord,
+        receiver: &Trajectory,
+        uncertainty: Uncertainty,
+    ) -> bool {
+        let sender = Trajectory::Static(*emission);
+        match self.occlusion.schedule_at_c(&sender, receiver, emission.t, self.c) {
+            LinkState::Clear { arrival, .. } => {
+                // Includes any time spent held behind an occluder.
+                let available_at = match self.mode {
+                    GateMode::Receipt => self.clock.now().saturating_add(arrival.saturating_sub(emission.t)),
+                    GateMode::Coordinate => arrival,
+                };
+                self.hold_until(msg, available_at, uncertainty);
+                true
+            }
+            LinkState::Blocked { .. } => false,
+        }
+    }
+
+    /// Buffers `msg` for `delay_ns` after now, widened by the worst case of `uncertainty`.
+    fn schedule(&mut self, msg: M, delay_ns: u128, uncertainty: Uncertainty) {
+        self.hold_until(msg, self.clock.now().saturating_add(delay_ns), uncertainty);
+    }
+
+    /// Buffers `msg` until `available_at`, widened by the worst case of `uncertainty`.
+    fn hold_until(&mut self, msg: M, available_at: u128, uncertainty: Uncertainty) {
+        let available_at = available_at
+            .saturating_add(seconds_to_ns(uncertainty.radius / self.c))
+            .saturating_add(uncertainty.time_ns);
+        self.buffer.push(PendingPacket { available_at, msg });
+    }
+
+    /// Drains all messages whose causal deadline has passed.
+    ///
+    /// This is the “light cone gate”: messages outside the receiver's light cone remain buffered
+    /// until their propagation time has elapsed.
+    pub fn drain(&mut self) -> Vec<M> {
+        let now = self.clock.now();
+        let mut ready = Vec::new();
+        while let Some(top) = self.buffer.peek() {
+            if top.available_at <= now {
+                let pkt = self.buffer.pop().expect("peek followed by pop");
+                ready.push(pkt.msg);
+            } else {
+                break;
+            }
+        }
+        ready
+    }
+
+    /// Compatibility alias for the network loop.
+    ///
+    /// The name emphasizes intent: deliver only messages that have *arrived* according to the
+    /// simulated causal model.
+    pub fn drain_arrived(&mut self) -> Vec<M> {
+        self.drain()
+    }
+}
+
+/// A non-negative duration in seconds, in whole nanoseconds.
+fn seconds_to_ns(seconds: f64) -> u128 {
+    (seconds.max(0.0) * 1e9).round() as u128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn gate_opens_exactly_at_the_light_time_on_the_injected_clock() {
+        let clock = ManualClock::new(1_000);
+        let mut physics = PhysicsLayer::with_clock(100.0, OcclusionModel::default(), clock.clone());
+
+        // 1000 m at 100 m/s: ten seconds.
+        physics.ingest(ProtocolMessage::CustodyAck, 1_000.0);
+        assert_eq!(physics.next_deadline(), Some(1_000 + 10_000_000_000));
+
+        clock.advance(10_000_000_000 - 1);
+        assert!(physics.drain().is_empty());
+        clock.advance(1);
+        assert_eq!(physics.drain().len(), 1);
+        assert_eq!(physics.next_deadline(), None);
+    }
+
+    #[test]
+    fn coordinate_mode_gates_on_emission_time_not_receipt() {
+        const S: u128 = 1_000_000_000;
+        // The layer's own c governs the light time, whatever the global setting.
+        let _c = crate::spacetime::SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        crate::spacetime::set_speed_of_light(crate::spacetime::DEFAULT_C);
+
+        let clock = ManualClock::new(0);
+        let mut physics = PhysicsLayer::with_clock(100.0, OcclusionModel::default(), clock.clone());
+        physics.set_mode(GateMode::Coordinate);
+        let here = Trajectory::Static(SpacetimeCoord { t: 0, x: 0.0, y: 0.0, z: 0.0 });
+        let sent_at = |t| SpacetimeCoord { t, x: 1_000.0, y: 0.0, z: 0.0 };
+        let digest = |n| ProtocolMessage::Digest { heads: vec![[n; 32]] };
+
+        // Emitted 10 s apart, 10 s away, but both delivered by the transport at 12 s.
+        clock.set(12 * S);
+        assert!(physics.ingest_path(digest(2), &sent_at(10 * S), &here, Uncertainty::EXACT));
+        assert!(physics.ingest_path(digest(1), &sent_at(0), &here, Uncertainty::EXACT));
+
+        // The first was due at 10 s and goes out at once; the second waits until 20 s.
+        assert!(matches!(physics.drain()[..], [ProtocolMessage::Digest { ref heads }] if heads[0][0] == 1));
+        clock.set(20 * S - 1);
+        assert!(physics.drain().is_empty());
+        clock.set(20 * S);
+        assert_eq!(physics.drain().len(), 1);
+    }
+}
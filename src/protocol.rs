@@ -1,9 +1,158 @@
-use serde::{Deserialize, Serialize};
-
-use crate::event::Event;
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub enum ProtocolMessage {
-    Gossip(Event),
-    Hello { coords: (f64, f64, f64) },
-}
+//! The replica protocol, shared by every transport and the in-process simulation.
+//!
+//! A session starts with [`ProtocolMessage::Hello`], which carries the sender's heads. From then on
+//! replicas push new writes as [`ProtocolMessage::Gossip`] batches and periodically advertise their
+//! heads in a [`ProtocolMessage::Digest`]. Whenever a replica sees a head or parent it does not
+//! have, it pulls it with [`ProtocolMessage::Request`]; the answer is another gossip batch, carrying
+//! the requested events together with whatever of their causal past the requester lacks, judging
+//! by the heads it last advertised.
+//! [`ProtocolMessage::CustodyAck`] acknowledges that a message has been stored.
+//!
+//! Messages arrive from untrusted peers, so [`decode`] bounds what a single packet can make us
+//! allocate: the message as a whole is capped at [`MAX_MESSAGE_BYTES`], and every event in it at
+//! the [event limits](crate::event::MAX_PARENTS) for parents, payload size and batch nesting.
+
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::event::{Event, EventHash};
+
+/// Largest encoded message accepted from a peer, in bytes.
+pub const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum ProtocolMessage {
+    /// Handshake: the sender's position and the heads of its DAG.
+    Hello { coords: (f64, f64, f64), heads: Vec<EventHash> },
+    /// A batch of events, parents before children.
+    Gossip(Vec<Event>),
+    /// Anti-entropy summary: the heads of the sender's DAG.
+    Digest { heads: Vec<EventHash> },
+    /// Asks the receiver to gossip back the events with these hashes, and their ancestors the
+    /// sender lacks.
+    Request(Vec<EventHash>),
+    /// Reply on the same stream: the receiver has stored the message and taken custody of it.
+    CustodyAck,
+}
+
+impl ProtocolMessage {
+    /// Encoded size in bytes, used to budget transmission time within a contact.
+    pub fn wire_size(&self) -> usize {
+        bincode::serialized_size(self).map_or(0, |s| s as usize)
+    }
+}
+
+/// Packs `events` into as few [`ProtocolMessage::Gossip`] messages as fit under
+/// [`MAX_MESSAGE_BYTES`], keeping their order, so peers never refuse a batch for its size.
+///
+/// An event within the [event limits](crate::event::MAX_PARENTS) always fits in a message of its
+/// own: 256 parents and a 16 KiB payload come to about 25 KiB encoded.
+pub fn gossip_batches(events: Vec<Event>) -> Vec<ProtocolMessage> {
+    let empty = ProtocolMessage::Gossip(Vec::new()).wire_size();
+    let mut batches = Vec::new();
+    let (mut batch, mut size) = (Vec::new(), empty);
+    for event in events {
+        let bytes = bincode::serialized_size(&event).map_or(0, |s| s as usize);
+        if !batch.is_empty() && size + bytes > MAX_MESSAGE_BYTES {
+            batches.push(ProtocolMessage::Gossip(std::mem::take(&mut batch)));
+            size = empty;
+        }
+        size += bytes;
+        batch.push(event);
+    }
+    if !batch.is_empty() {
+        batches.push(ProtocolMessage::Gossip(batch));
+    }
+    batches
+}
+
+/// Encodes a message (or an event) in the wire format.
+pub fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    bincode::serialize(value).expect("protocol types encode to bincode")
+}
+
+/// Decodes bytes received from a peer, refusing inputs longer than [`MAX_MESSAGE_BYTES`], length
+/// prefixes that claim more than that, and trailing garbage.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, bincode::Error> {
+    if bytes.len() > MAX_MESSAGE_BYTES {
+        return Err(Box::new(bincode::ErrorKind::SizeLimit));
+    }
+    bincode::options()
+        .with_fixint_encoding()
+        .with_limit(MAX_MESSAGE_BYTES as u64)
+        .deserialize(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Operation, MAX_NESTING, MAX_PARENTS};
+    use crate::spacetime::SpacetimeCoord;
+    use std::collections::BTreeSet;
+
+    fn event(parents: BTreeSet<EventHash>, payload: Operation) -> Event {
+        Event::new(parents, SpacetimeCoord { t: 1, x: 0.0, y: 0.0, z: 0.0 }, payload)
+    }
+
+    fn nest(depth: usize) -> Operation {
+        (0..depth).fold(Operation::Merge, |op, _| Operation::Batch(vec![op]))
+    }
+
+    #[test]
+    fn decode_limits_reject_oversized_events() {
+        let genesis = BTreeSet::from([Event::genesis().hash]);
+        let ok = event(genesis.clone(), nest(MAX_NESTING));
+        let Ok(ProtocolMessage::Gossip(events)) = decode(&encode(&ProtocolMessage::Gossip(vec![ok.clone()]))) else {
+            panic!("an event within the limits decodes");
+        };
+        assert_eq!(events[0].hash, ok.hash);
+
+        let parents = (0..=MAX_PARENTS as u32).map(|i| blake3::hash(&i.to_le_bytes()).into()).collect();
+        let hostile = [
+            event(parents, Operation::Merge),
+            event(genesis.clone(), Operation::Put("k".into(), vec![0; 20_000])),
+            event(genesis, nest(MAX_NESTING + 1)),
+        ];
+        for ev in hostile {
+            assert!(decode::<ProtocolMessage>(&encode(&ProtocolMessage::Gossip(vec![ev]))).is_err());
+        }
+        assert!(decode::<ProtocolMessage>(&[0; MAX_MESSAGE_BYTES + 1]).is_err());
+    }
+
+    #[test]
+    fn gossip_batches_fit_the_message_limit() {
+        let genesis = BTreeSet::from([Event::genesis().hash]);
+        let events: Vec<Event> = (0..10).map(|i| event(genesis.clone(), Operation::Put(format!("k{i}"), vec![0; 15_000]))).collect();
+        let batches = gossip_batches(events.clone());
+        assert!(batches.len() > 1);
+
+        let mut hashes = Vec::new();
+        for msg in batches {
+            let Ok(ProtocolMessage::Gossip(batch)) = decode(&encode(&msg)) else {
+                panic!("every batch decodes");
+            };
+            hashes.extend(batch.iter().map(|ev| ev.hash));
+        }
+        assert_eq!(hashes, events.iter().map(|ev| ev.hash).collect::<Vec<_>>(), "order is kept");
+        assert!(gossip_batches(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn deeply_nested_batches_fail_without_recursing() {
+        // The payload is encoded last, so thousands of batch headers can be spliced in before its
+        // innermost operation: 12 bytes a level, well within the message size limit.
+        let merge = encode(&Operation::Merge);
+        let batch = encode(&Operation::Batch(vec![Operation::Merge]));
+        let header = &batch[..batch.len() - merge.len()];
+        let mut bytes = encode(&ProtocolMessage::Gossip(vec![event(BTreeSet::new(), Operation::Merge)]));
+        bytes.truncate(bytes.len() - merge.len());
+        for _ in 0..4_000 {
+            bytes.extend_from_slice(header);
+        }
+        bytes.extend_from_slice(&merge);
+
+        let err = decode::<ProtocolMessage>(&bytes).unwrap_err();
+        assert!(err.to_string().contains("nested deeper"), "{err}");
+    }
+}
@@ -0,0 +1,361 @@
+//! Transport-independent replica logic.
+//!
+//! A [`Replica`] owns a DAG and implements the [protocol](crate::protocol) as a pure state
+//! machine: it is handed local writes and inbound messages, and returns the messages it wants sent.
+//! It performs no I/O and reads no clock, so the async [`Node`](crate::node::Node) can drive it
+//! over a real transport while the [`Simulator`](crate::simulator::Simulator) drives the very same
+//! code under virtual time.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+
+use uuid::Uuid;
+
+use crate::dag::{DagError, SpacetimeDAG};
+use crate::event::{Event, EventHash, Operation, MAX_PARENTS};
+use crate::protocol::{gossip_batches, ProtocolMessage};
+use crate::spacetime::{SpacetimeCoord, Uncertainty};
+
+/// Messages a replica wants delivered, each addressed to a peer.
+pub type Outbox<P> = Vec<(P, ProtocolMessage)>;
+
+/// Most received events a replica buffers while waiting for their parents.
+///
+/// Orphans come from untrusted peers and may name parents that do not exist, so past this many the
+/// oldest is dropped; a genuine one is pulled again through the next digest.
+pub const MAX_ORPHANS: usize = 1024;
+
+/// Most hashes asked for in one [`ProtocolMessage::Request`], keeping it far below
+/// [`MAX_MESSAGE_BYTES`](crate::protocol::MAX_MESSAGE_BYTES).
+const REQUEST_HASHES: usize = 1024;
+
+pub struct Replica<P> {
+    pub id: P,
+    pub dag: SpacetimeDAG,
+    pub peers: Vec<P>,
+    /// Received events whose parents have not arrived yet.
+    orphans: Orphans,
+    /// The heads each peer last advertised in a hello or digest.
+    peer_heads: HashMap<P, Vec<EventHash>>,
+}
+
+impl<P: Copy + Eq + Hash> Replica<P> {
+    pub fn new(id: P, peers: Vec<P>) -> Self {
+        Self {
+            id,
+            dag: SpacetimeDAG::new(),
+            peers,
+            orphans: Orphans::default(),
+            peer_heads: HashMap::new(),
+        }
+    }
+
+    /// Opens a session with every peer.
+    pub fn hello(&self, coords: &SpacetimeCoord) -> Outbox<P> {
+        let coords = (coords.x, coords.y, coords.z);
+        self.broadcast(ProtocolMessage::Hello { coords, heads: self.dag.heads.clone() })
+    }
+
+    /// Advertises our heads to every peer so they can pull what they are missing.
+    pub fn sync(&self) -> Outbox<P> {
+        self.broadcast(ProtocolMessage::Digest { heads: self.dag.heads.clone() })
+    }
+
+    /// Applies a local write on top of the current heads and gossips it, with its parents, to
+    /// every peer. `id` becomes the event's identifier; pass a fresh random one outside simulation.
+    ///
+    /// At most [`MAX_PARENTS`] heads are merged per write; the rest are left for the next one. A
+    /// write over the other decode limits is refused with [`DagError::Limit`].
+    pub fn write(&mut self, id: Uuid, coords: SpacetimeCoord, payload: Operation) -> Result<(Event, Outbox<P>), DagError> {
+        let parents: BTreeSet<_> = self.dag.heads.iter().take(MAX_PARENTS).cloned().collect();
+
+        // Collect parent events so peers can ingest without parent-missing failures.
+        let mut gossip: Vec<Event> = parents.iter().filter_map(|p| self.dag.get(p).cloned()).collect();
+
+        let event = Event::with_id(id, parents, coords, Uncertainty::EXACT, payload);
+        self.dag.add_event(event.clone())?;
+        gossip.push(event.clone());
+        let out = gossip_batches(gossip).into_iter().flat_map(|msg| self.broadcast(msg)).collect();
+        Ok((event, out))
+    }
+
+    /// Handles a message from `from`, returning the replies.
+    pub fn handle(&mut self, from: P, msg: ProtocolMessage) -> Outbox<P> {
+        match msg {
+            ProtocolMessage::Gossip(events) => self.ingest(from, events),
+            ProtocolMessage::Hello { heads, .. } => {
+                self.peer_heads.insert(from, heads.clone());
+                let wanted: Vec<EventHash> = heads.into_iter().chain(self.orphan_parents()).collect();
+                let mut out = self.pull_missing(from, wanted);
+                out.push((from, ProtocolMessage::Digest { heads: self.dag.heads.clone() }));
+                out
+            }
+            // Also re-ask for our orphans' parents, in case the earlier request was lost.
+            ProtocolMessage::Digest { heads } => {
+                self.peer_heads.insert(from, heads.clone());
+                let wanted: Vec<EventHash> = heads.into_iter().chain(self.orphan_parents()).collect();
+                self.pull_missing(from, wanted)
+            }
+            ProtocolMessage::Request(hashes) => {
+                let events = self.with_missing_ancestors(from, hashes);
+                gossip_batches(events).into_iter().map(|msg| (from, msg)).collect()
+            }
+            ProtocolMessage::CustodyAck => Vec::new(),
+        }
+    }
+
+    /// Number of received events still waiting for their parents.
+    pub fn orphans(&self) -> usize {
+        self.orphans.len()
+    }
+
+    fn ingest(&mut self, from: P, events: Vec<Event>) -> Outbox<P> {
+        // Popped from the back, so reverse to try parents before children.
+        let mut pending: Vec<Event> = events.into_iter().rev().collect();
+
+        // Every added event may complete the parents of buffered orphans, which are retried next.
+        while let Some(ev) = pending.pop() {
+            let hash = ev.hash;
+            if self.dag.index_map.contains_key(&hash) || self.orphans.contains(&hash) {
+                continue;
+            }
+            let missing: Vec<EventHash> = ev.parents.iter().filter(|p| !self.dag.index_map.contains_key(*p)).copied().collect();
+            if !missing.is_empty() {
+                self.orphans.insert(ev, missing);
+                continue;
+            }
+            // Acausal or oversized events are rejected for good; there is nothing to retry.
+            if self.dag.add_event(ev).is_ok() {
+                pending.extend(self.orphans.release(&hash));
+            }
+        }
+
+        // Ask the sender for the parents our orphans are still waiting on.
+        let wanted: Vec<EventHash> = self.orphan_parents().collect();
+        self.pull_missing(from, wanted)
+    }
+
+    /// The requested events we hold, plus every ancestor of theirs that `peer` lacks judging by the
+    /// heads it last advertised, parents before children.
+    ///
+    /// A replica that missed a stretch of history thus gets all of it in one reply rather than one
+    /// ancestor per round trip, which at interplanetary distances is the difference between one
+    /// light-time exchange and dozens. Requested events are sent even if the peer's heads suggest it
+    /// has them, since it may have restarted empty.
+    fn with_missing_ancestors(&self, peer: P, hashes: Vec<EventHash>) -> Vec<Event> {
+        let mut known = HashSet::from([Event::genesis().hash]);
+        for head in self.peer_heads.get(&peer).into_iter().flatten() {
+            if self.dag.index_map.contains_key(head) {
+                known.insert(*head);
+                known.extend(self.dag.ancestors(head));
+            }
+        }
+
+        let mut wanted = HashSet::new();
+        let mut stack: Vec<EventHash> = hashes.into_iter().filter(|h| self.dag.index_map.contains_key(h)).collect();
+        while let Some(hash) = stack.pop() {
+            if wanted.insert(hash) {
+                let parents = self.dag.get(&hash).map(|ev| ev.parents.iter()).into_iter().flatten();
+                stack.extend(parents.filter(|p| !known.contains(*p)));
+            }
+        }
+
+        // Linearization order puts parents before children.
+        self.dag
+            .linearize()
+            .into_iter()
+            .map(|idx| &self.dag.graph[idx])
+            .filter(|e| wanted.contains(&e.hash))
+            .cloned()
+            .collect()
+    }
+
+    /// Parents our buffered orphans are still waiting on.
+    fn orphan_parents(&self) -> impl Iterator<Item = EventHash> + '_ {
+        self.orphans.waiting.keys().copied()
+    }
+
+    /// Requests from `peer` whichever of `hashes` we neither have nor are holding as orphans.
+    fn pull_missing(&self, peer: P, hashes: impl IntoIterator<Item = EventHash>) -> Outbox<P> {
+        let mut missing: Vec<EventHash> = hashes
+            .into_iter()
+            .filter(|h| !self.dag.index_map.contains_key(h) && !self.orphans.contains(h))
+            .collect();
+        missing.sort();
+        missing.dedup();
+        missing.chunks(REQUEST_HASHES).map(|chunk| (peer, ProtocolMessage::Request(chunk.to_vec()))).collect()
+    }
+
+    fn broadcast(&self, msg: ProtocolMessage) -> Outbox<P> {
+        self.peers.iter().map(|peer| (*peer, msg.clone())).collect()
+    }
+}
+
+/// Received events waiting for their parents, bounded by [`MAX_ORPHANS`] and indexed by the parents
+/// they wait on, so an arriving parent releases its children without rescanning the buffer.
+#[derive(Default)]
+struct Orphans {
+    /// Buffered events with their arrival sequence number.
+    events: HashMap<EventHash, (u64, Event)>,
+    /// Buffered events by arrival, oldest first, for eviction.
+    arrival: BTreeMap<u64, EventHash>,
+    /// For each missing parent, the buffered events waiting on it.
+    waiting: HashMap<EventHash, Vec<EventHash>>,
+    next_seq: u64,
+}
+
+impl Orphans {
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn contains(&self, hash: &EventHash) -> bool {
+        self.events.contains_key(hash)
+    }
+
+    /// Buffers `event` until every parent in `missing` has been added, evicting the oldest orphan
+    /// if the buffer is full.
+    fn insert(&mut self, event: Event, missing: Vec<EventHash>) {
+        if self.contains(&event.hash) {
+            return;
+        }
+        if self.len() >= MAX_ORPHANS {
+            if let Some((_, oldest)) = self.arrival.pop_first() {
+                self.remove(&oldest);
+            }
+        }
+        for parent in missing {
+            self.waiting.entry(parent).or_default().push(event.hash);
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.arrival.insert(seq, event.hash);
+        self.events.insert(event.hash, (seq, event));
+    }
+
+    /// Takes out every orphan that was waiting on `parent`, now that it has been added.
+    fn release(&mut self, parent: &EventHash) -> Vec<Event> {
+        let children = self.waiting.remove(parent).unwrap_or_default();
+        children.iter().filter_map(|child| self.remove(child)).collect()
+    }
+
+    fn remove(&mut self, hash: &EventHash) -> Option<Event> {
+        let (seq, event) = self.events.remove(hash)?;
+        self.arrival.remove(&seq);
+        for parent in &event.parents {
+            if let Some(children) = self.waiting.get_mut(parent) {
+                children.retain(|child| child != hash);
+                if children.is_empty() {
+                    self.waiting.remove(parent);
+                }
+            }
+        }
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{decode, encode, MAX_MESSAGE_BYTES};
+
+    fn at(t: u128) -> SpacetimeCoord {
+        SpacetimeCoord { t, x: 0.0, y: 0.0, z: 0.0 }
+    }
+
+    /// Every message decodes on the receiving side, as a peer would see it.
+    fn assert_deliverable(out: &Outbox<u8>) {
+        for (_, msg) in out {
+            let bytes = encode(msg);
+            assert!(bytes.len() <= MAX_MESSAGE_BYTES);
+            decode::<ProtocolMessage>(&bytes).expect("peers accept every message we send");
+        }
+    }
+
+    #[test]
+    fn writes_and_replies_stay_within_the_decode_limits() {
+        let mut replica = Replica::new(0u8, vec![1]);
+        let oversized = Operation::Put("k".into(), vec![0; 20_000]);
+        assert!(matches!(replica.write(Uuid::new_v4(), at(1), oversized), Err(DagError::Limit(_))));
+        assert_eq!(replica.dag.graph.node_count(), 1, "the refused write is not applied");
+
+        let mut hashes = Vec::new();
+        for t in 1..=10 {
+            let (event, out) = replica.write(Uuid::new_v4(), at(t), Operation::Put("k".into(), vec![0; 15_000])).unwrap();
+            assert_deliverable(&out);
+            hashes.push(event.hash);
+        }
+        let reply = replica.handle(1, ProtocolMessage::Request(hashes));
+        assert!(reply.len() > 1, "ten 15 KiB events do not fit one message");
+        assert_deliverable(&reply);
+    }
+
+    #[test]
+    fn a_write_merges_at_most_max_parents_heads() {
+        let mut replica = Replica::new(0u8, vec![1]);
+        let genesis = BTreeSet::from([replica.dag.heads[0]]);
+        let branches = (0..MAX_PARENTS + 10).map(|_| Event::new(genesis.clone(), at(1), Operation::Merge)).collect();
+        replica.handle(1, ProtocolMessage::Gossip(branches));
+        assert_eq!(replica.dag.heads.len(), MAX_PARENTS + 10);
+
+        let (event, out) = replica.write(Uuid::new_v4(), at(2), Operation::Merge).unwrap();
+        assert_eq!(event.parents.len(), MAX_PARENTS);
+        assert_eq!(replica.dag.heads.len(), 11, "the unmerged heads wait for the next write");
+        assert_deliverable(&out);
+    }
+
+    /// Delivers every message instantly until the replicas fall silent, returning how many
+    /// request/gossip round trips that took.
+    fn exchange(replicas: &mut [Replica<u8>], out: Outbox<u8>, from: u8) -> usize {
+        let mut in_flight: Vec<(u8, u8, ProtocolMessage)> = out.into_iter().map(|(to, msg)| (from, to, msg)).collect();
+        let mut requests = 0;
+        while !in_flight.is_empty() {
+            let mut next = Vec::new();
+            for (from, to, msg) in in_flight {
+                requests += matches!(msg, ProtocolMessage::Request(_)) as usize;
+                let replies = replicas[to as usize].handle(from, msg);
+                next.extend(replies.into_iter().map(|(peer, reply)| (to, peer, reply)));
+            }
+            in_flight = next;
+        }
+        requests
+    }
+
+    #[test]
+    fn one_request_pulls_a_whole_missed_history() {
+        let mut replicas = [Replica::new(0u8, vec![]), Replica::new(1u8, vec![0])];
+        for t in 1..=20 {
+            replicas[0].write(Uuid::new_v4(), at(t), Operation::Put(format!("k{t}"), vec![1])).unwrap();
+        }
+
+        let hello = replicas[1].hello(&at(0));
+        assert_eq!(exchange(&mut replicas, hello, 1), 1, "one round trip for 20 missed writes");
+        assert_eq!(replicas[1].dag.graph.node_count(), 21);
+        assert_eq!(replicas[0].dag.heads, replicas[1].dag.heads);
+
+        // Once it has caught up, a new write's request carries only that write.
+        replicas[0].write(Uuid::new_v4(), at(30), Operation::Merge).unwrap();
+        let digest = replicas[1].sync();
+        assert_eq!(exchange(&mut replicas, digest, 1), 0, "digests only flow one way");
+        let heads = replicas[0].dag.heads.clone();
+        let reply = replicas[0].handle(1, ProtocolMessage::Request(heads));
+        let [(_, ProtocolMessage::Gossip(events))] = reply.as_slice() else {
+            panic!("one gossip reply");
+        };
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn orphans_are_capped() {
+        let mut replica = Replica::new(0u8, vec![1]);
+        let bogus: Vec<Event> = (0..MAX_ORPHANS as u32 + 10)
+            .map(|i| Event::new(BTreeSet::from([*blake3::hash(&i.to_le_bytes()).as_bytes()]), at(1), Operation::Merge))
+            .collect();
+        let first = bogus[0].hash;
+        let out = replica.handle(1, ProtocolMessage::Gossip(bogus));
+        assert_eq!(replica.orphans(), MAX_ORPHANS);
+        assert!(!replica.orphans.contains(&first), "the oldest orphan is evicted");
+        assert_eq!(replica.orphans.waiting.len(), MAX_ORPHANS, "evicted orphans stop waiting");
+        assert_deliverable(&out);
+    }
+}
@@ -0,0 +1,214 @@
+//! Minimum-latency relay routing.
+//!
+//! When the direct line of sight between two nodes is blocked (e.g. Earth and Mars during solar
+//! conjunction), traffic can still flow through relays such as a Lagrange-point satellite. This
+//! module finds the route with the earliest arrival time over the peer table, given node
+//! trajectories, occluding bodies and which links are administratively up.
+//!
+//! Link delays are time-dependent (nodes move, occluders hold traffic), so this is an
+//! earliest-arrival variant of Dijkstra's algorithm: the label of a node is the earliest coordinate
+//! time a signal can reach it, and each hop is scheduled from that time with the light-time
+//! equation and the occlusion model.
+//!
+//! The in-process [`Cluster`](crate::simulation::Cluster) and the
+//! [`Simulator`](crate::simulator::Simulator) forward along these routes hop by hop, each relay
+//! planning the next hop when the message reaches it. The QUIC
+//! [`Network`](crate::network::Network) does not relay: it only talks to peers it can reach
+//! directly.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::contact::ContactPlan;
+use crate::fault::FaultInjector;
+use crate::occlusion::{LinkState, OcclusionModel};
+use crate::protocol::ProtocolMessage;
+use crate::trajectory::Trajectory;
+
+/// A relay path and the coordinate time at which the signal reaches each hop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route<Id> {
+    /// `(node, arrival time in ns)`, starting with the source at its emission time and ending with
+    /// the destination.
+    pub hops: Vec<(Id, u128)>,
+}
+
+impl<Id: Copy> Route<Id> {
+    /// Coordinate time at which the signal reaches the destination.
+    pub fn arrival(&self) -> u128 {
+        self.hops.last().map_or(0, |(_, t)| *t)
+    }
+
+    /// Intermediate nodes, excluding source and destination.
+    pub fn relays(&self) -> Vec<Id> {
+        let inner = self.hops.len().saturating_sub(1);
+        self.hops.iter().take(inner).skip(1).map(|(id, _)| *id).collect()
+    }
+}
+
+/// Undirected set of links that are administratively down. Links are up unless marked otherwise.
+#[derive(Debug, Clone)]
+pub struct LinkTable<Id> {
+    down: HashSet<(Id, Id)>,
+}
+
+impl<Id> Default for LinkTable<Id> {
+    fn default() -> Self {
+        Self {
+            down: HashSet::new(),
+        }
+    }
+}
+
+impl<Id: Copy + Eq + Hash + Ord> LinkTable<Id> {
+    fn key(a: Id, b: Id) -> (Id, Id) {
+        if a <= b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    /// Marks the link between `a` and `b` as up or down.
+    pub fn set_available(&mut self, a: Id, b: Id, up: bool) {
+        if up {
+            self.down.remove(&Self::key(a, b));
+        } else {
+            self.down.insert(Self::key(a, b));
+        }
+    }
+
+    pub fn is_available(&self, a: Id, b: Id) -> bool {
+        !self.down.contains(&Self::key(a, b))
+    }
+}
+
+/// Finds the earliest-arrival route from `from` to `to` for a signal emitted at `t_emit`.
+///
+/// `nodes` is the peer table: every node that may act as a relay, with its trajectory. Returns
+/// `None` if the destination is unreachable (unknown, or cut off by down links and occluders).
+/// Relays are tried in the order of `nodes`, so ties between equally fast routes break the same
+/// way on every run.
+pub fn earliest_arrival<Id: Copy + Eq + Hash + Ord>(
+    nodes: &[(Id, &Trajectory)],
+    occlusion: &OcclusionModel,
+    links: &LinkTable<Id>,
+    from: Id,
+    to: Id,
+    t_emit: u128,
+) -> Option<Route<Id>> {
+    let trajectories: HashMap<Id, &Trajectory> = nodes.iter().copied().collect();
+    if !trajectories.contains_key(&from) || !trajectories.contains_key(&to) {
+        return None;
+    }
+
+    let mut best: HashMap<Id, u128> = HashMap::from([(from, t_emit)]);
+    let mut prev: HashMap<Id, Id> = HashMap::new();
+    let mut queue = BinaryHeap::from([Reverse((t_emit, from))]);
+
+    while let Some(Reverse((t, node))) = queue.pop() {
+        if best.get(&node).is_some_and(|b| *b < t) {
+            continue;
+        }
+        if node == to {
+            break;
+        }
+
+        for &(next, next_trajectory) in nodes {
+            if next == node || !links.is_available(node, next) {
+                continue;
+            }
+            let LinkState::Clear { arrival, .. } = occlusion.schedule(trajectories[&node], next_trajectory, t) else {
+                continue;
+            };
+            if best.get(&next).is_none_or(|b| arrival < *b) {
+                best.insert(next, arrival);
+                prev.insert(next, node);
+                queue.push(Reverse((arrival, next)));
+            }
+        }
+    }
+
+    let arrival = *best.get(&to)?;
+    let mut hops = vec![(to, arrival)];
+    let mut cursor = to;
+    while let Some(&p) = prev.get(&cursor) {
+        hops.push((p, best[&p]));
+        cursor = p;
+    }
+    hops.reverse();
+    Some(Route { hops })
+}
+
+/// Everything a network routes by, borrowed from the in-process
+/// [`Cluster`](crate::simulation::Cluster) or the [`Simulator`](crate::simulator::Simulator), so
+/// both backends pick routes and transmit hops the same way.
+pub struct Topology<'a, Id> {
+    /// The peer table, in the order relays are tried.
+    pub nodes: Vec<(Id, &'a Trajectory)>,
+    pub occlusion: &'a OcclusionModel,
+    pub links: &'a LinkTable<Id>,
+    /// Scheduled contacts; when set, routes follow Contact Graph Routing instead of geometry.
+    pub contacts: Option<&'a ContactPlan<Id>>,
+}
+
+impl<Id: Copy + Eq + Hash + Ord> Topology<'_, Id> {
+    /// The earliest-arrival route from `from` to `to` for `bytes` emitted at `t_emit`, relaying
+    /// where the direct link is blocked, down, or simply slower. With a contact plan, the route
+    /// follows planned contacts only.
+    pub fn route(&self, from: Id, to: Id, t_emit: u128, bytes: usize) -> Option<Route<Id>> {
+        match self.contacts {
+            Some(plan) => plan.route(from, to, t_emit, bytes),
+            None => earliest_arrival(&self.nodes, self.occlusion, self.links, from, to, t_emit),
+        }
+    }
+
+    /// Transmits `msg`, held by `at` since `t`, over the first hop of the best route towards `to`,
+    /// subject to that link's `faults`.
+    ///
+    /// Returns the next hop with every copy that reaches it and its arrival time (none, if the
+    /// faults lost it), or `None` if there is no route. The next hop, when it is not `to`, plans
+    /// the rest of the way once the message reaches it.
+    pub fn hop(&self, faults: &mut FaultInjector<Id>, at: Id, to: Id, t: u128, msg: ProtocolMessage) -> Option<(Id, Vec<(u128, ProtocolMessage)>)> {
+        let route = self.route(at, to, t, msg.wire_size())?;
+        let &(next, arrival) = route.hops.get(1)?;
+        Some((next, faults.transmit(at, next, t, arrival.saturating_sub(t), msg)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::occlusion::{BlockedPolicy, Occluder};
+    use crate::spacetime::{set_speed_of_light, SpacetimeCoord, DEFAULT_C, SPEED_OF_LIGHT_TEST_LOCK};
+
+    const AU: f64 = 1.495_978_707e11;
+
+    fn fixed(x: f64, y: f64) -> Trajectory {
+        Trajectory::Static(SpacetimeCoord { t: 0, x, y, z: 0.0 })
+    }
+
+    #[test]
+    fn conjunction_traffic_goes_through_lagrange_relay() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        set_speed_of_light(DEFAULT_C);
+
+        // Earth and Mars on opposite sides of the Sun; a relay at Earth's L4 point sees both.
+        let (earth, mars, relay) = (fixed(AU, 0.0), fixed(-1.524 * AU, 0.0), fixed(0.5 * AU, 0.866 * AU));
+        let nodes = [("earth", &earth), ("mars", &mars), ("l4", &relay)];
+        let occlusion = OcclusionModel {
+            occluders: vec![Occluder::sun()],
+            policy: BlockedPolicy::Drop,
+        };
+        let mut links = LinkTable::default();
+
+        let route = earliest_arrival(&nodes, &occlusion, &links, "earth", "mars", 0).expect("relay route");
+        assert_eq!(route.relays(), ["l4"]);
+        let earth_l4 = route.hops[1].1;
+        assert!(route.arrival() > earth_l4, "delay accumulates across hops");
+
+        links.set_available("l4", "mars", false);
+        assert!(earliest_arrival(&nodes, &occlusion, &links, "earth", "mars", 0).is_none());
+    }
+}
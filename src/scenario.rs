@@ -0,0 +1,521 @@
+//! Declarative simulation scenarios.
+//!
+//! A scenario file describes a cluster and a script instead of Rust code: who the nodes are and
+//! where, the speed of light, which links are down or faulty, when partitions happen, which writes
+//! are issued at which coordinate times, and what must hold afterwards. `lightcone simulate <file>`
+//! runs it on the deterministic [`Simulator`] (the in-process cluster under virtual time), so a
+//! scenario with a given seed always produces the same report.
+//!
+//! One directive per line; `#` starts a comment. Times are numbers with an optional unit (`ns`,
+//! `us`, `ms`, `s`, `m`, `h`, `d`; seconds if omitted), distances are meters.
+//!
+//! ```text
+//! seed 42                                # randomness for ids and faults (default 0)
+//! c 299792458                            # speed of light in m/s (default: physical)
+//! ephemeris sample                       # or a file; lets nodes follow bodies
+//! node earth body Earth                  # follows an ephemeris body
+//! node relay at 1.2e11 8e10 0            # stays put
+//! contacts plan.csv                      # route over a contact plan (node names as ids)
+//! link earth mars down                   # administratively down; traffic is relayed around it
+//! faults * * loss=0.1 duplicate=0.05     # default faults; or name a link: faults earth mars ...
+//! faults earth mars reorder=0.2 window=1h corrupt=0.01 bandwidth=2000
+//! partition earth,moon 10h 30h [hold]    # isolate a group; drop (default) or hold traffic
+//! sync every 1h [until 48h]              # anti-entropy rounds by every node
+//! write 1h earth put greeting hello      # also: delete <key>, append <key> <value>, incr <key> <n>
+//! read 2h mars greeting                  # recorded in the history for `expect consistent`
+//! expect at 20h diverged                 # checked when the clock reaches 20h
+//! expect converged                       # checked at the end of the run
+//! expect mars greeting = hello           # or: expect mars greeting absent
+//! expect consistent                      # the history checker finds no anomaly (end of run only)
+//! run until 48h                          # default: until nothing is left to do
+//! ```
+//!
+//! Relative file paths are resolved against the scenario file's directory.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::contact::{ContactPlan, ContactPlanError};
+use crate::ephemeris::{Ephemeris, EphemerisError};
+use crate::event::Operation;
+use crate::fault::{FaultStats, LinkFaults, Partition, PartitionPolicy};
+use crate::simulator::{SimStats, Simulator};
+use crate::spacetime::{set_speed_of_light, SpacetimeCoord, DEFAULT_C};
+use crate::state::Value;
+use crate::trajectory::Trajectory;
+
+#[derive(Debug, Error)]
+pub enum ScenarioError {
+    #[error("failed to read scenario: {0}")]
+    Io(#[from] std::io::Error),
+    /// A directive could not be parsed.
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+    #[error(transparent)]
+    Ephemeris(#[from] EphemerisError),
+    #[error(transparent)]
+    Contacts(#[from] ContactPlanError),
+}
+
+/// Something a scenario asserts about the cluster.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Check {
+    /// Every replica has the same heads and state.
+    Converged,
+    /// At least two replicas differ.
+    Diverged,
+    /// `node` holds `value` (rendered as text) under `key`, or nothing if `value` is `None`.
+    Value { node: String, key: String, value: Option<String> },
+    /// The recorded reads and writes satisfy the [history checker](crate::history).
+    Consistent,
+}
+
+/// A check and when to evaluate it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expectation {
+    /// Coordinate time (ns), or `None` for the end of the run.
+    pub at: Option<u128>,
+    pub check: Check,
+    /// The directive as written, for the report.
+    pub source: String,
+}
+
+/// A parsed scenario.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub seed: u64,
+    pub c: f64,
+    /// In declaration order, which is also the order node ids are drawn from the seed.
+    pub nodes: Vec<(String, Trajectory)>,
+    pub contacts: Option<ContactPlan<String>>,
+    pub links_down: Vec<(String, String)>,
+    /// `None` for the default faults of every link.
+    pub faults: Vec<(Option<(String, String)>, LinkFaults)>,
+    pub partitions: Vec<Partition<String>>,
+    /// `(period, until)`: anti-entropy every `period` until `until` (or the end of the run).
+    pub syncs: Vec<(u128, Option<u128>)>,
+    pub writes: Vec<(u128, String, Operation)>,
+    /// `(at, node, key)`.
+    pub reads: Vec<(u128, String, String)>,
+    pub expectations: Vec<Expectation>,
+    pub until: Option<u128>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            c: DEFAULT_C,
+            nodes: Vec::new(),
+            contacts: None,
+            links_down: Vec::new(),
+            faults: Vec::new(),
+            partitions: Vec::new(),
+            syncs: Vec::new(),
+            writes: Vec::new(),
+            reads: Vec::new(),
+            expectations: Vec::new(),
+            until: None,
+        }
+    }
+}
+
+impl Scenario {
+    /// Parses a scenario, resolving relative paths against the working directory.
+    pub fn parse(text: &str) -> Result<Self, ScenarioError> {
+        Self::parse_in(text, Path::new("."))
+    }
+
+    /// Reads and parses a scenario file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        let path = path.as_ref();
+        let base = path.parent().unwrap_or(Path::new("."));
+        Self::parse_in(&std::fs::read_to_string(path)?, base)
+    }
+
+    fn parse_in(text: &str, base: &Path) -> Result<Self, ScenarioError> {
+        let mut scenario = Scenario::default();
+        let mut ephemeris: Option<Ephemeris> = None;
+        let resolve = |file: &str| -> PathBuf { base.join(file) };
+
+        for (idx, raw) in text.lines().enumerate() {
+            let line = raw.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |message: String| ScenarioError::Parse { line: idx + 1, message };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let known = |name: &str| {
+                if scenario.nodes.iter().any(|(n, _)| n == name) {
+                    Ok(name.to_string())
+                } else {
+                    Err(err(format!("unknown node {name:?}")))
+                }
+            };
+            let fresh = |name: &str| {
+                if scenario.nodes.iter().any(|(n, _)| n == name) {
+                    Err(err(format!("duplicate node {name:?}")))
+                } else {
+                    Ok(name.to_string())
+                }
+            };
+
+            match words[..] {
+                ["seed", seed] => scenario.seed = seed.parse().map_err(|_| err(format!("invalid seed {seed:?}")))?,
+                ["c", c] => scenario.c = number(c).filter(|c| *c > 0.0).ok_or_else(|| err(format!("invalid c {c:?}")))?,
+                ["ephemeris", "sample"] => ephemeris = Some(Ephemeris::sample()),
+                ["ephemeris", file] => ephemeris = Some(Ephemeris::load(resolve(file))?),
+                ["node", name, "at", x, y, z] => {
+                    let coord = |v: &str| number(v).ok_or_else(|| err(format!("invalid coordinate {v:?}")));
+                    let name = fresh(name)?;
+                    let at = SpacetimeCoord { t: 0, x: coord(x)?, y: coord(y)?, z: coord(z)? };
+                    scenario.nodes.push((name, Trajectory::Static(at)));
+                }
+                ["node", name, "body", body] => {
+                    let name = fresh(name)?;
+                    let ephemeris = ephemeris.as_ref().ok_or_else(|| err("`node ... body` needs an `ephemeris` first".into()))?;
+                    scenario.nodes.push((name, ephemeris.trajectory(body)?.clone()));
+                }
+                ["contacts", file] => {
+                    let plan = ContactPlan::load(resolve(file))?;
+                    for contact in &plan.contacts {
+                        known(&contact.from)?;
+                        known(&contact.to)?;
+                    }
+                    scenario.contacts = Some(plan);
+                }
+                ["link", a, b, "down"] => scenario.links_down.push((known(a)?, known(b)?)),
+                ["faults", a, b, ref settings @ ..] => {
+                    let link = match (a, b) {
+                        ("*", "*") => None,
+                        _ => Some((known(a)?, known(b)?)),
+                    };
+                    scenario.faults.push((link, link_faults(settings).map_err(err)?));
+                }
+                ["partition", group, start, end, ref policy @ ..] => {
+                    let isolated = group.split(',').map(known).collect::<Result<Vec<_>, _>>()?;
+                    let (start, end) = (time(start).ok_or_else(|| err(format!("invalid time {start:?}")))?, time(end).ok_or_else(|| err(format!("invalid time {end:?}")))?);
+                    let policy = match policy {
+                        [] | ["drop"] => PartitionPolicy::Drop,
+                        ["hold"] => PartitionPolicy::Hold,
+                        _ => return Err(err("expected `drop` or `hold` after the partition times".into())),
+                    };
+                    scenario.partitions.push(Partition { isolated: isolated.into_iter().collect(), during: start..end, policy });
+                }
+                ["sync", "every", period] | ["sync", "every", period, "until", _] => {
+                    let period = time(period).filter(|p| *p > 0).ok_or_else(|| err(format!("invalid period {period:?}")))?;
+                    let until = match words.get(4) {
+                        Some(t) => Some(time(t).ok_or_else(|| err(format!("invalid time {t:?}")))?),
+                        None => None,
+                    };
+                    scenario.syncs.push((period, until));
+                }
+                ["write", at, node, ref op @ ..] => {
+                    let at = time(at).ok_or_else(|| err(format!("invalid time {at:?}")))?;
+                    let payload = operation(op).map_err(err)?;
+                    scenario.writes.push((at, known(node)?, payload));
+                }
+                ["read", at, node, key] => {
+                    let at = time(at).ok_or_else(|| err(format!("invalid time {at:?}")))?;
+                    scenario.reads.push((at, known(node)?, key.to_string()));
+                }
+                ["expect", ref rest @ ..] => {
+                    let (at, check) = match rest {
+                        ["at", t, ref check @ ..] => (Some(time(t).ok_or_else(|| err(format!("invalid time {t:?}")))?), check),
+                        check => (None, check),
+                    };
+                    let check = match check {
+                        ["converged"] => Check::Converged,
+                        ["diverged"] => Check::Diverged,
+                        // Mid-run, replicas are expected to differ, which the checker reports.
+                        ["consistent"] if at.is_some() => return Err(err("`expect consistent` is only checked at the end".into())),
+                        ["consistent"] => Check::Consistent,
+                        [node, key, "absent"] => Check::Value { node: known(node)?, key: key.to_string(), value: None },
+                        [node, key, "=", ref value @ ..] if !value.is_empty() => {
+                            Check::Value { node: known(node)?, key: key.to_string(), value: Some(value.join(" ")) }
+                        }
+                        _ => return Err(err(format!("unknown expectation {line:?}"))),
+                    };
+                    scenario.expectations.push(Expectation { at, check, source: line.to_string() });
+                }
+                ["run", "until", t] => scenario.until = Some(time(t).ok_or_else(|| err(format!("invalid time {t:?}")))?),
+                _ => return Err(err(format!("unknown directive {line:?}"))),
+            }
+        }
+
+        if scenario.syncs.iter().any(|(_, until)| until.is_none()) && scenario.until.is_none() {
+            return Err(ScenarioError::Parse {
+                line: 0,
+                message: "`sync every` without `until` needs a `run until`".into(),
+            });
+        }
+        Ok(scenario)
+    }
+
+    /// Runs the scenario to completion and evaluates its expectations.
+    ///
+    /// This sets the process-wide speed of light to the scenario's `c`.
+    pub fn run(&self) -> Report {
+        set_speed_of_light(self.c);
+        let mut sim = Simulator::new(self.seed);
+        let ids: HashMap<&str, Uuid> = self
+            .nodes
+            .iter()
+            .map(|(name, trajectory)| (name.as_str(), sim.add_node(trajectory.clone())))
+            .collect();
+        let id = |name: &String| ids[name.as_str()];
+
+        if let Some(plan) = &self.contacts {
+            sim.contacts = Some(plan.resolve(|name| ids.get(name).copied()).expect("contacts are validated on parse"));
+        }
+        for (a, b) in &self.links_down {
+            sim.links.set_available(id(a), id(b), false);
+        }
+        for (link, faults) in &self.faults {
+            match link {
+                None => sim.faults.default = *faults,
+                Some((a, b)) => sim.faults.set_link(id(a), id(b), *faults),
+            }
+        }
+        for cut in &self.partitions {
+            sim.faults.partitions.push(Partition {
+                isolated: cut.isolated.iter().map(id).collect(),
+                during: cut.during.clone(),
+                policy: cut.policy,
+            });
+        }
+        for (period, until) in &self.syncs {
+            sim.sync_every(*period, until.or(self.until).expect("validated on parse"));
+        }
+        for (at, node, payload) in &self.writes {
+            sim.write_at(*at, id(node), payload.clone());
+        }
+        for (at, node, key) in &self.reads {
+            sim.read_at(*at, id(node), key);
+        }
+
+        let mut expectations: Vec<&Expectation> = self.expectations.iter().collect();
+        // Timed checks in time order, then the end-of-run ones; stable, so ties keep file order.
+        expectations.sort_by_key(|e| (e.at.is_none(), e.at));
+
+        let mut outcomes = Vec::new();
+        let mut finished = false;
+        for expectation in expectations {
+            match (expectation.at, finished) {
+                (Some(at), _) => sim.run_until(at),
+                (None, false) => {
+                    match self.until {
+                        Some(until) => sim.run_until(until),
+                        None => sim.run(),
+                    }
+                    finished = true;
+                }
+                (None, true) => {}
+            }
+            let result = evaluate(&sim, &ids, &expectation.check);
+            outcomes.push(Outcome { source: expectation.source.clone(), at: sim.now(), result });
+        }
+        if !finished {
+            match self.until {
+                Some(until) => sim.run_until(until),
+                None => sim.run(),
+            }
+        }
+
+        Report {
+            end: sim.now(),
+            stats: sim.stats,
+            faults: sim.faults.stats,
+            outcomes,
+        }
+    }
+}
+
+/// The result of one expectation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub source: String,
+    /// Coordinate time (ns) at which it was evaluated.
+    pub at: u128,
+    /// `Err` explains what was found instead.
+    pub result: Result<(), String>,
+}
+
+/// What a scenario run did and whether its expectations held.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Coordinate time (ns) at which the run ended.
+    pub end: u128,
+    pub stats: SimStats,
+    pub faults: FaultStats,
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.outcomes.iter().all(|o| o.result.is_ok())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hours = |ns: u128| ns as f64 / 3.6e12;
+        writeln!(f, "simulated {:.2} h", hours(self.end))?;
+        let s = &self.stats;
+        writeln!(f, "writes: {} ({} rejected), messages delivered: {}, unroutable: {}", s.writes, s.rejected_writes, s.delivered, s.unroutable)?;
+        let x = &self.faults;
+        writeln!(
+            f,
+            "faults: {} lost, {} duplicated, {} reordered, {} corrupted, {} partitioned, {} held",
+            x.lost, x.duplicated, x.reordered, x.corrupted, x.partitioned, x.held
+        )?;
+        for outcome in &self.outcomes {
+            match &outcome.result {
+                Ok(()) => writeln!(f, "  ok    {:>9.2} h  {}", hours(outcome.at), outcome.source)?,
+                Err(why) => writeln!(f, "  FAIL  {:>9.2} h  {}: {}", hours(outcome.at), outcome.source, why)?,
+            }
+        }
+        let failed = self.outcomes.iter().filter(|o| o.result.is_err()).count();
+        write!(f, "{} of {} expectations held", self.outcomes.len() - failed, self.outcomes.len())
+    }
+}
+
+fn evaluate(sim: &Simulator, ids: &HashMap<&str, Uuid>, check: &Check) -> Result<(), String> {
+    match check {
+        Check::Converged => sim.check_convergence().map_err(|d| d.to_string()),
+        Check::Diverged => match sim.check_convergence() {
+            Ok(()) => Err("all replicas agree".into()),
+            Err(_) => Ok(()),
+        },
+        Check::Consistent => match sim.check_history() {
+            anomalies if anomalies.is_empty() => Ok(()),
+            anomalies => Err(anomalies.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")),
+        },
+        Check::Value { node, key, value } => {
+            let replica = sim.replica(ids[node.as_str()]).expect("scenario nodes exist");
+            let actual = replica.dag.state().get(key).map(render);
+            if actual == *value {
+                Ok(())
+            } else {
+                Err(format!("found {}", actual.as_deref().unwrap_or("nothing")))
+            }
+        }
+    }
+}
+
+/// A value as a scenario writes it: text for registers, a number for counters.
+fn render(value: &Value) -> String {
+    match value {
+        Value::Bytes(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        Value::Counter(n) => n.to_string(),
+        Value::List(items) => items.iter().map(|i| String::from_utf8_lossy(i)).collect::<Vec<_>>().join(","),
+        other => format!("{other:?}"),
+    }
+}
+
+fn number(text: &str) -> Option<f64> {
+    text.parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+/// A non-negative time in ns, from a number with an optional unit (seconds by default).
+///
+/// The unit is the trailing run of letters, so an exponent in the number (`1e3s`) stays with it.
+fn time(text: &str) -> Option<u128> {
+    let split = text.trim_end_matches(|c: char| c.is_ascii_alphabetic()).len();
+    let (value, unit) = text.split_at(split);
+    let scale = match unit {
+        "ns" => 1.0,
+        "us" => 1e3,
+        "ms" => 1e6,
+        "" | "s" => 1e9,
+        "m" => 60e9,
+        "h" => 3_600e9,
+        "d" => 86_400e9,
+        _ => return None,
+    };
+    number(value).filter(|v| *v >= 0.0).map(|v| (v * scale).round() as u128)
+}
+
+fn operation(words: &[&str]) -> Result<Operation, String> {
+    match words {
+        ["put", key, ref value @ ..] if !value.is_empty() => Ok(Operation::Put(key.to_string(), value.join(" ").into_bytes())),
+        ["delete", key] => Ok(Operation::Delete(key.to_string())),
+        ["append", key, ref value @ ..] if !value.is_empty() => Ok(Operation::Append(key.to_string(), value.join(" ").into_bytes())),
+        ["incr", key, delta] => delta.parse().map(|d| Operation::Increment(key.to_string(), d)).map_err(|_| format!("invalid delta {delta:?}")),
+        _ => Err(format!("unknown operation {:?}", words.join(" "))),
+    }
+}
+
+fn link_faults(settings: &[&str]) -> Result<LinkFaults, String> {
+    let mut faults = LinkFaults::default();
+    for setting in settings {
+        let (name, value) = setting.split_once('=').ok_or_else(|| format!("expected name=value, got {setting:?}"))?;
+        let probability = || number(value).filter(|p| (0.0..=1.0).contains(p)).ok_or_else(|| format!("invalid probability {value:?}"));
+        match name {
+            "loss" => faults.loss = probability()?,
+            "duplicate" => faults.duplicate = probability()?,
+            "reorder" => faults.reorder = probability()?,
+            "corrupt" => faults.corrupt = probability()?,
+            "window" => faults.reorder_window_ns = time(value).ok_or_else(|| format!("invalid window {value:?}"))?,
+            "bandwidth" => faults.bandwidth = Some(number(value).filter(|b| *b > 0.0).ok_or_else(|| format!("invalid bandwidth {value:?}"))?),
+            _ => return Err(format!("unknown fault {name:?}")),
+        }
+    }
+    Ok(faults)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spacetime::SPEED_OF_LIGHT_TEST_LOCK;
+
+    #[test]
+    fn bundled_partition_scenario_passes() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/scenarios/earth_moon_mars_partition.scenario");
+        let report = Scenario::load(path).unwrap().run();
+        assert!(report.passed(), "{report}");
+        assert!(report.faults.partitioned > 0);
+    }
+
+    #[test]
+    fn failed_expectations_and_bad_directives_are_reported() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        let scenario = Scenario::parse(
+            "c 100\n\
+             node a at 0 0 0\n\
+             node b at 1000 0 0\n\
+             write 0 a put k v\n\
+             expect at 5s b k = v   # 10 s away\n\
+             expect b k = v\n",
+        )
+        .unwrap();
+        let report = scenario.run();
+        assert_eq!(report.outcomes[0].result, Err("found nothing".into()));
+        assert_eq!(report.outcomes[1].result, Ok(()));
+        assert!(!report.passed());
+
+        let err = Scenario::parse("node a at 0 0 0\nwrite 1h b put k v\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unknown node \"b\"");
+    }
+
+    #[test]
+    fn duplicate_nodes_are_refused() {
+        let err = Scenario::parse("node a at 0 0 0\nnode a at 1 0 0\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: duplicate node \"a\"");
+    }
+
+    #[test]
+    fn times_take_exponents_and_a_trailing_unit() {
+        assert_eq!(time("1e3"), Some(1_000_000_000_000));
+        assert_eq!(time("1e3s"), Some(1_000_000_000_000));
+        assert_eq!(time("2.5e-1ms"), Some(250_000));
+        assert_eq!(time("90m"), Some(5_400_000_000_000));
+        assert_eq!(time("1e3x"), None);
+        assert_eq!(time("-1s"), None);
+    }
+}
@@ -0,0 +1,536 @@
+//! Deterministic discrete-event simulation of a replica cluster.
+//!
+//! Where [`Cluster`](crate::simulation::Cluster) delivers messages with real `tokio` timers, the
+//! [`Simulator`] keeps a virtual clock and a priority queue of scheduled deliveries, writes and
+//! syncs. Popping the next item jumps the clock straight to it, so hours of interplanetary light
+//! time cost nothing to simulate. Node and event identifiers are drawn from a seeded generator and
+//! ties are broken by scheduling order, so a run is fully determined by its seed and inputs.
+//!
+//! Nodes run the same [`Replica`] state machine as the async [`Node`](crate::node::Node); the
+//! simulator only decides when (and whether) each message arrives, using the same routing and
+//! [fault injection](crate::fault) as the cluster.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::clock::{Clock, SimulatedClock};
+use crate::contact::ContactPlan;
+use crate::event::{EventHash, Operation};
+use crate::fault::FaultInjector;
+use crate::history::{Anomaly, History};
+use crate::occlusion::OcclusionModel;
+use crate::protocol::ProtocolMessage;
+use crate::replica::{Outbox, Replica};
+use crate::routing::{LinkTable, Route, Topology};
+use crate::trajectory::Trajectory;
+
+/// SplitMix64: a tiny, fast generator whose whole state is its seed.
+#[derive(Debug, Clone)]
+pub struct SimRng(u64);
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A version 4 UUID built from the generator's output.
+    pub fn uuid(&mut self) -> Uuid {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.next_u64().to_le_bytes());
+        bytes[8..].copy_from_slice(&self.next_u64().to_le_bytes());
+        uuid::Builder::from_random_bytes(bytes).into_uuid()
+    }
+}
+
+/// Something the simulator will do at a given coordinate time.
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// `msg` from `from` reaches `to`.
+    Deliver { from: Uuid, to: Uuid, msg: ProtocolMessage },
+    /// `msg` from `from` to `to` reaches the relay `via`, which sends it on.
+    Relay { from: Uuid, via: Uuid, to: Uuid, msg: ProtocolMessage },
+    /// `node` applies a local write.
+    Write { node: Uuid, payload: Operation },
+    /// `node` advertises its heads to its peers.
+    Sync { node: Uuid },
+    /// A client reads `key` from `node`.
+    Read { node: Uuid, key: String },
+}
+
+struct Scheduled {
+    at: u128,
+    /// Insertion order, so simultaneous actions run first-scheduled first.
+    seq: u64,
+    action: Action,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    /// Reversed, so the max-heap pops the earliest action.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+/// How two replicas differ, as found by [`Simulator::check_convergence`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    Heads { reference: Uuid, node: Uuid },
+    /// The states differ at `key` (empty if one state merely has more keys than the other).
+    State { reference: Uuid, node: Uuid, key: String },
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Heads { reference, node } => write!(f, "{node} has different heads from {reference}"),
+            Self::State { reference, node, key } => write!(f, "{node} disagrees with {reference} on key {key:?}"),
+        }
+    }
+}
+
+/// Counters describing a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimStats {
+    pub delivered: u64,
+    /// Messages dropped because no route to the receiver existed when they were sent.
+    pub unroutable: u64,
+    pub writes: u64,
+    /// Local writes the replica refused (see [`DagError`](crate::dag::DagError)).
+    pub rejected_writes: u64,
+}
+
+pub struct SimNode {
+    pub replica: Replica<Uuid>,
+    pub trajectory: Trajectory,
+}
+
+pub struct Simulator {
+    clock: Arc<SimulatedClock>,
+    seq: u64,
+    pub rng: SimRng,
+    queue: BinaryHeap<Scheduled>,
+    /// Ordered by id, so iteration (and therefore routing) is the same on every run.
+    nodes: BTreeMap<Uuid, SimNode>,
+    /// Bodies that block line of sight between nodes (none by default).
+    pub occlusion: OcclusionModel,
+    /// Links that are administratively down; messages are relayed around them.
+    pub links: LinkTable<Uuid>,
+    /// Scheduled contacts; when set, routes follow Contact Graph Routing instead of geometry.
+    pub contacts: Option<ContactPlan<Uuid>>,
+    /// Loss, duplication, reordering, corruption and bandwidth limits per link (none by default).
+    pub faults: FaultInjector<Uuid>,
+    /// Every client write and read, for [`Self::check_history`].
+    pub history: History,
+    pub stats: SimStats,
+}
+
+impl Simulator {
+    /// An empty simulation at coordinate time zero whose randomness all derives from `seed`.
+    pub fn new(seed: u64) -> Self {
+        let mut rng = SimRng::new(seed);
+        Self {
+            faults: FaultInjector::new(rng.next_u64()),
+            history: History::new(),
+            clock: SimulatedClock::new(),
+            seq: 0,
+            rng,
+            queue: BinaryHeap::new(),
+            nodes: BTreeMap::new(),
+            occlusion: OcclusionModel::default(),
+            links: LinkTable::default(),
+            contacts: None,
+            stats: SimStats::default(),
+        }
+    }
+
+    /// Current virtual coordinate time (ns).
+    pub fn now(&self) -> u128 {
+        self.clock.now()
+    }
+
+    /// The virtual clock, for components (such as a [`PhysicsLayer`](crate::physics::PhysicsLayer))
+    /// that should gate on simulated rather than wall-clock time.
+    pub fn clock(&self) -> Arc<SimulatedClock> {
+        self.clock.clone()
+    }
+
+    /// Adds a node moving along `trajectory`, peered with every existing node.
+    pub fn add_node(&mut self, trajectory: Trajectory) -> Uuid {
+        let id = self.rng.uuid();
+        let peers: Vec<Uuid> = self.nodes.keys().copied().collect();
+        for node in self.nodes.values_mut() {
+            node.replica.peers.push(id);
+        }
+        self.nodes.insert(id, SimNode { replica: Replica::new(id, peers), trajectory });
+        id
+    }
+
+    pub fn node_ids(&self) -> Vec<Uuid> {
+        self.nodes.keys().copied().collect()
+    }
+
+    pub fn replica(&self, id: Uuid) -> Option<&Replica<Uuid>> {
+        self.nodes.get(&id).map(|n| &n.replica)
+    }
+
+    /// Whether every replica has the same heads and the same materialized state.
+    pub fn converged(&self) -> bool {
+        self.check_convergence().is_ok()
+    }
+
+    /// Compares every replica against the first, reporting the first difference found.
+    pub fn check_convergence(&self) -> Result<(), Divergence> {
+        let mut nodes = self.nodes.iter();
+        let Some((&reference, first)) = nodes.next() else {
+            return Ok(());
+        };
+        // Heads are kept in arrival order, so compare them as sets.
+        let heads = |replica: &Replica<Uuid>| replica.dag.heads.iter().copied().collect::<BTreeSet<_>>();
+        let (expected_heads, expected) = (heads(&first.replica), first.replica.dag.state());
+        for (&node, other) in nodes {
+            if heads(&other.replica) != expected_heads {
+                return Err(Divergence::Heads { reference, node });
+            }
+            let actual = other.replica.dag.state();
+            let mismatch = expected.scan("").zip(actual.scan("")).find(|(a, b)| a != b);
+            if let Some(((key, _), _)) = mismatch {
+                return Err(Divergence::State { reference, node, key: key.to_owned() });
+            }
+            if expected.len() != actual.len() {
+                return Err(Divergence::State { reference, node, key: String::new() });
+            }
+        }
+        Ok(())
+    }
+
+    /// Schedules `action` at coordinate time `at` (or now, if `at` has passed).
+    pub fn schedule(&mut self, at: u128, action: Action) {
+        let seq = self.seq;
+        self.seq += 1;
+        self.queue.push(Scheduled { at: at.max(self.now()), seq, action });
+    }
+
+    /// Schedules a write of `payload` by `node` at `at`.
+    pub fn write_at(&mut self, at: u128, node: Uuid, payload: Operation) {
+        self.schedule(at, Action::Write { node, payload });
+    }
+
+    /// Schedules a client read of `key` from `node` at `at`, recorded in the history.
+    pub fn read_at(&mut self, at: u128, node: Uuid, key: &str) {
+        self.schedule(at, Action::Read { node, key: key.to_string() });
+    }
+
+    /// Checks the recorded history for consistency anomalies, including convergence of the
+    /// replicas as they stand now.
+    pub fn check_history(&self) -> Vec<Anomaly> {
+        let finals: Vec<(Uuid, Vec<EventHash>)> = self.nodes.iter().map(|(id, n)| (*id, n.replica.dag.heads.clone())).collect();
+        self.history.check(&finals)
+    }
+
+    /// Schedules an anti-entropy round by `node` at `at`.
+    pub fn sync_at(&mut self, at: u128, node: Uuid) {
+        self.schedule(at, Action::Sync { node });
+    }
+
+    /// Schedules an anti-entropy round by every node each `period`, from now until `until`.
+    pub fn sync_every(&mut self, period: u128, until: u128) {
+        let mut at = self.now() + period;
+        while at <= until {
+            for node in self.node_ids() {
+                self.sync_at(at, node);
+            }
+            at += period;
+        }
+    }
+
+    /// Runs the next scheduled action, advancing the clock to it. Returns `false` when idle.
+    pub fn step(&mut self) -> bool {
+        let Some(Scheduled { at, action, .. }) = self.queue.pop() else {
+            return false;
+        };
+        self.clock.advance_to(at);
+        match action {
+            Action::Deliver { from, to, msg } => {
+                let Some(node) = self.nodes.get_mut(&to) else {
+                    return true;
+                };
+                self.stats.delivered += 1;
+                let out = node.replica.handle(from, msg);
+                self.send(to, out);
+            }
+            Action::Relay { from, via, to, msg } => self.forward(from, via, to, msg),
+            Action::Write { node, payload } => {
+                let Some(n) = self.nodes.get_mut(&node) else {
+                    return true;
+                };
+                let coords = n.trajectory.position_at(at);
+                let id = self.rng.uuid();
+                self.stats.writes += 1;
+                match n.replica.write(id, coords, payload) {
+                    Ok((event, out)) => {
+                        self.history.write(node, at, event);
+                        self.send(node, out);
+                    }
+                    Err(_) => self.stats.rejected_writes += 1,
+                }
+            }
+            Action::Sync { node } => {
+                if let Some(n) = self.nodes.get(&node) {
+                    let out = n.replica.sync();
+                    self.send(node, out);
+                }
+            }
+            Action::Read { node, key } => {
+                if let Some(n) = self.nodes.get(&node) {
+                    let dag = &n.replica.dag;
+                    let observed = dag.state().get(&key).cloned();
+                    self.history.read(node, at, &key, dag.heads.clone(), observed);
+                }
+            }
+        }
+        true
+    }
+
+    /// Runs every action scheduled up to and including `t`, then sets the clock to `t`.
+    pub fn run_until(&mut self, t: u128) {
+        while self.queue.peek().is_some_and(|s| s.at <= t) {
+            self.step();
+        }
+        self.clock.advance_to(t);
+    }
+
+    /// Runs until nothing is left to do.
+    pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    /// The earliest-arrival route for `bytes` emitted by `from` at `t_emit`, as
+    /// [`Cluster::route`](crate::simulation::Cluster::route) computes it.
+    pub fn route(&self, from: Uuid, to: Uuid, t_emit: u128, bytes: usize) -> Option<Route<Uuid>> {
+        topology(&self.nodes, &self.occlusion, &self.links, self.contacts.as_ref()).route(from, to, t_emit, bytes)
+    }
+
+    fn send(&mut self, from: Uuid, out: Outbox<Uuid>) {
+        for (to, msg) in out {
+            self.forward(from, from, to, msg);
+        }
+    }
+
+    /// Sends `msg` from `from` to `to`, now held by `at`, over the first hop of the best route
+    /// from `at`. A relay plans the rest of the way when the message reaches it.
+    fn forward(&mut self, from: Uuid, at: Uuid, to: Uuid, msg: ProtocolMessage) {
+        let now = self.now();
+        let topology = topology(&self.nodes, &self.occlusion, &self.links, self.contacts.as_ref());
+        let Some((next, copies)) = topology.hop(&mut self.faults, at, to, now, msg) else {
+            self.stats.unroutable += 1;
+            return;
+        };
+        for (arrival, msg) in copies {
+            let action = if next == to {
+                Action::Deliver { from, to, msg }
+            } else {
+                Action::Relay { from, via: next, to, msg }
+            };
+            self.schedule(arrival, action);
+        }
+    }
+}
+
+/// The simulator's routing inputs, borrowed field by field so the fault injector stays free to be
+/// borrowed mutably alongside.
+fn topology<'a>(
+    nodes: &'a BTreeMap<Uuid, SimNode>,
+    occlusion: &'a OcclusionModel,
+    links: &'a LinkTable<Uuid>,
+    contacts: Option<&'a ContactPlan<Uuid>>,
+) -> Topology<'a, Uuid> {
+    Topology {
+        nodes: nodes.iter().map(|(id, n)| (*id, &n.trajectory)).collect(),
+        occlusion,
+        links,
+        contacts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fault::{LinkFaults, Partition};
+    use crate::spacetime::{set_speed_of_light, SpacetimeCoord, DEFAULT_C, SPEED_OF_LIGHT_TEST_LOCK};
+    use std::time::Instant;
+
+    const AU: f64 = 1.495_978_707e11;
+    const HOUR: u128 = 3_600 * 1_000_000_000;
+
+    fn fixed(x: f64, y: f64) -> Trajectory {
+        Trajectory::Static(SpacetimeCoord { t: 0, x, y, z: 0.0 })
+    }
+
+    /// Earth, Mars near opposition and a relay, writing and syncing hourly for `hours`.
+    fn interplanetary(seed: u64, hours: u128) -> Simulator {
+        let mut sim = Simulator::new(seed);
+        let nodes = [sim.add_node(fixed(AU, 0.0)), sim.add_node(fixed(1.524 * AU, 0.0)), sim.add_node(fixed(AU, 0.1 * AU))];
+        for hour in 0..hours {
+            let writer = nodes[sim.rng.next_u64() as usize % nodes.len()];
+            let payload = Operation::Put(format!("k{}", hour % 7), hour.to_le_bytes().to_vec());
+            sim.write_at(hour * HOUR, writer, payload);
+            for node in nodes {
+                sim.sync_at(hour * HOUR + HOUR / 2, node);
+            }
+        }
+        sim.run();
+        sim
+    }
+
+    #[test]
+    fn thousands_of_light_hours_run_in_moments_and_converge() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        set_speed_of_light(DEFAULT_C);
+
+        let started = Instant::now();
+        let sim = interplanetary(7, 2_000);
+        assert!(started.elapsed().as_secs() < 10, "virtual time must not cost wall time");
+
+        assert!(sim.now() > 1_999 * HOUR);
+        assert_eq!(sim.stats.writes, 2_000);
+        assert_eq!(sim.stats.rejected_writes, 0);
+        assert!(sim.converged());
+        let ids = sim.node_ids();
+        let replica = sim.replica(ids[0]).unwrap();
+        assert_eq!(replica.dag.graph.node_count(), 2_001, "genesis plus every write");
+    }
+
+    #[test]
+    fn relays_plan_the_next_hop_when_the_message_reaches_them() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        set_speed_of_light(100.0);
+        const MS: u128 = 1_000_000;
+
+        let mut sim = Simulator::new(3);
+        let (earth, relay, mars) = (sim.add_node(fixed(0.0, 0.0)), sim.add_node(fixed(20.0, 15.0)), sim.add_node(fixed(40.0, 0.0)));
+        sim.links.set_available(earth, mars, false);
+
+        // The relay is 250 ms out. Its onward link fails while the write is on the way to it, so
+        // the relay finds no route left and the message stops there.
+        sim.write_at(0, earth, Operation::Put("k".into(), vec![1]));
+        sim.run_until(100 * MS);
+        sim.links.set_available(relay, mars, false);
+        sim.run();
+        assert_eq!(sim.stats.unroutable, 1);
+        assert_eq!(sim.replica(relay).unwrap().dag.graph.node_count(), 2);
+        assert_eq!(sim.replica(mars).unwrap().dag.graph.node_count(), 1, "only genesis");
+
+        // Once the link is back, the next write goes through the relay.
+        sim.links.set_available(relay, mars, true);
+        sim.write_at(sim.now(), earth, Operation::Put("k".into(), vec![2]));
+        sim.run();
+        assert_eq!(sim.replica(mars).unwrap().dag.graph.node_count(), 3);
+    }
+
+    #[test]
+    fn converges_through_loss_duplication_reordering_and_corruption() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        set_speed_of_light(DEFAULT_C);
+
+        let mut sim = Simulator::new(11);
+        sim.faults.default = LinkFaults {
+            loss: 0.2,
+            duplicate: 0.2,
+            reorder: 0.3,
+            reorder_window_ns: 2 * HOUR,
+            corrupt: 0.02,
+            bandwidth: Some(4_000.0),
+        };
+        let nodes = [sim.add_node(fixed(AU, 0.0)), sim.add_node(fixed(AU + 3.84e8, 0.0)), sim.add_node(fixed(1.524 * AU, 0.0))];
+        for hour in 0..200 {
+            let writer = nodes[hour as usize % nodes.len()];
+            sim.write_at(hour * HOUR, writer, Operation::Put(format!("k{}", hour % 5), vec![hour as u8]));
+        }
+        // Anti-entropy keeps running after the last write until every loss has been repaired.
+        for hour in 0..260 {
+            for node in nodes {
+                sim.sync_at(hour * HOUR + HOUR / 2, node);
+            }
+        }
+        sim.run();
+
+        let stats = sim.faults.stats;
+        assert!(stats.lost > 0 && stats.duplicated > 0 && stats.reordered > 0 && stats.corrupted > 0, "{stats:?}");
+        assert_eq!(sim.check_convergence(), Ok(()));
+        // Corrupted copies are discarded, so nothing but the writes themselves is ever observed.
+        assert_eq!(sim.check_history(), []);
+    }
+
+    #[test]
+    fn partitioned_sides_diverge_then_converge_after_the_heal() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        set_speed_of_light(DEFAULT_C);
+
+        let mut sim = Simulator::new(5);
+        let (earth, moon, mars) = (sim.add_node(fixed(AU, 0.0)), sim.add_node(fixed(AU + 3.84e8, 0.0)), sim.add_node(fixed(1.524 * AU, 0.0)));
+        // Earth and Moon isolated from Mars from t=10h to t=30h.
+        sim.faults.partitions.push(Partition::isolate([earth, moon], 10 * HOUR..30 * HOUR));
+        sim.sync_every(HOUR, 40 * HOUR);
+        for (hour, node) in (0..36).zip([earth, moon, mars].into_iter().cycle()) {
+            sim.write_at(hour * HOUR, node, Operation::Put("shared".into(), vec![hour as u8]));
+            for reader in [earth, moon, mars] {
+                sim.read_at(hour * HOUR + HOUR / 4, reader, "shared");
+            }
+        }
+
+        sim.run_until(29 * HOUR);
+        assert!(sim.check_convergence().is_err(), "each side has writes the other lacks");
+        let heads = |id| sim.replica(id).unwrap().dag.heads.iter().copied().collect::<BTreeSet<_>>();
+        assert_eq!(heads(earth), heads(moon), "Earth and Moon still talk");
+
+        sim.run();
+        assert!(sim.faults.stats.partitioned > 0);
+        assert_eq!(sim.check_convergence(), Ok(()));
+        assert_eq!(sim.replica(mars).unwrap().dag.graph.node_count(), 37);
+        assert_eq!(sim.history.ops.len(), 36 * 4);
+        assert_eq!(sim.check_history(), []);
+    }
+
+    #[test]
+    fn a_seed_reproduces_its_run() {
+        let _c = SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        set_speed_of_light(DEFAULT_C);
+
+        let heads = |seed| {
+            let sim = interplanetary(seed, 50);
+            (sim.stats, sim.replica(sim.node_ids()[0]).unwrap().dag.heads.clone())
+        };
+        assert_eq!(heads(42), heads(42));
+        assert_ne!(heads(42).1, heads(43).1);
+    }
+}
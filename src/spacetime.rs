@@ -1,145 +1,305 @@
-use std::cmp::Ordering;
-use std::sync::{OnceLock, RwLock};
-
-use serde::{Deserialize, Serialize};
-
-/// Physical speed of light in meters per second.
-///
-/// Minkowski-KV typically overrides this with a smaller value for interactive simulations.
-pub const DEFAULT_C: f64 = 299_792_458.0;
-
-static SPEED_OF_LIGHT: OnceLock<RwLock<f64>> = OnceLock::new();
-
-fn c_cell() -> &'static RwLock<f64> {
-    SPEED_OF_LIGHT.get_or_init(|| RwLock::new(DEFAULT_C))
-}
-
-/// Returns the currently configured speed of light (m/s).
-///
-/// This is a *simulation parameter* rather than a compile-time constant so test scenarios can
-/// compress astronomical distances into human-scale delays.
-pub fn speed_of_light() -> f64 {
-    *c_cell().read().expect("speed_of_light poisoned")
-}
-
-/// Overrides the simulated speed of light (m/s).
-///
-/// Safety note: this is a global setting (shared across threads). It is intended for tests and
-/// single-process simulations. A real distributed deployment would treat $c$ as a physical constant
-/// and would not dynamically mutate it.
-pub fn set_speed_of_light(c: f64) {
-    if let Ok(mut guard) = c_cell().write() {
-        *guard = c;
-    }
-}
-
-/// A spacetime coordinate used by Minkowski-KV to reason about causality.
-///
-/// The project is a *relativistic* distributed database: we treat each write/event as a spacetime
-/// event and use the Minkowski interval to decide whether two events are causally orderable.
-///
-/// - `t` is expressed in nanoseconds (as a coordinate-time in some chosen frame).
-/// - `(x, y, z)` are meters in the same frame.
-///
-/// The core invariant we care about is the Minkowski interval:
-///
-/// $s^2 = c^2\Delta t^2 - \Delta x^2 - \Delta y^2 - \Delta z^2$
-///
-/// With this sign convention (“West Coast” / $(+,-,-,-)$):
-/// - $s^2 > 0$ (timelike): events can be causally related; an ordering exists.
-/// - $s^2 = 0$ (lightlike): events lie on the light cone.
-/// - $s^2 < 0$ (spacelike): no causal order is enforced by physics alone; we treat them as
-///   concurrent for database purposes (e.g., both may become DAG heads).
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
-pub struct SpacetimeCoord {
-    /// Nanoseconds from epoch.
-    pub t: u128,
-    /// Position in meters.
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
-}
-
-impl SpacetimeCoord {
-    /// Computes the Minkowski interval squared $s^2$ between two coordinates.
-    ///
-    /// This function deliberately returns the *squared* interval to avoid an unnecessary `sqrt` and
-    /// to preserve the sign, which is what we care about for causal classification.
-    pub fn interval_sq(&self, other: &Self) -> f64 {
-        let c = speed_of_light();
-        let delta_t_ns = other.t as i128 - self.t as i128;
-        let delta_t_s = delta_t_ns as f64 * 1e-9;
-
-        let delta_x = other.x - self.x;
-        let delta_y = other.y - self.y;
-        let delta_z = other.z - self.z;
-
-        let spatial_sq = delta_x * delta_x + delta_y * delta_y + delta_z * delta_z;
-        let ct = c * delta_t_s;
-        (ct * ct) - spatial_sq
-    }
-}
-
-impl PartialOrd for SpacetimeCoord {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let s_sq = self.interval_sq(other);
-        if s_sq < 0.0 {
-            // Spacelike-separated events have no physically mandated order.
-            return None;
-        }
-
-        let delta_t_ns = other.t as i128 - self.t as i128;
-        if delta_t_ns > 0 {
-            Some(Ordering::Less)
-        } else if delta_t_ns < 0 {
-            Some(Ordering::Greater)
-        } else {
-            Some(Ordering::Equal)
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn timelike_ordering() {
-        let a = SpacetimeCoord {
-            t: 0,
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        };
-        let b = SpacetimeCoord {
-            t: 10,
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        };
-
-        assert!(a < b, "Timelike future event should order as less");
-        assert_eq!(a.partial_cmp(&b), Some(Ordering::Less));
-        assert_eq!(b.partial_cmp(&a), Some(Ordering::Greater));
-    }
-
-    #[test]
-    fn spacelike_concurrent() {
-        let earth = SpacetimeCoord {
-            t: 0,
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        };
-        // Approximate Mars distance requiring ~3 minutes of light travel, but only 1s apart in time.
-        let mars = SpacetimeCoord {
-            t: 1_000_000_000, // 1 second later in nanoseconds
-            x: 5.4e10,
-            y: 0.0,
-            z: 0.0,
-        };
-
-        assert_eq!(earth.partial_cmp(&mars), None);
-        assert_eq!(mars.partial_cmp(&earth), None);
-    }
-}
+use std::cmp::Ordering;
+use std::sync::{OnceLock, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+/// Physical speed of light in meters per second.
+///
+/// Minkowski-KV typically overrides this with a smaller value for interactive simulations.
+pub const DEFAULT_C: f64 = 299_792_458.0;
+
+static SPEED_OF_LIGHT: OnceLock<RwLock<f64>> = OnceLock::new();
+
+fn c_cell() -> &'static RwLock<f64> {
+    SPEED_OF_LIGHT.get_or_init(|| RwLock::new(DEFAULT_C))
+}
+
+/// Returns the currently configured speed of light (m/s).
+///
+/// This is a *simulation parameter* rather than a compile-time constant so test scenarios can
+/// compress astronomical distances into human-scale delays.
+pub fn speed_of_light() -> f64 {
+    *c_cell().read().expect("speed_of_light poisoned")
+}
+
+/// Serializes tests that configure the global speed of light.
+///
+/// Tests run concurrently, so any test that calls [`set_speed_of_light`] and depends on the value
+/// must hold this lock for as long as it relies on it.
+#[cfg(test)]
+pub(crate) static SPEED_OF_LIGHT_TEST_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Overrides the simulated speed of light (m/s).
+///
+/// Safety note: this is a global setting (shared across threads). It is intended for tests and
+/// single-process simulations. A real distributed deployment would treat $c$ as a physical constant
+/// and would not dynamically mutate it.
+pub fn set_speed_of_light(c: f64) {
+    if let Ok(mut guard) = c_cell().write() {
+        *guard = c;
+    }
+}
+
+/// Coordinate time in nanoseconds since the Unix epoch (the demo's shared frame).
+pub fn now_ns() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+/// A spacetime coordinate used by Minkowski-KV to reason about causality.
+///
+/// The project is a *relativistic* distributed database: we treat each write/event as a spacetime
+/// event and use the Minkowski interval to decide whether two events are causally orderable.
+///
+/// - `t` is expressed in nanoseconds (as a coordinate-time in some chosen frame).
+/// - `(x, y, z)` are meters in the same frame.
+///
+/// The core invariant we care about is the Minkowski interval:
+///
+/// $s^2 = c^2\Delta t^2 - \Delta x^2 - \Delta y^2 - \Delta z^2$
+///
+/// With this sign convention (“West Coast” / $(+,-,-,-)$):
+/// - $s^2 > 0$ (timelike): events can be causally related; an ordering exists.
+/// - $s^2 = 0$ (lightlike): events lie on the light cone.
+/// - $s^2 < 0$ (spacelike): no causal order is enforced by physics alone; we treat them as
+///   concurrent for database purposes (e.g., both may become DAG heads).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SpacetimeCoord {
+    /// Nanoseconds from epoch.
+    pub t: u128,
+    /// Position in meters.
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl SpacetimeCoord {
+    /// Computes the Minkowski interval squared $s^2$ between two coordinates.
+    ///
+    /// This function deliberately returns the *squared* interval to avoid an unnecessary `sqrt` and
+    /// to preserve the sign, which is what we care about for causal classification.
+    pub fn interval_sq(&self, other: &Self) -> f64 {
+        let c = speed_of_light();
+        // Only the square of the time difference matters, so its sign can be dropped.
+        let delta_t_s = other.t.abs_diff(self.t) as f64 * 1e-9;
+
+        let delta_x = other.x - self.x;
+        let delta_y = other.y - self.y;
+        let delta_z = other.z - self.z;
+
+        let spatial_sq = delta_x * delta_x + delta_y * delta_y + delta_z * delta_z;
+        let ct = c * delta_t_s;
+        (ct * ct) - spatial_sq
+    }
+
+    /// Whether `self` lies inside or on the past light cone of `point`, i.e. whether a signal
+    /// emitted at `self` could have reached `point`.
+    pub fn is_in_past_light_cone_of(&self, point: &Self) -> bool {
+        self.t <= point.t && self.interval_sq(point) >= 0.0
+    }
+}
+
+/// Error bounds on a measured spacetime coordinate.
+///
+/// Real nodes do not know their coordinate time or position exactly: clocks drift between syncs and
+/// ephemerides have finite precision. The true coordinate is assumed to lie within `t ± time_ns`
+/// and within a sphere of `radius` meters around `(x, y, z)`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Uncertainty {
+    /// Clock error bound in nanoseconds.
+    pub time_ns: u128,
+    /// Position error bound in meters.
+    pub radius: f64,
+}
+
+impl Uncertainty {
+    /// A perfectly known coordinate.
+    pub const EXACT: Self = Self {
+        time_ns: 0,
+        radius: 0.0,
+    };
+
+    /// Error bounds of a separation between two uncertain coordinates (errors add up).
+    pub fn combine(&self, other: &Self) -> Self {
+        Self {
+            time_ns: self.time_ns.saturating_add(other.time_ns),
+            radius: self.radius + other.radius,
+        }
+    }
+}
+
+/// Three-valued causal classification of two uncertain coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CausalClass {
+    /// Timelike or lightlike for every placement within the error bounds.
+    Timelike,
+    /// Spacelike for every placement within the error bounds.
+    Spacelike,
+    /// The error bounds straddle the light cone.
+    Ambiguous,
+}
+
+/// A spacetime coordinate together with its error bounds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct UncertainCoord {
+    pub coord: SpacetimeCoord,
+    pub uncertainty: Uncertainty,
+}
+
+impl UncertainCoord {
+    /// Wraps a coordinate that is known exactly.
+    pub fn exact(coord: SpacetimeCoord) -> Self {
+        Self {
+            coord,
+            uncertainty: Uncertainty::EXACT,
+        }
+    }
+
+    /// Classifies the separation between `self` and `other`, accounting for both error bounds.
+    ///
+    /// The separation is definitely timelike if even the shortest admissible time difference lets
+    /// light cover the longest admissible distance, definitely spacelike if even the longest time
+    /// difference cannot cover the shortest distance, and ambiguous otherwise. A separation that is
+    /// not finite counts as spacelike.
+    pub fn classify(&self, other: &Self) -> CausalClass {
+        let c = speed_of_light();
+        let err = self.uncertainty.combine(&other.uncertainty);
+
+        let dt_ns = other.coord.t.abs_diff(self.coord.t);
+        let min_ct = c * dt_ns.saturating_sub(err.time_ns) as f64 * 1e-9;
+        let max_ct = c * dt_ns.saturating_add(err.time_ns) as f64 * 1e-9;
+
+        let dx = other.coord.x - self.coord.x;
+        let dy = other.coord.y - self.coord.y;
+        let dz = other.coord.z - self.coord.z;
+        let dist = (dx * dx + dy * dy + dz * dz).sqrt();
+        let min_dist = (dist - err.radius).max(0.0);
+        let max_dist = dist + err.radius;
+
+        // Nothing is known about a separation that is not finite (a NaN or infinite coordinate or
+        // radius), so it must not be waved through as possibly causal.
+        if !(dist.is_finite() && err.radius.is_finite()) {
+            return CausalClass::Spacelike;
+        }
+        if min_ct >= max_dist {
+            CausalClass::Timelike
+        } else if max_ct < min_dist {
+            CausalClass::Spacelike
+        } else {
+            CausalClass::Ambiguous
+        }
+    }
+
+    /// Whether `self` could lie in the past light cone of `later`.
+    ///
+    /// This is the conservative test used at ingest: it is `false` only if `self` is definitely
+    /// spacelike to `later`, or definitely timelike *and* definitely later.
+    pub fn may_precede(&self, later: &Self) -> bool {
+        let err = self.uncertainty.combine(&later.uncertainty);
+        match self.classify(later) {
+            CausalClass::Spacelike => false,
+            CausalClass::Timelike => self.coord.t <= later.coord.t.saturating_add(err.time_ns),
+            CausalClass::Ambiguous => true,
+        }
+    }
+}
+
+impl PartialOrd for SpacetimeCoord {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let s_sq = self.interval_sq(other);
+        if s_sq < 0.0 {
+            // Spacelike-separated events have no physically mandated order.
+            return None;
+        }
+
+        Some(self.t.cmp(&other.t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timelike_ordering() {
+        let a = SpacetimeCoord {
+            t: 0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        let b = SpacetimeCoord {
+            t: 10,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+
+        assert!(a < b, "Timelike future event should order as less");
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Less));
+        assert_eq!(b.partial_cmp(&a), Some(Ordering::Greater));
+    }
+
+    #[test]
+    fn uncertainty_classification() {
+        // Distances are chosen so the verdicts hold for any simulated c other tests may configure.
+        let at = |t: u128, x: f64, time_ns: u128, radius: f64| UncertainCoord {
+            coord: SpacetimeCoord { t, x, y: 0.0, z: 0.0 },
+            uncertainty: Uncertainty { time_ns, radius },
+        };
+
+        let here = at(0, 0.0, 0, 0.0);
+        assert_eq!(here.classify(&at(10, 0.0, 0, 0.0)), CausalClass::Timelike);
+        assert_eq!(here.classify(&at(1, 1.0e9, 0, 0.0)), CausalClass::Spacelike);
+        // Exactly known, this pair is spacelike; a 1e9 m radius straddles the light cone.
+        assert_eq!(here.classify(&at(1, 1.0e9, 0, 1.0e9)), CausalClass::Ambiguous);
+
+        let later = at(1_000, 0.0, 0, 0.0);
+        assert!(here.may_precede(&later));
+        assert!(!later.may_precede(&here));
+        assert!(at(1_000, 0.0, 5_000, 0.0).may_precede(&here), "clock error hides the order");
+    }
+
+    #[test]
+    fn degenerate_coordinates_are_not_causal() {
+        let at = |t: u128, x: f64, time_ns: u128, radius: f64| UncertainCoord {
+            coord: SpacetimeCoord { t, x, y: 0.0, z: 0.0 },
+            uncertainty: Uncertainty { time_ns, radius },
+        };
+
+        let here = at(0, 0.0, 0, 0.0);
+        for bad in [at(10, 0.0, 0, f64::NAN), at(10, 0.0, 0, f64::INFINITY), at(10, f64::NAN, 0, 0.0), at(10, f64::INFINITY, 0, 0.0)] {
+            assert_eq!(bad.classify(&here), CausalClass::Spacelike);
+            assert!(!bad.may_precede(&here));
+        }
+
+        // Times at the very end of the range neither overflow nor wrap around.
+        let end = at(u128::MAX, 1.0, u128::MAX, 0.0);
+        assert_eq!(here.classify(&end), CausalClass::Ambiguous);
+        assert_eq!(end.classify(&end), CausalClass::Timelike);
+        assert_eq!(here.coord.partial_cmp(&end.coord), Some(Ordering::Less));
+        assert!(here.coord.interval_sq(&end.coord) > 0.0);
+    }
+
+    #[test]
+    fn spacelike_concurrent() {
+        let earth = SpacetimeCoord {
+            t: 0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        // Approximate Mars distance requiring ~3 minutes of light travel, but only 1s apart in time.
+        let mars = SpacetimeCoord {
+            t: 1_000_000_000, // 1 second later in nanoseconds
+            x: 5.4e10,
+            y: 0.0,
+            z: 0.0,
+        };
+
+        assert_eq!(earth.partial_cmp(&mars), None);
+        assert_eq!(mars.partial_cmp(&earth), None);
+    }
+}
@@ -0,0 +1,654 @@
+//! Materialized key/value state (the DAG fold).
+//!
+//! The DAG stores *operations*; applications want *values*. This module folds the event graph into
+//! a key/value map by replaying every event in the DAG's deterministic linearization (see
+//! [`SpacetimeDAG::linearize`]). Because that order depends only on the set of events, any two
+//! replicas holding the same events materialize byte-identical state, regardless of the order in
+//! which gossip delivered them.
+//!
+//! Semantics under spacelike concurrency:
+//! - `Put` / `Delete` / successful `CompareAndSet` behave as a last-writer-wins register, where
+//!   "last" means later in the linearization (causal successors always win over ancestors).
+//! - `Increment` deltas are summed, so concurrent increments all count.
+//! - `Append` keeps every concurrently appended element, ordered by the linearization.
+//! - `CompareAndSet` is judged against the writer's causal past only, so replicas agree on whether
+//!   it succeeded even if they received concurrent writes in different orders.
+//! - `Batch` applies its operations with a single version; a failed compare-and-set anywhere in the
+//!   batch voids the whole batch.
+//! - `Crdt` updates merge according to the CRDT held by the key (see [`crate::crdt`]).
+//!
+//! Operations that target a key holding a different kind of value (e.g. `Increment` on a key last
+//! written by `Put`) replace it, as if the key had been absent.
+//!
+//! Live keys are kept in an ordered index, so prefix and range scans (forward or reverse, with
+//! pagination) read straight from the fold instead of re-walking the graph.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+
+use crate::crdt::{CrdtOp, LwwMap, OrSet, PnCounter, Rga, SetOp};
+use crate::dag::SpacetimeDAG;
+use crate::event::{Event, EventHash, Operation};
+
+/// A materialized value stored under a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// An opaque register written by `Put` or `CompareAndSet`.
+    Bytes(Vec<u8>),
+    /// A counter accumulated from `Increment` deltas.
+    Counter(i64),
+    /// A list accumulated from `Append` operations.
+    List(Vec<Vec<u8>>),
+    /// A positive-negative counter.
+    PnCounter(PnCounter),
+    /// An observed-remove set.
+    OrSet(OrSet),
+    /// A map of last-writer-wins fields.
+    LwwMap(LwwMap),
+    /// A replicated text sequence.
+    Sequence(Rga),
+}
+
+/// A value together with the version (event hash) that last modified it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub value: Value,
+    /// Hash of the most recent event (in linearization order) that modified this key.
+    pub version: EventHash,
+}
+
+/// Scan direction for paginated reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// Opaque continuation token returned by [`State::page`].
+///
+/// It records where the next page resumes: strictly after the last key of the previous page (or
+/// strictly before it, for reverse scans), or where the previous page started if it had a limit of
+/// zero. Tokens stay valid across writes: keys inserted behind the cursor are skipped, keys inserted
+/// ahead of it are returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageToken(Bound<String>);
+
+/// One page of a scan.
+#[derive(Debug)]
+pub struct Page<'a> {
+    pub entries: Vec<(&'a str, &'a Entry)>,
+    /// Token for the following page, or `None` if the scan is exhausted.
+    pub next: Option<PageToken>,
+}
+
+/// Key/value state materialized from a [`SpacetimeDAG`].
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    /// Live keys, ordered for scans.
+    entries: BTreeMap<String, Entry>,
+    /// Every event that modified each key, in linearization order, flagged with whether the key
+    /// still existed afterwards (`false` for deletes).
+    ///
+    /// This is what lets compare-and-set ask "which version did the *writer* observe?" without
+    /// re-folding the writer's causal past.
+    writes: HashMap<String, Vec<(EventHash, bool)>>,
+}
+
+impl State {
+    /// Creates an empty state.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Folds every event of `dag` into a fresh state.
+    pub fn from_dag(dag: &SpacetimeDAG) -> Self {
+        let mut state = Self::new();
+        for idx in dag.linearize() {
+            state.apply(dag, &dag.graph[idx]);
+        }
+        state
+    }
+
+    /// Folds only the events of `dag` whose hashes are in `subset`.
+    ///
+    /// `subset` should be causally closed (contain the ancestors of each member); otherwise
+    /// compare-and-set and OR-set removes are judged against writes the subset does not contain.
+    pub fn from_subset(dag: &SpacetimeDAG, subset: &HashSet<EventHash>) -> Self {
+        let mut state = Self::new();
+        for idx in dag.linearize() {
+            let event = &dag.graph[idx];
+            if subset.contains(&event.hash) {
+                state.apply(dag, event);
+            }
+        }
+        state
+    }
+
+    /// Returns the value stored under `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.get(key).map(|entry| &entry.value)
+    }
+
+    /// Returns the value and version stored under `key`, if any.
+    pub fn entry(&self, key: &str) -> Option<&Entry> {
+        self.entries.get(key)
+    }
+
+    /// Number of live keys.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no keys are live.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over live keys within `(start, end)` in key order. Reverse with `.rev()`.
+    pub fn range<'a>(
+        &'a self,
+        start: Bound<&str>,
+        end: Bound<&str>,
+    ) -> impl DoubleEndedIterator<Item = (&'a str, &'a Entry)> + 'a {
+        let empty = match (start, end) {
+            (Bound::Included(s), Bound::Included(e)) => s > e,
+            (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+                s >= e
+            }
+            _ => false,
+        };
+        // `BTreeMap::range` panics on inverted bounds; an inverted range is simply empty.
+        let bounds = if empty {
+            (Bound::Included(""), Bound::Excluded(""))
+        } else {
+            (start, end)
+        };
+        self.entries
+            .range::<str, _>(bounds)
+            .map(|(key, entry)| (key.as_str(), entry))
+    }
+
+    /// Iterates over live keys starting with `prefix` in key order. Reverse with `.rev()`.
+    pub fn scan<'a>(
+        &'a self,
+        prefix: &str,
+    ) -> impl DoubleEndedIterator<Item = (&'a str, &'a Entry)> + 'a {
+        let end = prefix_end(prefix);
+        self.range(
+            Bound::Included(prefix),
+            end.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
+        )
+    }
+
+    /// Returns up to `limit` entries of the range `(start, end)`, resuming at `token` if given.
+    ///
+    /// The token only narrows the range, so a page never strays outside `(start, end)`. `next` is
+    /// set whenever entries remain, even if `limit` is zero.
+    pub fn page<'a>(
+        &'a self,
+        start: Bound<&str>,
+        end: Bound<&str>,
+        direction: Direction,
+        limit: usize,
+        token: Option<&PageToken>,
+    ) -> Page<'a> {
+        let resume = token.map(|PageToken(bound)| bound.as_ref().map(String::as_str));
+        let (start, end) = match (direction, resume) {
+            (Direction::Forward, Some(resume)) => (tighter(start, resume, |a, b| a > b), end),
+            (Direction::Reverse, Some(resume)) => (start, tighter(end, resume, |a, b| a < b)),
+            (_, None) => (start, end),
+        };
+
+        // Fetch one extra entry to learn whether another page exists.
+        let mut entries: Vec<_> = match direction {
+            Direction::Forward => self.range(start, end).take(limit + 1).collect(),
+            Direction::Reverse => self.range(start, end).rev().take(limit + 1).collect(),
+        };
+        let next = (entries.len() > limit).then(|| {
+            entries.truncate(limit);
+            let bound = match (entries.last(), direction) {
+                (Some((key, _)), _) => Bound::Excluded(*key),
+                (None, Direction::Forward) => start,
+                (None, Direction::Reverse) => end,
+            };
+            PageToken(bound.map(str::to_string))
+        });
+        Page { entries, next }
+    }
+
+    /// Paginated form of [`State::scan`].
+    pub fn scan_page<'a>(
+        &'a self,
+        prefix: &str,
+        direction: Direction,
+        limit: usize,
+        token: Option<&PageToken>,
+    ) -> Page<'a> {
+        let end = prefix_end(prefix);
+        self.page(
+            Bound::Included(prefix),
+            end.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
+            direction,
+            limit,
+            token,
+        )
+    }
+
+    /// Applies a single event on top of the current state.
+    ///
+    /// Callers must apply events in linearization order; the event's ancestors must already have
+    /// been applied for compare-and-set to be judged correctly.
+    pub fn apply(&mut self, dag: &SpacetimeDAG, event: &Event) {
+        let mut ctx = OpContext::new(dag, event, None);
+        if self.preconditions_hold(&mut ctx, &event.payload) {
+            self.apply_op(&mut ctx, &event.payload);
+        }
+    }
+
+    /// Folds `event` into a state that has already applied events sorting *after* it.
+    ///
+    /// `event` has just been added, so nothing already folded has it in its causal past: every
+    /// compare-and-set verdict and OR-set remove stays as it was, and only the keys `event` writes
+    /// can change. Each of those is re-folded from its last overwrite (put, compare-and-set or
+    /// delete) before `event`, replaying just that key's writes.
+    pub(crate) fn apply_late(&mut self, dag: &SpacetimeDAG, event: &Event) {
+        let mut ctx = OpContext::new(dag, event, None);
+        if !self.preconditions_hold(&mut ctx, &event.payload) {
+            return;
+        }
+        let order = |hash: &EventHash| (dag.depth(hash), *hash);
+        let position = order(&event.hash);
+        let written = |hash: &EventHash| dag.get(hash).expect("recorded writes are in the DAG");
+
+        let mut keys = Vec::new();
+        touched_keys(&event.payload, &mut keys);
+        keys.sort_unstable();
+        keys.dedup();
+        for key in keys {
+            let writes = self.writes.remove(key).unwrap_or_default();
+            let at = writes.partition_point(|(hash, _)| order(hash) < position);
+            let from = writes[..at]
+                .iter()
+                .rposition(|(hash, _)| overwrites(&written(hash).payload, key))
+                .unwrap_or(0);
+            let replay: Vec<&Event> = writes[from..at]
+                .iter()
+                .map(|(hash, _)| written(hash))
+                .chain([event])
+                .chain(writes[at..].iter().map(|(hash, _)| written(hash)))
+                .collect();
+
+            self.entries.remove(key);
+            self.writes.insert(key.to_string(), writes[..from].to_vec());
+            for ev in replay {
+                self.apply_op(&mut OpContext::new(dag, ev, Some(key)), &ev.payload);
+            }
+        }
+    }
+
+    /// Returns the version of `key` visible to an observer whose causal past is `past`.
+    ///
+    /// `None` means the key was never written in that past, or its latest write was a delete.
+    fn visible_version(&self, key: &str, past: &HashSet<EventHash>) -> Option<EventHash> {
+        self.writes
+            .get(key)?
+            .iter()
+            .rev()
+            .find(|(hash, _)| past.contains(hash))
+            .and_then(|(hash, present)| present.then_some(*hash))
+    }
+
+    fn preconditions_hold(&self, ctx: &mut OpContext<'_>, op: &Operation) -> bool {
+        match op {
+            Operation::CompareAndSet { key, expected, .. } => {
+                self.visible_version(key, ctx.past()) == *expected
+            }
+            Operation::Batch(ops) => ops.iter().all(|op| self.preconditions_hold(ctx, op)),
+            _ => true,
+        }
+    }
+
+    fn apply_op(&mut self, ctx: &mut OpContext<'_>, op: &Operation) {
+        let version = ctx.event.hash;
+        if !matches!(op, Operation::Batch(_)) {
+            ctx.op_index += 1;
+            if ctx.only.is_some_and(|only| op_key(op) != Some(only)) {
+                return;
+            }
+        }
+        match op {
+            Operation::Put(key, value) | Operation::CompareAndSet { key, value, .. } => {
+                self.set(key, version, Value::Bytes(value.clone()));
+            }
+            Operation::Delete(key) => {
+                self.entries.remove(key);
+                self.record_write(key, version, false);
+            }
+            Operation::Append(key, value) => match self.entries.get_mut(key) {
+                Some(Entry {
+                    value: Value::List(items),
+                    version: v,
+                }) => {
+                    items.push(value.clone());
+                    *v = version;
+                    self.record_write(key, version, true);
+                }
+                _ => self.set(key, version, Value::List(vec![value.clone()])),
+            },
+            Operation::Increment(key, delta) => match self.entries.get_mut(key) {
+                Some(Entry {
+                    value: Value::Counter(n),
+                    version: v,
+                }) => {
+                    *n = n.saturating_add(*delta);
+                    *v = version;
+                    self.record_write(key, version, true);
+                }
+                _ => self.set(key, version, Value::Counter(*delta)),
+            },
+            Operation::Batch(ops) => {
+                for op in ops {
+                    self.apply_op(ctx, op);
+                }
+            }
+            Operation::Crdt(key, op) => self.apply_crdt(ctx, key, op),
+            Operation::Merge | Operation::Genesis => {}
+        }
+    }
+
+    fn apply_crdt(&mut self, ctx: &mut OpContext<'_>, key: &str, op: &CrdtOp) {
+        let version = ctx.event.hash;
+        let mut value = match (self.entries.remove(key).map(|e| e.value), op) {
+            (Some(value @ Value::PnCounter(_)), CrdtOp::Counter(_))
+            | (Some(value @ Value::OrSet(_)), CrdtOp::Set(_))
+            | (Some(value @ Value::LwwMap(_)), CrdtOp::Map(_))
+            | (Some(value @ Value::Sequence(_)), CrdtOp::Sequence(_)) => value,
+            (_, CrdtOp::Counter(_)) => Value::PnCounter(PnCounter::default()),
+            (_, CrdtOp::Set(_)) => Value::OrSet(OrSet::default()),
+            (_, CrdtOp::Map(_)) => Value::LwwMap(LwwMap::default()),
+            (_, CrdtOp::Sequence(_)) => Value::Sequence(Rga::default()),
+        };
+
+        match (&mut value, op) {
+            (Value::PnCounter(counter), CrdtOp::Counter(op)) => counter.apply(op),
+            (Value::OrSet(set), CrdtOp::Set(SetOp::Add(element))) => set.add(element, version),
+            (Value::OrSet(set), CrdtOp::Set(SetOp::Remove(element))) => {
+                set.remove(element, ctx.past())
+            }
+            (Value::LwwMap(map), CrdtOp::Map(op)) => map.apply(op),
+            (Value::Sequence(rga), CrdtOp::Sequence(op)) => rga.apply(op, version, ctx.op_index - 1),
+            _ => unreachable!("value kind was matched to the operation above"),
+        }
+        self.set(key, version, value);
+    }
+
+    fn set(&mut self, key: &str, version: EventHash, value: Value) {
+        self.entries.insert(key.to_string(), Entry { value, version });
+        self.record_write(key, version, true);
+    }
+
+    fn record_write(&mut self, key: &str, version: EventHash, present: bool) {
+        let writes = self.writes.entry(key.to_string()).or_default();
+        match writes.last_mut() {
+            // A batch touching the same key twice still produces a single version.
+            Some((last, last_present)) if *last == version => *last_present = present,
+            _ => writes.push((version, present)),
+        }
+    }
+}
+
+/// The narrower of two bounds on the same side of a range. `beyond(a, b)` says whether key `a` lies
+/// further inside the range than `b` (`>` for start bounds, `<` for end bounds).
+fn tighter<'a>(a: Bound<&'a str>, b: Bound<&'a str>, beyond: impl Fn(&str, &str) -> bool) -> Bound<&'a str> {
+    let key = |bound: Bound<&'a str>| match bound {
+        Bound::Included(key) | Bound::Excluded(key) => Some(key),
+        Bound::Unbounded => None,
+    };
+    match (key(a), key(b)) {
+        (None, _) => b,
+        (_, None) => a,
+        (Some(x), Some(y)) if x != y => {
+            if beyond(x, y) {
+                a
+            } else {
+                b
+            }
+        }
+        // Same key: excluding it is the narrower bound.
+        _ if matches!(a, Bound::Excluded(_)) => a,
+        _ => b,
+    }
+}
+
+/// Returns the smallest string greater than every string starting with `prefix`, or `None` if no
+/// such string exists (empty prefix, or a prefix of only `char::MAX`).
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+/// The key a non-batch operation writes, if any.
+fn op_key(op: &Operation) -> Option<&str> {
+    match op {
+        Operation::Put(key, _)
+        | Operation::Delete(key)
+        | Operation::Append(key, _)
+        | Operation::Increment(key, _)
+        | Operation::CompareAndSet { key, .. }
+        | Operation::Crdt(key, _) => Some(key),
+        Operation::Batch(_) | Operation::Merge | Operation::Genesis => None,
+    }
+}
+
+/// Collects every key `op` writes, including inside batches.
+fn touched_keys<'a>(op: &'a Operation, keys: &mut Vec<&'a str>) {
+    match op {
+        Operation::Batch(ops) => ops.iter().for_each(|op| touched_keys(op, keys)),
+        op => keys.extend(op_key(op)),
+    }
+}
+
+/// Whether the first thing `op` does to `key` replaces its value regardless of what was there.
+fn overwrites(op: &Operation, key: &str) -> bool {
+    fn first<'a>(op: &'a Operation, key: &str) -> Option<&'a Operation> {
+        match op {
+            Operation::Batch(ops) => ops.iter().find_map(|op| first(op, key)),
+            op => (op_key(op) == Some(key)).then_some(op),
+        }
+    }
+    matches!(
+        first(op, key),
+        Some(Operation::Put(..) | Operation::CompareAndSet { .. } | Operation::Delete(_))
+    )
+}
+
+/// Per-event context threaded through the fold, caching the event's causal past on first use.
+struct OpContext<'a> {
+    dag: &'a SpacetimeDAG,
+    event: &'a Event,
+    past: Option<HashSet<EventHash>>,
+    /// Operations of the event applied so far, counting through nested batches.
+    op_index: u32,
+    /// When set, only operations on this key take effect (see [`State::apply_late`]).
+    only: Option<&'a str>,
+}
+
+impl<'a> OpContext<'a> {
+    fn new(dag: &'a SpacetimeDAG, event: &'a Event, only: Option<&'a str>) -> Self {
+        Self {
+            dag,
+            event,
+            past: None,
+            op_index: 0,
+            only,
+        }
+    }
+
+    fn past(&mut self) -> &HashSet<EventHash> {
+        let (dag, hash) = (self.dag, self.event.hash);
+        self.past.get_or_insert_with(|| dag.ancestors(&hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    use crate::spacetime::SpacetimeCoord;
+
+    fn event_after(parents: &[EventHash], t: u128, payload: Operation) -> Event {
+        Event::new(
+            parents.iter().cloned().collect::<BTreeSet<_>>(),
+            SpacetimeCoord {
+                t,
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            payload,
+        )
+    }
+
+    #[test]
+    fn concurrent_increments_and_appends_merge() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis = dag.heads[0];
+
+        let a = event_after(&[genesis], 10, Operation::Increment("hits".into(), 2));
+        let b = event_after(&[genesis], 10, Operation::Increment("hits".into(), 3));
+        let c = event_after(&[genesis], 10, Operation::Append("log".into(), b"a".to_vec()));
+        let d = event_after(&[genesis], 10, Operation::Append("log".into(), b"b".to_vec()));
+        for ev in [a, b, c, d] {
+            dag.add_event(ev).expect("parents present");
+        }
+
+        let state = State::from_dag(&dag);
+        assert_eq!(state.get("hits"), Some(&Value::Counter(5)));
+        match state.get("log") {
+            Some(Value::List(items)) => assert_eq!(items.len(), 2),
+            other => panic!("expected list, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn compare_and_set_is_judged_against_causal_past() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis = dag.heads[0];
+
+        let put = event_after(&[genesis], 10, Operation::Put("k".into(), b"v1".to_vec()));
+        let put_hash = put.hash;
+        dag.add_event(put).unwrap();
+
+        // Both writers saw `put`; one expects it, the other expects the key to be absent.
+        let ok = event_after(
+            &[put_hash],
+            20,
+            Operation::CompareAndSet {
+                key: "k".into(),
+                expected: Some(put_hash),
+                value: b"v2".to_vec(),
+            },
+        );
+        let stale = event_after(
+            &[put_hash],
+            20,
+            Operation::CompareAndSet {
+                key: "k".into(),
+                expected: None,
+                value: b"v3".to_vec(),
+            },
+        );
+        let ok_hash = ok.hash;
+        dag.add_event(stale).unwrap();
+        dag.add_event(ok).unwrap();
+
+        let state = State::from_dag(&dag);
+        let entry = state.entry("k").expect("key present");
+        assert_eq!(entry.value, Value::Bytes(b"v2".to_vec()));
+        assert_eq!(entry.version, ok_hash);
+    }
+
+    #[test]
+    fn prefix_scan_paginates_in_both_directions() {
+        let mut dag = SpacetimeDAG::new();
+        let mut parent = dag.heads[0];
+        for (t, key) in ["crew/ada", "crew/bo", "crew/cy", "log/1", "crevasse"].iter().enumerate() {
+            let ev = event_after(&[parent], t as u128 + 1, Operation::Put(key.to_string(), vec![]));
+            parent = ev.hash;
+            dag.add_event(ev).unwrap();
+        }
+        let state = dag.state();
+
+        let first = state.scan_page("crew/", Direction::Forward, 2, None);
+        let keys: Vec<_> = first.entries.iter().map(|(k, _)| *k).collect();
+        assert_eq!(keys, ["crew/ada", "crew/bo"]);
+        let second = state.scan_page("crew/", Direction::Forward, 2, first.next.as_ref());
+        let keys: Vec<_> = second.entries.iter().map(|(k, _)| *k).collect();
+        assert_eq!(keys, ["crew/cy"]);
+        assert!(second.next.is_none());
+
+        let reverse: Vec<_> = state.scan("crew/").rev().map(|(k, _)| k).collect();
+        assert_eq!(reverse, ["crew/cy", "crew/bo", "crew/ada"]);
+    }
+
+    #[test]
+    fn pages_stay_in_range_and_zero_limits_keep_a_token() {
+        let mut dag = SpacetimeDAG::new();
+        let mut parent = dag.heads[0];
+        for (t, key) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+            let ev = event_after(&[parent], t as u128 + 1, Operation::Put(key.to_string(), vec![]));
+            parent = ev.hash;
+            dag.add_event(ev).unwrap();
+        }
+        let state = dag.state();
+        let keys = |page: &Page<'_>| page.entries.iter().map(|(k, _)| k.to_string()).collect::<Vec<_>>();
+
+        // An empty page still says where to resume, and resuming loses nothing.
+        let (start, end) = (Bound::Included("b"), Bound::Excluded("e"));
+        let empty = state.page(start, end, Direction::Forward, 0, None);
+        assert!(empty.entries.is_empty());
+        let first = state.page(start, end, Direction::Forward, 2, empty.next.as_ref());
+        assert_eq!(keys(&first), ["b", "c"]);
+
+        // A token from a wider scan cannot carry a page past the caller's bounds.
+        let wide = state.page(Bound::Unbounded, Bound::Unbounded, Direction::Forward, 1, None);
+        let narrow = state.page(Bound::Included("c"), end, Direction::Forward, 10, wide.next.as_ref());
+        assert_eq!(keys(&narrow), ["c", "d"]);
+        assert!(narrow.next.is_none());
+        let reverse = state.page(Bound::Unbounded, Bound::Excluded("c"), Direction::Reverse, 10, Some(&PageToken(Bound::Excluded("e".into()))));
+        assert_eq!(keys(&reverse), ["b", "a"]);
+        assert!(state.page(start, end, Direction::Reverse, 0, None).next.is_some());
+        assert!(state.page(Bound::Included("f"), Bound::Unbounded, Direction::Forward, 0, None).next.is_none());
+    }
+
+    #[test]
+    fn failed_compare_and_set_voids_batch() {
+        let mut dag = SpacetimeDAG::new();
+        let genesis = dag.heads[0];
+
+        let batch = event_after(
+            &[genesis],
+            10,
+            Operation::Batch(vec![
+                Operation::Put("a".into(), b"1".to_vec()),
+                Operation::CompareAndSet {
+                    key: "b".into(),
+                    expected: Some([7u8; 32]),
+                    value: b"2".to_vec(),
+                },
+            ]),
+        );
+        dag.add_event(batch).unwrap();
+
+        let state = State::from_dag(&dag);
+        assert!(state.is_empty(), "no operation of a failed batch may apply");
+    }
+}
@@ -0,0 +1,198 @@
+//! Node trajectories and the light-time equation.
+//!
+//! Planets and spacecraft move while a signal is in flight. For a signal emitted at coordinate time
+//! $t_e$ from the sender's position $S(t_e)$, the reception time $t_r$ at a moving receiver $R$
+//! solves the *light-time equation*:
+//!
+//! $c\,(t_r - t_e) = \lVert R(t_r) - S(t_e) \rVert$
+//!
+//! $R(t_r)$ depends on the unknown $t_r$, so we solve by fixed-point iteration starting from the
+//! receiver's position at emission. For receivers much slower than light this converges to
+//! nanosecond precision in a handful of iterations.
+
+use std::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::spacetime::{speed_of_light, SpacetimeCoord};
+
+/// Convergence threshold for the light-time iteration, in nanoseconds.
+const LIGHT_TIME_TOLERANCE_NS: u128 = 1;
+/// Upper bound on light-time iterations; reached only by receivers moving near $c$.
+const LIGHT_TIME_MAX_ITERATIONS: usize = 32;
+
+/// Where a node is over time.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Trajectory {
+    /// A node that never moves. The coordinate's `t` is ignored.
+    Static(SpacetimeCoord),
+    /// Ephemeris-style samples, linearly interpolated between neighbours and held constant before
+    /// the first and after the last sample.
+    Sampled(Samples),
+}
+
+/// Samples a [`Trajectory::Sampled`] cannot be built from.
+#[derive(Debug, Error)]
+pub enum InvalidSamples {
+    #[error("a sampled trajectory needs at least one sample")]
+    Empty,
+    /// A NaN or infinite position, which would defeat every light-cone check it takes part in.
+    #[error("sample at t = {0} ns has a non-finite position")]
+    NonFinite(u128),
+}
+
+/// The samples of a [`Trajectory::Sampled`]: never empty, finite, and sorted by `t`.
+///
+/// Deserialization goes through the same check as [`Trajectory::sampled`], so a malformed
+/// trajectory is rejected when it is loaded rather than at the first position query.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "Vec<SpacetimeCoord>", into = "Vec<SpacetimeCoord>")]
+pub struct Samples(pub(crate) Vec<SpacetimeCoord>);
+
+impl TryFrom<Vec<SpacetimeCoord>> for Samples {
+    type Error = InvalidSamples;
+
+    fn try_from(mut samples: Vec<SpacetimeCoord>) -> Result<Self, InvalidSamples> {
+        if samples.is_empty() {
+            return Err(InvalidSamples::Empty);
+        }
+        if let Some(bad) = samples.iter().find(|s| ![s.x, s.y, s.z].iter().all(|v| v.is_finite())) {
+            return Err(InvalidSamples::NonFinite(bad.t));
+        }
+        samples.sort_by_key(|s| s.t);
+        Ok(Self(samples))
+    }
+}
+
+impl From<Samples> for Vec<SpacetimeCoord> {
+    fn from(samples: Samples) -> Self {
+        samples.0
+    }
+}
+
+impl Deref for Samples {
+    type Target = [SpacetimeCoord];
+
+    fn deref(&self) -> &[SpacetimeCoord] {
+        &self.0
+    }
+}
+
+impl Trajectory {
+    /// Builds a piecewise-linear trajectory from samples, sorting them by time.
+    ///
+    /// Returns `None` if `samples` is empty or has a non-finite position.
+    pub fn sampled(samples: Vec<SpacetimeCoord>) -> Option<Self> {
+        Samples::try_from(samples).ok().map(Self::Sampled)
+    }
+
+    /// The node's position at coordinate time `t` (nanoseconds), stamped with `t`.
+    pub fn position_at(&self, t: u128) -> SpacetimeCoord {
+        match self {
+            Self::Static(coord) => SpacetimeCoord { t, ..*coord },
+            Self::Sampled(samples) => {
+                let idx = samples.partition_point(|s| s.t <= t);
+                let at = |s: &SpacetimeCoord| SpacetimeCoord { t, ..*s };
+                match (idx.checked_sub(1).map(|i| &samples[i]), samples.get(idx)) {
+                    (Some(a), Some(b)) => {
+                        let f = (t - a.t) as f64 / (b.t - a.t) as f64;
+                        SpacetimeCoord {
+                            t,
+                            x: a.x + (b.x - a.x) * f,
+                            y: a.y + (b.y - a.y) * f,
+                            z: a.z + (b.z - a.z) * f,
+                        }
+                    }
+                    (Some(last), None) => at(last),
+                    (None, Some(first)) => at(first),
+                    (None, None) => unreachable!("sampled trajectories are never empty"),
+                }
+            }
+        }
+    }
+}
+
+/// Euclidean distance between the spatial parts of two coordinates (meters).
+pub fn distance(a: &SpacetimeCoord, b: &SpacetimeCoord) -> f64 {
+    let dx = b.x - a.x;
+    let dy = b.y - a.y;
+    let dz = b.z - a.z;
+    (dx * dx + dy * dy + dz * dz).sqrt()
+}
+
+/// Solves the light-time equation for a signal emitted at `emission` towards `receiver`.
+///
+/// Returns the coordinate time (nanoseconds) at which the signal reaches the receiver.
+pub fn arrival_time(emission: &SpacetimeCoord, receiver: &Trajectory) -> u128 {
+    arrival_time_at_c(emission, receiver, speed_of_light())
+}
+
+/// Like [`arrival_time`], for a signal travelling at `c` (m/s) rather than the configured speed of
+/// light.
+pub fn arrival_time_at_c(emission: &SpacetimeCoord, receiver: &Trajectory, c: f64) -> u128 {
+    let mut arrival = emission.t;
+    for _ in 0..LIGHT_TIME_MAX_ITERATIONS {
+        let path = distance(emission, &receiver.position_at(arrival));
+        // Saturate rather than overflow for emissions at the far end of the time axis.
+        let next = emission.t.saturating_add((path / c * 1e9).round() as u128);
+        let converged = next.abs_diff(arrival) <= LIGHT_TIME_TOLERANCE_NS;
+        arrival = next;
+        if converged {
+            break;
+        }
+    }
+    arrival
+}
+
+/// One-way light time (nanoseconds) for a signal `sender` emits at `t_emit` towards `receiver`,
+/// using the sender's position at emission and the receiver's position at reception.
+pub fn light_time(sender: &Trajectory, receiver: &Trajectory, t_emit: u128) -> u128 {
+    arrival_time(&sender.position_at(t_emit), receiver).saturating_sub(t_emit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(t: u128, x: f64) -> SpacetimeCoord {
+        SpacetimeCoord {
+            t,
+            x,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    #[test]
+    fn receding_receiver_lengthens_light_time() {
+        let _c = crate::spacetime::SPEED_OF_LIGHT_TEST_LOCK.blocking_lock();
+        crate::spacetime::set_speed_of_light(100.0);
+        let c = speed_of_light();
+        let origin = Trajectory::Static(at(0, 0.0));
+        let fixed = Trajectory::Static(at(0, c));
+        // Starts one light-second away and recedes at c/2: 1 + 0.5 * t_r = t_r => t_r = 2 s.
+        let receding = Trajectory::sampled(vec![at(0, c), at(10_000_000_000, c + 5.0 * c)]).unwrap();
+
+        assert_eq!(light_time(&origin, &fixed, 0), 1_000_000_000);
+        let lt = light_time(&origin, &receding, 0);
+        assert!(lt.abs_diff(2_000_000_000) <= 2, "light time {lt}ns");
+    }
+
+    #[test]
+    fn malformed_samples_are_rejected_on_load() {
+        let empty = bincode::serialize(&Trajectory::Sampled(Samples(Vec::new()))).unwrap();
+        assert!(bincode::deserialize::<Trajectory>(&empty).is_err());
+
+        let unsorted = Trajectory::Sampled(Samples(vec![at(10, 1.0), at(0, 0.0)]));
+        let decoded: Trajectory = bincode::deserialize(&bincode::serialize(&unsorted).unwrap()).unwrap();
+        assert_eq!(decoded.position_at(5).x, 0.5, "samples are sorted on load");
+
+        for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(Trajectory::sampled(vec![at(0, 0.0), at(10, bad)]).is_none());
+            let forged = Trajectory::Sampled(Samples(vec![at(0, 0.0), at(10, bad)]));
+            assert!(bincode::deserialize::<Trajectory>(&bincode::serialize(&forged).unwrap()).is_err());
+        }
+        assert_eq!(arrival_time(&at(u128::MAX - 1, 0.0), &Trajectory::Static(at(0, 1e9))), u128::MAX);
+    }
+}
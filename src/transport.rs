@@ -0,0 +1,62 @@
+//! Pluggable transports.
+//!
+//! A [`Transport`] moves [`ProtocolMessage`]s between nodes, addressed by whatever identifies a
+//! peer on that transport (a local port for QUIC and BPv7, a UUID in the in-process cluster).
+//! Replica logic ([`Node`](crate::node::Node)) is generic over it, so the same code runs in fast
+//! simulation tests and over real sockets.
+//!
+//! A transport owns the wire (QUIC streams, BPv7 bundles over UDP, ...) and nothing else: inbound
+//! messages are handed to the node as [`Inbound`] values, and the node decides whether to take
+//! custody. The transport relays that decision back to the sender, whose [`Transport::send`]
+//! resolves only once custody has been accepted.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::protocol::ProtocolMessage;
+
+/// A message received from a peer, awaiting the node's custody decision.
+pub struct Inbound<P> {
+    /// The peer that sent it, so the node can reply (e.g. to a request).
+    pub from: P,
+    pub message: ProtocolMessage,
+    /// Send `true` once the message is stored, `false` to refuse it (the sender will retry).
+    pub custody: oneshot::Sender<bool>,
+}
+
+impl<P> Inbound<P> {
+    /// Wraps `message` from `from`, returning the receiver on which the node's custody decision
+    /// arrives.
+    pub fn new(from: P, message: ProtocolMessage) -> (Self, oneshot::Receiver<bool>) {
+        let (custody, decision) = oneshot::channel();
+        (Self { from, message, custody }, decision)
+    }
+}
+
+/// A wire protocol carrying messages between nodes.
+pub trait Transport: Send + Sync {
+    /// How peers are addressed.
+    type Peer: Copy + Eq + std::hash::Hash + std::fmt::Display + Send + Sync + 'static;
+
+    /// Delivers `msg` to `peer`, resolving once that node has taken custody.
+    fn send(&self, peer: Self::Peer, msg: ProtocolMessage) -> BoxFuture<'_, Result<()>>;
+
+    /// Accepts messages from peers until the transport fails, forwarding each to `inbound` and
+    /// relaying the node's custody decision back to its sender.
+    fn serve(&self, inbound: mpsc::Sender<Inbound<Self::Peer>>) -> BoxFuture<'_, Result<()>>;
+}
+
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    type Peer = T::Peer;
+
+    fn send(&self, peer: Self::Peer, msg: ProtocolMessage) -> BoxFuture<'_, Result<()>> {
+        (**self).send(peer, msg)
+    }
+
+    fn serve(&self, inbound: mpsc::Sender<Inbound<Self::Peer>>) -> BoxFuture<'_, Result<()>> {
+        (**self).serve(inbound)
+    }
+}